@@ -0,0 +1,42 @@
+//! System clipboard access for `portal send --clipboard` (and the
+//! interactive menu's `SendClipboard`) and for a `portal receive --clipboard`
+//! session placing incoming text there. Kept separate from
+//! [`portal_core::master::Master::send_text`]/[`portal_core::slave::Slave::on_text`],
+//! which only know about moving a string over the wire, not where it comes
+//! from or ends up on either machine.
+
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use arboard::Clipboard;
+
+/// What [`read`] found on the clipboard.
+pub enum ClipboardContent {
+    Text(String),
+    /// PNG-encoded image bytes; the wire protocol has no separate binary
+    /// clipboard message, so an image is sent like any other file instead.
+    ImagePng(Vec<u8>),
+}
+
+/// Reads whatever's currently on the clipboard, preferring text over an
+/// image if a platform somehow reports both.
+pub fn read() -> Result<ClipboardContent> {
+    let mut clipboard = Clipboard::new().context("failed to access the system clipboard")?;
+    if let Ok(text) = clipboard.get_text() {
+        return Ok(ClipboardContent::Text(text));
+    }
+    let image = clipboard.get_image().context("clipboard has neither text nor an image on it")?;
+    let buffer = image::RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned())
+        .context("clipboard image has an unexpected pixel layout")?;
+    let mut png = Vec::new();
+    buffer
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("failed to encode the clipboard image as PNG")?;
+    Ok(ClipboardContent::ImagePng(png))
+}
+
+/// Puts `text` on the clipboard, for a `portal receive --clipboard` session.
+pub fn write_text(text: &str) -> Result<()> {
+    let mut clipboard = Clipboard::new().context("failed to access the system clipboard")?;
+    clipboard.set_text(text).context("failed to write to the system clipboard")
+}
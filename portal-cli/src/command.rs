@@ -0,0 +1,843 @@
+//! The interactive menu shown when `portal` is run without a subcommand;
+//! see [`crate::script`] for the non-interactive `clap` subcommands used to
+//! script `portal` instead.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use inquire::CustomUserError;
+use portal_core::broadcast::{self, DiscoveredDevice, Listener, ScanHandle};
+use portal_core::history::History;
+use portal_core::identity::DeviceId;
+use portal_core::master::{Master, MasterBuilder};
+use portal_core::journal::JournalEntry;
+use portal_core::registry::{Registry, RegistryEntry};
+use portal_core::slave::{ActiveTransfer, IncomingFile, Slave, SlaveService};
+use portal_core::task_manager::{TaskId, TaskManager};
+use strum::{Display, EnumIter};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{AutoAccept, Config, CONFIG_PATH};
+use crate::glob_util::{expand_glob, print_batch_summary};
+use crate::json_output::{print_json, DeviceRecord};
+use crate::script::{
+    record_history, ANNOUNCE_INTERVAL, CONNECT_TIMEOUT, DEVICE_ID_PATH, HISTORY_PATH, JOURNAL_PATH, REGISTRY_PATH,
+};
+
+/// Pre-fills the extensions prompt in `edit_config` with whatever
+/// [`AutoAccept::MatchingExtension`] list is already configured, or an
+/// empty string for `Never`/`Always`.
+fn auto_accept_extensions_default(auto_accept: &AutoAccept) -> String {
+    match auto_accept {
+        AutoAccept::MatchingExtension(extensions) => extensions.join(","),
+        AutoAccept::Never | AutoAccept::Always => String::new(),
+    }
+}
+
+/// Autocompletes `input` against the filesystem, for the `SendFile` path
+/// prompt: whatever's already typed is split into a directory and a
+/// partial file name, and every entry in that directory whose name starts
+/// with the partial name is offered as a suggestion, with directories
+/// suffixed with `/` so the user can keep tabbing deeper.
+fn suggest_paths(input: &str) -> Result<Vec<String>, CustomUserError> {
+    let (dir, partial) = match input.rfind('/') {
+        Some(index) => (&input[..=index], &input[index + 1..]),
+        None => ("", input),
+    };
+    let dir_path = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+
+    let Ok(entries) = std::fs::read_dir(dir_path) else {
+        return Ok(Vec::new());
+    };
+    let mut suggestions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(partial) {
+                return None;
+            }
+            let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+            Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    suggestions.sort();
+    Ok(suggestions)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter)]
+pub enum Commands {
+    SendFile,
+    SendClipboard,
+    SetTarget,
+    ListDevices,
+    StartReceiving,
+    StopReceiving,
+    PauseTask,
+    ResumeTask,
+    AbortTask,
+    ListTask,
+    ListIncomplete,
+    History,
+    EditConfig,
+    Quit,
+}
+
+/// A file currently being received by [`Manager::receiving`], tracked the
+/// same way [`crate::script::receive`] tracks its progress bars, so
+/// `ListTask` can report on it without its own [`TaskManager`] knowing
+/// anything about inbound transfers.
+#[derive(Debug, Clone)]
+struct IncomingProgress {
+    file_name: String,
+    bytes_received: u64,
+    file_size: u64,
+}
+
+/// A background `StartReceiving` session: announcing over broadcast and
+/// serving incoming connections, same as `portal receive`, but running
+/// alongside the rest of the menu instead of blocking it, so this device
+/// can be discoverable and receiving files at the same time it's sending
+/// them through `SendFile`.
+struct ReceiveSession {
+    device_id: DeviceId,
+    port: u16,
+    cancellation: CancellationToken,
+    announce_task: JoinHandle<()>,
+    service_task: JoinHandle<()>,
+    incoming: Arc<Mutex<HashMap<u32, IncomingProgress>>>,
+}
+
+impl ReceiveSession {
+    /// Cancels both background tasks and waits for them to actually stop,
+    /// so a second `StartReceiving` right after doesn't race the old
+    /// listener for the same port.
+    async fn stop(self) {
+        self.cancellation.cancel();
+        let _ = self.announce_task.await;
+        let _ = self.service_task.await;
+    }
+}
+
+/// Holds the CLI's session state between menu selections: the current
+/// connection to a target device, known devices, in-flight tasks, and the
+/// settings loaded from [`CONFIG_PATH`].
+pub struct Manager {
+    pub master: Option<Master>,
+    pub config: Config,
+    scan: Option<ScanHandle>,
+    /// Set alongside `master` by `SetTarget`, so `SendFile`, `ResumeTask`
+    /// and friends can open further connections to the same device without
+    /// needing `master`'s own (already handshaked and in use) connection.
+    target_addr: Option<SocketAddr>,
+    tasks: TaskManager,
+    /// Saved device aliases and last-known addresses; see [`REGISTRY_PATH`].
+    registry: Registry,
+    /// Set by `StartReceiving`, cleared by `StopReceiving`; lets this menu
+    /// session receive files at the same time it sends them, instead of
+    /// the two roles being mutually exclusive.
+    receiving: Option<ReceiveSession>,
+    /// Set via [`Self::with_json`]; switches `ListDevices`, `ListTask`, and
+    /// `SendFile`'s output to JSON lines instead of human-readable text.
+    json: bool,
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Manager {
+    /// Loads [`Config`] from [`CONFIG_PATH`], falling back to defaults if
+    /// it can't be read (e.g. first run, or a corrupt file) rather than
+    /// failing the whole menu over a settings problem.
+    pub fn new() -> Self {
+        let config = Config::load_or_default(CONFIG_PATH).unwrap_or_else(|err| {
+            eprintln!("warning: {err:#}; using default settings");
+            Config::default()
+        });
+        let registry = Registry::open(REGISTRY_PATH).unwrap_or_else(|err| {
+            eprintln!("warning: {err:#}; starting with an empty device registry");
+            Registry::new(REGISTRY_PATH)
+        });
+        let tasks = TaskManager::with_journal(JOURNAL_PATH).unwrap_or_else(|err| {
+            eprintln!("warning: {err:#}; transfers won't survive a restart this session");
+            TaskManager::new()
+        });
+        Self {
+            master: None,
+            config,
+            scan: None,
+            target_addr: None,
+            tasks,
+            registry,
+            receiving: None,
+            json: false,
+        }
+    }
+
+    /// Switches every command's output to JSON lines instead of the
+    /// human-readable format, so the menu can be driven by another program
+    /// rather than typed at interactively. Set once at startup, from the
+    /// top-level `--json` flag.
+    pub fn with_json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Starts a background scan via [`Listener::spawn_scan`] the first time
+    /// `ListDevices` or `SetTarget` needs one, and reuses it after that, so
+    /// devices discovered by an earlier menu turn are still known later
+    /// instead of each command starting from a blank scan.
+    async fn ensure_scan(&mut self) -> Result<&ScanHandle> {
+        if self.scan.is_none() {
+            let listener = Listener::bind(&format!("0.0.0.0:{}", self.config.broadcast_port))
+                .await
+                .context("failed to start scanning for devices")?;
+            self.scan = Some(listener.spawn_scan(CancellationToken::new()));
+        }
+        Ok(self.scan.as_ref().expect("just set above"))
+    }
+
+    pub async fn dispatch(&mut self, command: Commands) -> Result<()> {
+        match command {
+            Commands::SendFile => self.send_file().await,
+            Commands::SendClipboard => self.send_clipboard().await,
+            Commands::SetTarget => self.set_target().await,
+            Commands::ListDevices => self.list_devices().await,
+            Commands::StartReceiving => self.start_receiving().await,
+            Commands::StopReceiving => self.stop_receiving().await,
+            Commands::PauseTask => self.pause_task().await,
+            Commands::ResumeTask => self.resume_task().await,
+            Commands::AbortTask => self.abort_task().await,
+            Commands::ListTask => self.list_task().await,
+            Commands::ListIncomplete => self.list_incomplete().await,
+            Commands::History => self.show_history(),
+            Commands::EditConfig => self.edit_config().await,
+            Commands::Quit => Ok(()),
+        }
+    }
+
+    /// Prompts for a path or glob pattern (e.g. `*.txt`); a single match is
+    /// submitted to the [`TaskManager`] so its progress can be tracked and
+    /// it can be paused, resumed, or aborted by id, while several matches go
+    /// through a `MultiSelect` and are sent inline over `self.master`, same
+    /// as before task tracking existed, since a whole batch being one task
+    /// wouldn't let any single file in it be paused independently.
+    async fn send_file(&mut self) -> Result<()> {
+        if self.master.is_none() {
+            println!("No target set; use SetTarget first.");
+            return Ok(());
+        }
+        let pattern = inquire::Text::new("Path or glob pattern to send (e.g. \"*.txt\")")
+            .with_autocomplete(suggest_paths)
+            .prompt()?;
+        let matches = expand_glob(&pattern)?;
+        if matches.is_empty() {
+            println!("no files matched {pattern:?}");
+            return Ok(());
+        }
+
+        let paths = if matches.len() == 1 {
+            matches
+        } else {
+            let options: Vec<String> = matches.iter().map(|path| path.display().to_string()).collect();
+            inquire::MultiSelect::new("Select files to send", options)
+                .prompt()?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect()
+        };
+        if paths.is_empty() {
+            println!("no files selected");
+            return Ok(());
+        }
+
+        if let [path] = paths.as_slice() {
+            self.submit_send(path.clone()).await?;
+        } else {
+            let addr = self.target_addr.context("No target set; use SetTarget first.")?;
+            let master = self.master.as_mut().expect("checked above");
+            let started = std::time::Instant::now();
+            let batch = master.send_files(paths).await;
+            let duration = started.elapsed();
+            for file in &batch.files {
+                record_history(addr, &file.path, duration, &file.response);
+            }
+            print_batch_summary(&batch, self.json);
+        }
+        Ok(())
+    }
+
+    /// Sends whatever's on the system clipboard to the current target: text
+    /// goes straight over `self.master` via `send_text`, while an image is
+    /// written to a temporary PNG file and handed to [`Self::submit_send`]
+    /// like any other file, since the wire protocol has no separate binary
+    /// clipboard message.
+    async fn send_clipboard(&mut self) -> Result<()> {
+        if self.master.is_none() {
+            println!("No target set; use SetTarget first.");
+            return Ok(());
+        }
+        match crate::clipboard::read().context("failed to read the clipboard")? {
+            crate::clipboard::ClipboardContent::Text(content) => {
+                let master = self.master.as_mut().expect("checked above");
+                let response = master.send_text(content).await?;
+                println!("{response:?}");
+                Ok(())
+            }
+            crate::clipboard::ClipboardContent::ImagePng(png) => {
+                let path = std::env::temp_dir().join(format!("portal-clipboard-{}.png", std::process::id()));
+                std::fs::write(&path, png).context("failed to write the clipboard image to a temporary file")?;
+                let result = self.submit_send(path.clone()).await;
+                let _ = std::fs::remove_file(&path);
+                result
+            }
+        }
+    }
+
+    /// Dials a fresh connection to the current target and hands it to
+    /// [`TaskManager::submit_file`], rather than sending over `self.master`
+    /// directly, so the transfer gets a [`TaskId`] that `ListTask`,
+    /// `PauseTask`, `ResumeTask` and `AbortTask` can act on.
+    async fn submit_send(&mut self, path: PathBuf) -> Result<()> {
+        let addr = self.target_addr.context("No target set; use SetTarget first.")?;
+        let stream = TcpStream::connect(addr).await.with_context(|| format!("failed to connect to {addr}"))?;
+        let id = self.tasks.submit_file(self.master_builder(), stream, path);
+        if self.json {
+            print_json(&serde_json::json!({"task_id": id.as_u64(), "status": "started"}));
+        } else {
+            println!("started task {id}; use ListTask to check on it");
+        }
+        Ok(())
+    }
+
+    /// Builds a [`MasterBuilder`] pre-configured with [`Config::send_rate_limit`],
+    /// so every fresh connection — `SetTarget`, a task-tracked `SendFile`,
+    /// or a `ResumeTask` — respects the same outgoing bandwidth cap.
+    fn master_builder(&self) -> MasterBuilder {
+        match self.config.send_rate_limit {
+            Some(limit) => MasterBuilder::new().rate_limit(limit),
+            None => MasterBuilder::new(),
+        }
+    }
+
+    /// Lets the user pick a saved alias or a freshly scanned device and
+    /// dials it, replacing `self.master` on success so `SendFile` has
+    /// something to send through; on failure `self.master` is left as it
+    /// was rather than cleared, so a bad dial doesn't also drop an
+    /// already-working target. Successfully connecting also offers to save
+    /// or update the device's alias, so it can be picked without rescanning
+    /// next time.
+    async fn set_target(&mut self) -> Result<()> {
+        let saved: Vec<RegistryEntry> = self.registry.entries().filter(|entry| entry.alias.is_some()).cloned().collect();
+        let (device_id, addr, label) = if saved.is_empty() {
+            let Some(picked) = self.pick_scanned_device().await? else {
+                return Ok(());
+            };
+            picked
+        } else {
+            let mut options: Vec<String> =
+                saved.iter().map(|entry| format!("{} (saved)", entry.alias.as_deref().unwrap_or_default())).collect();
+            options.push("Scan for devices...".to_string());
+            let choice = inquire::Select::new("Connect to a saved device, or scan for new ones?", options.clone())
+                .prompt()?;
+            let index = options.iter().position(|option| option == &choice).expect("choice came from options");
+            match saved.get(index) {
+                Some(entry) => (entry.device_id, entry.last_addr, entry.alias.clone().unwrap_or_default()),
+                None => {
+                    let Some(picked) = self.pick_scanned_device().await? else {
+                        return Ok(());
+                    };
+                    picked
+                }
+            }
+        };
+
+        match self.master_builder().connect(addr, CONNECT_TIMEOUT).await {
+            Ok(master) => {
+                self.master = Some(master);
+                self.target_addr = Some(addr);
+                if let Err(err) = self.registry.note_seen(device_id, addr) {
+                    eprintln!("warning: failed to update the device registry: {err:#}");
+                }
+                println!("connected to {label} ({addr})");
+
+                let current_alias =
+                    self.registry.get(device_id).and_then(|entry| entry.alias.clone()).unwrap_or_default();
+                let alias = inquire::Text::new("Alias for this device (blank to keep current)")
+                    .with_default(&current_alias)
+                    .prompt()?;
+                if !alias.is_empty() && alias != current_alias {
+                    self.registry.set_alias(device_id, Some(alias))?;
+                }
+            }
+            Err(err) => println!("failed to connect to {label} ({addr}): {err:#}"),
+        }
+        Ok(())
+    }
+
+    /// Ensures a background scan is running, then lets the user pick one of
+    /// its discovered devices, returning its id, dial address (its
+    /// [`DiscoveredDevice::service_port`], not the ephemeral broadcast
+    /// source port), and hostname — or `None` if nothing's been found yet.
+    async fn pick_scanned_device(&mut self) -> Result<Option<(DeviceId, SocketAddr, String)>> {
+        let scan = self.ensure_scan().await?;
+        let mut devices: Vec<(DeviceId, DiscoveredDevice)> = scan.devices().into_iter().collect();
+        if devices.is_empty() {
+            println!("no devices found yet; try again in a few seconds");
+            return Ok(None);
+        }
+        devices.sort_by(|(_, a), (_, b)| a.hostname.cmp(&b.hostname));
+
+        let options: Vec<String> = devices
+            .iter()
+            .map(|(device_id, device)| format!("{} ({device_id}, {})", device.hostname, device.addr.ip()))
+            .collect();
+        let choice = inquire::Select::new("Select a device to connect to", options.clone()).prompt()?;
+        let index = options.iter().position(|option| option == &choice).expect("choice came from options");
+        let (device_id, device) = &devices[index];
+        let addr = SocketAddr::new(device.addr.ip(), device.service_port);
+        Ok(Some((*device_id, addr, device.hostname.clone())))
+    }
+
+    async fn list_devices(&mut self) -> Result<()> {
+        let scan = self.ensure_scan().await?;
+        let devices = scan.devices();
+        if devices.is_empty() {
+            println!("no devices found yet; try again in a few seconds");
+            return Ok(());
+        }
+        for (device_id, device) in &devices {
+            let alias = self.registry.get(*device_id).and_then(|entry| entry.alias.clone());
+            if self.json {
+                print_json(&DeviceRecord::new(*device_id, device, alias));
+            } else {
+                println!(
+                    "{device_id}  {:<21} {:<8} {:<8} {:<12} {}",
+                    device.addr.to_string(),
+                    device.platform,
+                    device.version,
+                    alias.as_deref().unwrap_or("-"),
+                    device.hostname,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts announcing this device over broadcast and accepting incoming
+    /// files in the background, using the same [`Config::service_port`],
+    /// `received_dir` and `auto_accept` settings `portal receive` would, so
+    /// `SendFile` and receiving can run side by side in one session.
+    /// Incoming files are accepted per `auto_accept` without prompting,
+    /// since an interactive `Confirm` from a background task would fight
+    /// the menu for the terminal; the one `Confirm` asked here, for whether
+    /// to show a pairing QR code, runs before the background task starts so
+    /// it doesn't have that problem.
+    async fn start_receiving(&mut self) -> Result<()> {
+        if self.receiving.is_some() {
+            println!("already receiving; use StopReceiving first");
+            return Ok(());
+        }
+        let device_id = DeviceId::load_or_create(DEVICE_ID_PATH)
+            .context("failed to load or create this device's persistent id")?;
+        let port = self.config.service_port;
+        let cancellation = CancellationToken::new();
+
+        let mut sender = broadcast::Sender::new_on_port(port, self.config.broadcast_port, device_id)
+            .context("failed to set up discovery broadcasts")?;
+        if let Some(device_name) = &self.config.device_name {
+            sender = sender.with_hostname(device_name.clone());
+        }
+        let announce_task = {
+            let cancellation = cancellation.clone();
+            tokio::spawn(async move {
+                sender.async_send_loop(ANNOUNCE_INTERVAL, &AtomicUsize::new(0), cancellation).await;
+            })
+        };
+
+        let mut service = SlaveService::bind(("0.0.0.0", port)).await.with_context(|| format!("failed to bind port {port}"))?;
+        service.set_cancellation_token(cancellation.clone());
+        if let Some(limit) = self.config.max_inbound_bytes_per_sec {
+            service.set_max_inbound_bytes_per_sec(limit);
+        }
+
+        let show_qr = inquire::Confirm::new("Show a QR code for pairing without discovery?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        let place_on_clipboard = inquire::Confirm::new("Place incoming text snippets on this device's clipboard?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        let pairing_code = show_qr.then(|| DeviceId::generate().to_string().replace('-', "")[..6].to_uppercase());
+        if let Some(pairing_code) = &pairing_code {
+            let ip = broadcast::local_ipv4_addresses().into_iter().next().unwrap_or(std::net::Ipv4Addr::UNSPECIFIED);
+            crate::qr::print_connect_qr(ip, port, pairing_code);
+        }
+
+        let incoming: Arc<Mutex<HashMap<u32, IncomingProgress>>> = Arc::new(Mutex::new(HashMap::new()));
+        let output = self.config.received_dir.clone();
+        let auto_accept = self.config.auto_accept.clone();
+        let progress_map = incoming.clone();
+        service.configure(Arc::new(move |slave: &mut Slave| {
+            if let Some(pairing_code) = &pairing_code {
+                slave.set_pairing_key(pairing_code.clone());
+            }
+            slave.set_output_dir(output.clone());
+            slave.on_text(Arc::new(move |content: String| {
+                println!("received text: {content}");
+                if place_on_clipboard {
+                    if let Err(err) = crate::clipboard::write_text(&content) {
+                        tracing::warn!(%err, "failed to place received text on the clipboard");
+                    }
+                }
+            }));
+            let auto_accept = auto_accept.clone();
+            slave.on_incoming_file(Arc::new(move |incoming: IncomingFile| {
+                let accept = auto_accept.accepts(&incoming.file_name);
+                Box::pin(async move { accept })
+            }));
+            let progress_map = progress_map.clone();
+            slave.on_progress(Arc::new(move |transfer: ActiveTransfer| {
+                let mut map = progress_map.lock().unwrap_or_else(|err| err.into_inner());
+                if transfer.bytes_received >= transfer.file_size {
+                    map.remove(&transfer.file_id);
+                    crate::notify::notify_transfer(&transfer.file_name, true, "received");
+                } else {
+                    map.insert(
+                        transfer.file_id,
+                        IncomingProgress {
+                            file_name: transfer.file_name.clone(),
+                            bytes_received: transfer.bytes_received,
+                            file_size: transfer.file_size,
+                        },
+                    );
+                }
+            }));
+        }));
+
+        let service_task = tokio::spawn(async move {
+            service.run().await;
+        });
+
+        if !self.json {
+            println!("receiving on port {port}, discoverable as {device_id}");
+        }
+        self.receiving = Some(ReceiveSession { device_id, port, cancellation, announce_task, service_task, incoming });
+        Ok(())
+    }
+
+    /// Stops a session started by `StartReceiving`, if any.
+    async fn stop_receiving(&mut self) -> Result<()> {
+        let Some(session) = self.receiving.take() else {
+            println!("not currently receiving");
+            return Ok(());
+        };
+        let (device_id, port) = (session.device_id, session.port);
+        session.stop().await;
+        if !self.json {
+            println!("stopped receiving as {device_id} on port {port}");
+        }
+        Ok(())
+    }
+
+    /// Prompts for one of [`TaskManager::list`]'s ids via `inquire::Select`,
+    /// or `None` if there's nothing to choose from.
+    fn select_task(&self, message: &str) -> Result<Option<TaskId>> {
+        let ids = self.tasks.list();
+        if ids.is_empty() {
+            println!("no tasks");
+            return Ok(None);
+        }
+        let options: Vec<String> = ids.iter().map(|id| format!("task {id}")).collect();
+        let choice = inquire::Select::new(message, options.clone()).prompt()?;
+        let index = options.iter().position(|option| option == &choice).expect("choice came from options");
+        Ok(Some(ids[index]))
+    }
+
+    async fn pause_task(&mut self) -> Result<()> {
+        let Some(id) = self.select_task("Select a task to pause")? else {
+            return Ok(());
+        };
+        self.tasks.pause(id).await;
+        if self.json {
+            print_json(&serde_json::json!({"task_id": id.as_u64(), "status": "paused"}));
+        } else {
+            println!("paused task {id}");
+        }
+        Ok(())
+    }
+
+    /// Reconnects to the current target and hands the fresh stream to
+    /// [`TaskManager::resume`], which continues the transfer via
+    /// [`Master::resume_a_file`].
+    async fn resume_task(&mut self) -> Result<()> {
+        let Some(id) = self.select_task("Select a task to resume")? else {
+            return Ok(());
+        };
+        let addr = self.target_addr.context("No target set; use SetTarget first.")?;
+        let stream = TcpStream::connect(addr).await.with_context(|| format!("failed to connect to {addr}"))?;
+        let resumed = self.tasks.resume(id, self.master_builder(), stream);
+        if self.json {
+            print_json(&serde_json::json!({
+                "task_id": id.as_u64(),
+                "status": if resumed { "resumed" } else { "unknown" },
+            }));
+        } else if resumed {
+            println!("resumed task {id}");
+        } else {
+            println!("task {id} is no longer known");
+        }
+        Ok(())
+    }
+
+    async fn abort_task(&mut self) -> Result<()> {
+        let Some(id) = self.select_task("Select a task to abort")? else {
+            return Ok(());
+        };
+        let aborted = self.tasks.abort(id);
+        if self.json {
+            print_json(&serde_json::json!({
+                "task_id": id.as_u64(),
+                "status": if aborted { "aborted" } else { "unknown" },
+            }));
+        } else if aborted {
+            println!("aborted task {id}");
+        } else {
+            println!("task {id} is no longer known");
+        }
+        Ok(())
+    }
+
+    /// Prints every known task's id, status, and progress.
+    async fn list_task(&mut self) -> Result<()> {
+        let ids = self.tasks.list();
+        let incoming = match &self.receiving {
+            Some(session) => session.incoming.lock().unwrap_or_else(|err| err.into_inner()).clone(),
+            None => HashMap::new(),
+        };
+        if ids.is_empty() && incoming.is_empty() {
+            println!("no tasks");
+            return Ok(());
+        }
+        for id in ids {
+            let status = self.tasks.status(id).await;
+            let progress = self.tasks.progress(id);
+            let Some(status) = status else { continue };
+            if self.json {
+                print_json(&serde_json::json!({
+                    "direction": "outgoing",
+                    "task_id": id.as_u64(),
+                    "status": format!("{status:?}"),
+                    "bytes_sent": progress.as_ref().map(|progress| progress.bytes_sent),
+                    "total_bytes": progress.as_ref().map(|progress| progress.total),
+                }));
+            } else if let Some(progress) = progress {
+                println!("task {id} (out): {status:?} ({}/{} bytes)", progress.bytes_sent, progress.total);
+            } else {
+                println!("task {id} (out): {status:?}");
+            }
+        }
+        for (file_id, progress) in &incoming {
+            if self.json {
+                print_json(&serde_json::json!({
+                    "direction": "incoming",
+                    "file_id": file_id,
+                    "file_name": progress.file_name,
+                    "bytes_received": progress.bytes_received,
+                    "file_size": progress.file_size,
+                }));
+            } else {
+                println!(
+                    "file {file_id} (in): {} ({}/{} bytes)",
+                    progress.file_name, progress.bytes_received, progress.file_size
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists transfers left over in the journal from a previous run (one
+    /// that exited or crashed mid-transfer), and lets the user resume one
+    /// over the current target or discard it from the journal for good.
+    /// Unlike `ResumeTask`, these entries aren't already registered with
+    /// `self.tasks`, so they're offered from [`TaskManager::interrupted`]
+    /// rather than [`Self::select_task`].
+    async fn list_incomplete(&mut self) -> Result<()> {
+        let interrupted = self.tasks.interrupted();
+        if interrupted.is_empty() {
+            println!("no interrupted transfers");
+            return Ok(());
+        }
+        if self.json {
+            for entry in &interrupted {
+                print_json(&serde_json::json!({
+                    "task_id": entry.task_id,
+                    "path": entry.path,
+                    "bytes_confirmed": entry.bytes_confirmed,
+                }));
+            }
+            return Ok(());
+        }
+
+        let options: Vec<String> = interrupted
+            .iter()
+            .map(|entry| format!("task {} ({}, {} bytes confirmed)", entry.task_id, entry.path.display(), entry.bytes_confirmed))
+            .collect();
+        let choice = inquire::Select::new("Select an interrupted transfer", options.clone()).prompt()?;
+        let index = options.iter().position(|option| option == &choice).expect("choice came from options");
+        let entry: JournalEntry = interrupted[index].clone();
+
+        let resume = inquire::Confirm::new("Resume this transfer? (no discards it instead)").with_default(true).prompt()?;
+        if !resume {
+            self.tasks.discard_interrupted(entry.task_id);
+            println!("discarded task {}", entry.task_id);
+            return Ok(());
+        }
+
+        let addr = self.target_addr.context("No target set; use SetTarget first.")?;
+        let stream = TcpStream::connect(addr).await.with_context(|| format!("failed to connect to {addr}"))?;
+        let id = self.tasks.resume_interrupted(entry, self.master_builder(), stream);
+        println!("resumed task {id}");
+        Ok(())
+    }
+
+    /// Prints every completed or failed transfer logged at [`HISTORY_PATH`],
+    /// oldest first.
+    fn show_history(&self) -> Result<()> {
+        let history = History::open(HISTORY_PATH).context("failed to open the transfer history")?;
+        if history.entries().is_empty() {
+            if !self.json {
+                println!("no transfers recorded yet");
+            }
+            return Ok(());
+        }
+        for entry in history.entries() {
+            if self.json {
+                print_json(entry);
+            } else {
+                let status = if entry.succeeded { "ok" } else { "failed" };
+                println!(
+                    "{:<21} {:>10} bytes  {:>6} ms  {status:<6} {}: {}",
+                    entry.peer.to_string(),
+                    entry.size,
+                    entry.duration_ms,
+                    entry.file.display(),
+                    entry.result,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the user through every [`Config`] field, applies the answers
+    /// to `self.config`, and saves the result to [`CONFIG_PATH`]. A target
+    /// already set via `SetTarget` is left alone; only settings that affect
+    /// future commands (e.g. the port a future `SetTarget` connects to)
+    /// change.
+    async fn edit_config(&mut self) -> Result<()> {
+        let service_port = inquire::CustomType::<u16>::new("Default service port")
+            .with_default(self.config.service_port)
+            .prompt()?;
+
+        let broadcast_port = inquire::CustomType::<u16>::new("Discovery broadcast port")
+            .with_default(self.config.broadcast_port)
+            .prompt()?;
+
+        let device_name = inquire::Text::new("Device name (blank to use the OS hostname)")
+            .with_default(self.config.device_name.clone().unwrap_or_default().as_str())
+            .prompt()?;
+
+        let received_dir = inquire::Text::new("Received files folder")
+            .with_default(self.config.received_dir.to_string_lossy().as_ref())
+            .prompt()?;
+
+        let auto_accept = if inquire::Confirm::new("Auto-accept every incoming file?")
+            .with_default(self.config.auto_accept == AutoAccept::Always)
+            .prompt()?
+        {
+            AutoAccept::Always
+        } else {
+            let extensions = inquire::Text::new(
+                "Auto-accept files with these extensions (comma-separated, blank for none, e.g. \"txt,pdf\")",
+            )
+            .with_default(&auto_accept_extensions_default(&self.config.auto_accept))
+            .prompt()?;
+            let extensions: Vec<String> =
+                extensions.split(',').map(str::trim).filter(|ext| !ext.is_empty()).map(str::to_string).collect();
+            if extensions.is_empty() {
+                AutoAccept::Never
+            } else {
+                AutoAccept::MatchingExtension(extensions)
+            }
+        };
+
+        let bandwidth_limit = inquire::Text::new("Inbound bandwidth limit in bytes/sec (blank for no limit)")
+            .with_default(&self.config.max_inbound_bytes_per_sec.map(|limit| limit.to_string()).unwrap_or_default())
+            .prompt()?;
+        let max_inbound_bytes_per_sec = if bandwidth_limit.trim().is_empty() {
+            None
+        } else {
+            Some(bandwidth_limit.trim().parse().context("bandwidth limit must be a whole number of bytes/sec")?)
+        };
+
+        let send_rate_limit_text = inquire::Text::new(
+            "Default outgoing bandwidth limit, e.g. \"5MB/s\" (blank for no limit)",
+        )
+        .with_default(&self.config.send_rate_limit.map(|limit| limit.to_string()).unwrap_or_default())
+        .prompt()?;
+        let send_rate_limit = if send_rate_limit_text.trim().is_empty() {
+            None
+        } else {
+            Some(crate::script::parse_bandwidth(send_rate_limit_text.trim()).map_err(anyhow::Error::msg)?)
+        };
+
+        self.config = Config {
+            service_port,
+            broadcast_port,
+            device_name: if device_name.trim().is_empty() { None } else { Some(device_name.trim().to_string()) },
+            received_dir: PathBuf::from(received_dir),
+            auto_accept,
+            max_inbound_bytes_per_sec,
+            send_rate_limit,
+        };
+        self.config.save(CONFIG_PATH)?;
+        println!("saved settings to {CONFIG_PATH}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggest_paths_lists_entries_whose_name_starts_with_the_partial_input() {
+        let dir = std::env::temp_dir().join(format!("portal-cli-suggest-paths-{:?}", std::thread::current().id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("report.txt"), b"").unwrap();
+        std::fs::write(dir.join("readme.md"), b"").unwrap();
+
+        let prefix = format!("{}/re", dir.to_string_lossy());
+        let mut suggestions = suggest_paths(&prefix).unwrap();
+        suggestions.sort();
+
+        assert_eq!(
+            suggestions,
+            vec![format!("{}/readme.md", dir.to_string_lossy()), format!("{}/report.txt", dir.to_string_lossy())]
+        );
+
+        let sub_suggestions = suggest_paths(&format!("{}/s", dir.to_string_lossy())).unwrap();
+        assert_eq!(sub_suggestions, vec![format!("{}/sub/", dir.to_string_lossy())]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
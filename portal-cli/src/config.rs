@@ -0,0 +1,150 @@
+//! On-disk CLI settings (ports, device name, received-files folder,
+//! auto-accept rules, bandwidth limits), so they survive a restart instead
+//! of being re-entered every session; see [`crate::command::Manager`]'s
+//! `EditConfig` entry, which is the only place that edits these today.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use portal_core::broadcast::DISCOVERY_PORT;
+use serde::{Deserialize, Serialize};
+
+use crate::script::DEFAULT_SERVICE_PORT;
+
+/// Where [`Manager::new`](crate::command::Manager::new) loads its
+/// [`Config`] from and `EditConfig` saves it back to.
+pub const CONFIG_PATH: &str = "portal.toml";
+
+/// Whether an incoming file is accepted without prompting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoAccept {
+    /// Always prompt; the safe default.
+    Never,
+    /// Accept everything without prompting, for unattended use.
+    Always,
+    /// Accept without prompting only when the file name ends in one of
+    /// these extensions (case-insensitive, no leading dot), e.g. `"txt"`;
+    /// anything else is still prompted for.
+    MatchingExtension(Vec<String>),
+}
+
+impl AutoAccept {
+    /// Whether a file named `file_name` should be accepted without asking.
+    pub fn accepts(&self, file_name: &str) -> bool {
+        match self {
+            AutoAccept::Never => false,
+            AutoAccept::Always => true,
+            AutoAccept::MatchingExtension(extensions) => {
+                let Some(extension) = Path::new(file_name).extension().and_then(|ext| ext.to_str()) else {
+                    return false;
+                };
+                extensions.iter().any(|candidate| candidate.eq_ignore_ascii_case(extension))
+            }
+        }
+    }
+}
+
+/// Persisted CLI settings; see the module docs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Port `receive` listens on and `send` connects to by default.
+    pub service_port: u16,
+    /// Port discovery broadcasts are sent and listened for on; separate from
+    /// [`Self::service_port`] so a fixed pair of ports can be punched
+    /// through a firewall instead of one changing between runs.
+    pub broadcast_port: u16,
+    /// Announced as this device's hostname over discovery, overriding
+    /// whatever the OS reports; `None` uses the OS hostname.
+    pub device_name: Option<String>,
+    /// Directory incoming files are saved to.
+    pub received_dir: PathBuf,
+    /// Whether and when an incoming file is accepted without prompting.
+    pub auto_accept: AutoAccept,
+    /// Caps inbound bytes per second across all transfers; `None` means no
+    /// limit.
+    pub max_inbound_bytes_per_sec: Option<u64>,
+    /// Caps outgoing bytes per second for `send`, unless overridden by
+    /// `--limit`; `None` means no limit. See
+    /// [`crate::script::parse_bandwidth`] for the accepted `--limit` syntax
+    /// this is stored as the parsed result of.
+    pub send_rate_limit: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            service_port: DEFAULT_SERVICE_PORT,
+            broadcast_port: DISCOVERY_PORT,
+            device_name: None,
+            received_dir: PathBuf::from("."),
+            auto_accept: AutoAccept::Never,
+            max_inbound_bytes_per_sec: None,
+            send_rate_limit: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads `path`, or falls back to [`Config::default`] if it doesn't
+    /// exist yet (no config saved before).
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes this config to `path`, overwriting whatever was there.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = toml::to_string_pretty(self).context("failed to serialize config")?;
+        std::fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_toml() {
+        let config = Config {
+            service_port: 9999,
+            broadcast_port: 3001,
+            device_name: Some("Laptop".to_string()),
+            received_dir: PathBuf::from("/tmp/received"),
+            auto_accept: AutoAccept::MatchingExtension(vec!["txt".to_string(), "pdf".to_string()]),
+            max_inbound_bytes_per_sec: Some(1_000_000),
+            send_rate_limit: Some(5_000_000),
+        };
+        let dir = std::env::temp_dir().join(format!("portal-cli-config-test-{:?}", std::thread::current().id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("portal.toml");
+
+        config.save(&path).unwrap();
+        let loaded = Config::load_or_default(&path).unwrap();
+
+        assert_eq!(loaded, config);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn falls_back_to_defaults_when_the_file_is_missing() {
+        let path = std::env::temp_dir().join("portal-cli-config-test-does-not-exist.toml");
+        assert_eq!(Config::load_or_default(&path).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn matching_extension_is_case_insensitive_and_ignores_other_files() {
+        let rule = AutoAccept::MatchingExtension(vec!["txt".to_string()]);
+        assert!(rule.accepts("notes.TXT"));
+        assert!(!rule.accepts("photo.png"));
+        assert!(!rule.accepts("no-extension"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Glob pattern expansion and batch-result summaries shared by
+//! [`crate::command`]'s interactive `SendFile` flow and [`crate::script`]'s
+//! non-interactive `send` subcommand, so entering `*.txt` behaves the same
+//! either way.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use portal_core::codec::SlaveResponse;
+use portal_core::master::BatchHandle;
+
+use crate::json_output::{print_json, FileResult};
+
+/// Expands `pattern` to the files it matches. A pattern containing `*`,
+/// `?`, or `[` is expanded as a glob against the filesystem, keeping only
+/// regular files, sorted for a stable, predictable order; anything else is
+/// treated as a literal path and returned as-is, so a single plain path
+/// behaves exactly like it always has (including surfacing "no such file"
+/// from the send itself, rather than being silently dropped here).
+pub fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let mut matches: Vec<PathBuf> = glob::glob(pattern)
+        .with_context(|| format!("invalid glob pattern {pattern:?}"))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+/// Prints one line per file in `batch` plus an aggregate count, so a
+/// multi-file send's outcome is legible at a glance instead of a wall of
+/// `SlaveResponse` debug output; one [`FileResult`] JSON line per file
+/// instead, followed by a `{"sent": _, "total": _}` line, if `json` is set.
+pub fn print_batch_summary(batch: &BatchHandle, json: bool) {
+    let total = batch.files.len();
+    let mut sent = 0;
+    for file in &batch.files {
+        match &file.response {
+            Ok(response) => {
+                if matches!(response, SlaveResponse::Ok) {
+                    sent += 1;
+                }
+                if json {
+                    print_json(&FileResult {
+                        path: file.path.display().to_string(),
+                        response: Some(response.clone()),
+                        error: None,
+                    });
+                } else {
+                    println!("{}: {response:?}", file.path.display());
+                }
+            }
+            Err(err) => {
+                if json {
+                    print_json(&FileResult {
+                        path: file.path.display().to_string(),
+                        response: None,
+                        error: Some(format!("{err:#}")),
+                    });
+                } else {
+                    println!("{}: error: {err:#}", file.path.display());
+                }
+            }
+        }
+    }
+    if json {
+        print_json(&serde_json::json!({"sent": sent, "total": total}));
+    } else {
+        println!("{sent}/{total} files sent successfully");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_literal_pattern_is_returned_unexpanded() {
+        assert_eq!(expand_glob("some/file.txt").unwrap(), vec![PathBuf::from("some/file.txt")]);
+    }
+
+    #[test]
+    fn a_glob_pattern_expands_to_matching_files_sorted() {
+        let dir = std::env::temp_dir().join(format!("portal-cli-glob-test-{:?}", std::thread::current().id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.txt"), b"").unwrap();
+        std::fs::write(dir.join("a.txt"), b"").unwrap();
+        std::fs::write(dir.join("c.md"), b"").unwrap();
+
+        let matches = expand_glob(&format!("{}/*.txt", dir.to_string_lossy())).unwrap();
+
+        assert_eq!(matches, vec![dir.join("a.txt"), dir.join("b.txt")]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,57 @@
+//! Shared `--json` helpers: one object per line, printed as it's produced
+//! rather than batched into an array, so a consumer can start processing
+//! scan results or transfer outcomes before the command finishes.
+
+use std::net::SocketAddr;
+
+use portal_core::broadcast::DiscoveredDevice;
+use portal_core::codec::SlaveResponse;
+use portal_core::identity::DeviceId;
+use serde::Serialize;
+
+/// Prints `value` as one line of JSON, or a warning to stderr if it somehow
+/// fails to serialize (no type used with this ever should), so a caller
+/// already past its main work doesn't have to decide what to do with the
+/// error itself.
+pub fn print_json(value: &impl Serialize) {
+    match serde_json::to_string(value) {
+        Ok(line) => println!("{line}"),
+        Err(err) => eprintln!("warning: failed to serialize JSON output: {err:#}"),
+    }
+}
+
+/// A discovered device, as printed by `portal scan --json` and the
+/// interactive menu's `ListDevices` when `--json` is set.
+#[derive(Serialize)]
+pub struct DeviceRecord {
+    pub device_id: DeviceId,
+    pub addr: SocketAddr,
+    pub service_port: u16,
+    pub platform: String,
+    pub version: String,
+    pub alias: Option<String>,
+    pub hostname: String,
+}
+
+impl DeviceRecord {
+    pub fn new(device_id: DeviceId, device: &DiscoveredDevice, alias: Option<String>) -> Self {
+        Self {
+            device_id,
+            addr: device.addr,
+            service_port: device.service_port,
+            platform: device.platform.clone(),
+            version: device.version.clone(),
+            alias,
+            hostname: device.hostname.clone(),
+        }
+    }
+}
+
+/// One file's outcome from a send, as printed by `portal send --json` and
+/// [`crate::glob_util::print_batch_summary`] when `--json` is set.
+#[derive(Serialize)]
+pub struct FileResult {
+    pub path: String,
+    pub response: Option<SlaveResponse>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,56 @@
+mod clipboard;
+mod command;
+mod config;
+mod glob_util;
+mod json_output;
+mod notify;
+mod qr;
+mod script;
+
+use anyhow::Result;
+use clap::Parser;
+use inquire::Select;
+use strum::IntoEnumIterator;
+
+use command::{Commands, Manager};
+
+/// `portal` with no subcommand falls back to the interactive `inquire`
+/// menu; any other invocation runs that one [`script::Command`] and exits,
+/// so the tool can be scripted or run over SSH without a TTY.
+#[derive(Parser)]
+#[command(name = "portal", version, about = "Send and receive files over the LAN")]
+struct Cli {
+    /// Print scan results, task listings, and transfer outcomes as JSON
+    /// lines instead of human-readable text, for driving `portal` from a
+    /// script or another program.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Option<script::Command>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+    match cli.command {
+        Some(command) => script::run(command, cli.json).await,
+        None => run_interactive(cli.json).await,
+    }
+}
+
+async fn run_interactive(json: bool) -> Result<()> {
+    let mut manager = Manager::new().with_json(json);
+    loop {
+        let command = Select::new("What do you want to do?", Commands::iter().collect())
+            .prompt()?;
+        if command == Commands::Quit {
+            break;
+        }
+        if let Err(err) = manager.dispatch(command).await {
+            eprintln!("error: {err:#}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! Desktop notifications for transfers finishing while `portal` is running
+//! unattended — `portal receive` and the interactive menu's
+//! `StartReceiving` — so a user doesn't have to keep the terminal in view
+//! to know a transfer finished.
+
+use notify_rust::Notification;
+
+/// Shows a notification for one finished transfer, logging a warning
+/// instead of failing the caller if there's no notification daemon to show
+/// it to (e.g. running headless or over SSH without a desktop session).
+pub fn notify_transfer(file_name: &str, succeeded: bool, detail: &str) {
+    let summary = if succeeded { "Transfer complete" } else { "Transfer failed" };
+    let result = Notification::new().summary(summary).body(&format!("{file_name}: {detail}")).show();
+    if let Err(err) = result {
+        tracing::warn!(%err, "failed to show a desktop notification");
+    }
+}
@@ -0,0 +1,28 @@
+//! Renders connection info as a terminal QR code, so a phone app or second
+//! machine can connect to a `portal receive` session by scanning rather than
+//! needing discovery to find it first.
+
+use std::net::Ipv4Addr;
+
+use qrcode::render::unicode;
+use qrcode::QrCode;
+
+/// Prints a `portal://ip:port?pairing=code` URI as a QR code, falling back
+/// to a warning if the code can't be rendered; the URI itself is always
+/// printed underneath so it can be typed in by hand if scanning isn't an
+/// option.
+pub fn print_connect_qr(ip: Ipv4Addr, port: u16, pairing_code: &str) {
+    let uri = format!("portal://{ip}:{port}?pairing={pairing_code}");
+    match QrCode::new(&uri) {
+        Ok(code) => {
+            let image = code
+                .render::<unicode::Dense1x2>()
+                .dark_color(unicode::Dense1x2::Light)
+                .light_color(unicode::Dense1x2::Dark)
+                .build();
+            println!("{image}");
+        }
+        Err(err) => tracing::warn!(%err, "failed to render a QR code"),
+    }
+    println!("{uri}");
+}
@@ -0,0 +1,675 @@
+//! Non-interactive `clap` subcommands, so `portal` can be scripted or run
+//! over SSH without a TTY for [`crate::command`]'s `inquire` menu. Each
+//! variant maps to a single, self-contained operation rather than sharing
+//! [`crate::command::Manager`]'s session state, since a scripted invocation
+//! runs once and exits instead of sticking around between commands.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use fs_notify::Watcher;
+use indicatif::{ProgressBar, ProgressStyle};
+use portal_core::broadcast::{self, Listener};
+use portal_core::history::{History, HistoryEntry};
+use portal_core::identity::DeviceId;
+use portal_core::master::{Master, MasterBuilder, Progress};
+use portal_core::registry::Registry;
+use portal_core::slave::{ActiveTransfer, IncomingFile, Slave, SlaveService};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{Config, CONFIG_PATH};
+use crate::glob_util::{expand_glob, print_batch_summary};
+use crate::json_output::{print_json, DeviceRecord, FileResult};
+use crate::notify;
+use crate::qr;
+
+/// TCP port `portal receive` listens on and `portal send` connects to when
+/// `--to` doesn't specify one, separate from [`broadcast::DISCOVERY_PORT`]
+/// since discovery and the file-transfer connection itself don't have to
+/// share a port.
+pub(crate) const DEFAULT_SERVICE_PORT: u16 = 4242;
+
+/// How long `portal send` waits for the TCP connection and handshake to
+/// `--to` to complete before giving up; also used by [`crate::command`]'s
+/// `SetTarget` to dial a device picked from a scan.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where `portal receive` persists its [`DeviceId`] so it keeps the same
+/// identity across restarts instead of looking like a new device to every
+/// peer each time it's run; see [`DeviceId::load_or_create`]. Shared with
+/// [`crate::command::Manager`]'s `StartReceiving`, so the menu and the
+/// script announce under the same identity.
+pub(crate) const DEVICE_ID_PATH: &str = ".portal-device-id";
+
+/// Where device aliases and last-known addresses are persisted; see
+/// [`Registry`]. Shared with [`crate::command::Manager`], so an alias
+/// assigned from either the interactive menu or `portal alias` is visible
+/// to the other.
+pub(crate) const REGISTRY_PATH: &str = ".portal-devices";
+
+/// Where finished transfers are logged; see [`History`]. Shared with
+/// [`crate::command::Manager`], so a transfer started from either the
+/// interactive menu or `portal send` shows up in the same `portal history`.
+pub(crate) const HISTORY_PATH: &str = ".portal-history";
+
+/// How often `portal receive` (and [`crate::command::Manager`]'s
+/// `StartReceiving`) re-announces itself over broadcast while running, so it
+/// stays discoverable to a `portal scan` started after it.
+pub(crate) const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long `watch` waits after a file's last filesystem event before
+/// sending it, so a slow copy into the watched folder isn't read mid-write.
+const WATCH_SETTLE: Duration = Duration::from_millis(750);
+
+/// Where [`crate::command::Manager`] persists in-flight `SendFile`/`submit_send`
+/// tasks, so a transfer interrupted by the process exiting or crashing shows
+/// up as interrupted in `ListIncomplete` on the next run instead of being
+/// forgotten; see [`portal_core::task_manager::TaskManager::with_journal`].
+pub(crate) const JOURNAL_PATH: &str = ".portal-journal";
+
+/// Shared by every progress bar `send` and `receive` draw, so a transfer
+/// looks the same from either side of the connection.
+const PROGRESS_TEMPLATE: &str = "{msg} {bar:40.cyan/blue} {bytes}/{total_bytes} {bytes_per_sec} eta {eta}";
+
+/// Builds a progress bar styled per [`PROGRESS_TEMPLATE`], falling back to
+/// indicatif's default style if the template somehow fails to parse.
+fn progress_bar(file_name: &str, total: u64) -> ProgressBar {
+    let bar = ProgressBar::new(total);
+    if let Ok(style) = ProgressStyle::with_template(PROGRESS_TEMPLATE) {
+        bar.set_style(style.progress_chars("#>-"));
+    }
+    bar.set_message(file_name.to_string());
+    bar
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Send one or more files to a target address.
+    Send {
+        /// Path, or glob pattern (e.g. "*.txt"), of the file(s) to send.
+        /// Omitted when `--clipboard` is given instead.
+        pattern: Option<String>,
+        /// Target to connect to, e.g. "192.168.1.42" or "192.168.1.42:4242";
+        /// a bare address is assumed to be listening on the default port.
+        #[arg(long)]
+        to: String,
+        /// Caps the outgoing transfer rate, e.g. "5MB/s" or "500KB/s";
+        /// overrides the configured `send_rate_limit` default (see
+        /// `EditConfig`) when given.
+        #[arg(long, value_parser = parse_bandwidth)]
+        limit: Option<u64>,
+        /// Send the current clipboard contents instead of `pattern`: text
+        /// goes over the `Text` message, an image is re-encoded as a PNG
+        /// file and sent like any other.
+        #[arg(long, conflicts_with = "pattern")]
+        clipboard: bool,
+    },
+    /// Listen for broadcasts and print the devices found.
+    Scan {
+        /// How long to listen before printing results, in seconds.
+        #[arg(long, default_value_t = 5)]
+        seconds: u64,
+        /// Port to listen for discovery broadcasts on; overrides the
+        /// configured `broadcast_port` (see `EditConfig`) when given.
+        #[arg(long)]
+        broadcast_port: Option<u16>,
+    },
+    /// Assign a saved name to a device, so a later `--to` can use it instead
+    /// of an address.
+    Alias {
+        /// Alias to assign, e.g. "work-laptop".
+        name: String,
+        /// Address of the device to alias, e.g. "192.168.1.42:4242".
+        #[arg(long)]
+        to: String,
+    },
+    /// Make this device discoverable and listen for incoming files, saving
+    /// them to a directory, until interrupted.
+    Receive {
+        /// Port to listen on for incoming files; overrides the configured
+        /// `service_port` (see `EditConfig`) when given.
+        #[arg(long)]
+        service_port: Option<u16>,
+        /// Port to send and listen for discovery broadcasts on; overrides
+        /// the configured `broadcast_port` when given.
+        #[arg(long)]
+        broadcast_port: Option<u16>,
+        /// Directory incoming files are saved to; defaults to whatever is
+        /// configured via the interactive menu's `EditConfig`, or the
+        /// current directory if nothing is configured.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Accept every incoming file without prompting, for unattended use
+        /// (e.g. over SSH without a TTY to prompt on).
+        #[arg(long)]
+        auto_accept: bool,
+        /// Print a QR code encoding this device's address, port, and a
+        /// generated pairing code, so a phone app or second machine can
+        /// connect without waiting for discovery. Requires pairing: only a
+        /// peer that proves knowledge of the code can send files.
+        #[arg(long)]
+        qr: bool,
+        /// Also place incoming text snippets (sent with
+        /// `portal send --clipboard`) on this device's own clipboard.
+        #[arg(long)]
+        clipboard: bool,
+    },
+    /// Show the log of completed and failed transfers.
+    History,
+    /// Watch a directory and automatically send every new or modified file
+    /// in it to a target device, until interrupted; for "drop it in this
+    /// folder and it appears on my desktop" workflows.
+    Watch {
+        /// Directory to watch; not recursive, so files dropped into a
+        /// subdirectory of it aren't sent.
+        dir: PathBuf,
+        /// Target to send to, same as `send --to`.
+        #[arg(long)]
+        to: String,
+        /// Caps the outgoing transfer rate, same as `send --limit`.
+        #[arg(long, value_parser = parse_bandwidth)]
+        limit: Option<u64>,
+    },
+}
+
+/// Runs `command` to completion, printing its result the same way whether
+/// it's a single reply (`send`), a list (`scan`), or output that keeps
+/// appearing for as long as the process runs (`receive`). `json` is the
+/// top-level `--json` flag; `alias` and `receive` ignore it, since the
+/// former is already a single line and the latter runs indefinitely with
+/// interactive accept prompts rather than producing discrete records.
+pub async fn run(command: Command, json: bool) -> Result<()> {
+    match command {
+        Command::Send { pattern, to, limit, clipboard } => send(pattern.as_deref(), &to, limit, clipboard, json).await,
+        Command::Scan { seconds, broadcast_port } => scan(seconds, broadcast_port, json).await,
+        Command::Alias { name, to } => alias(&name, &to).await,
+        Command::Receive { service_port, broadcast_port, output, auto_accept, qr, clipboard } => {
+            receive(service_port, broadcast_port, output, auto_accept, qr, clipboard).await
+        }
+        Command::History => history(json),
+        Command::Watch { dir, to, limit } => watch(dir, &to, limit, json).await,
+    }
+}
+
+/// Resolves `to` to a [`SocketAddr`]: a literal address is used as-is,
+/// otherwise `to` is looked up as a saved alias in the [`Registry`] at
+/// [`REGISTRY_PATH`], and only if that also doesn't match is it resolved as
+/// a hostname, assuming [`DEFAULT_SERVICE_PORT`] if no port is given.
+fn resolve_target(to: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = to.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(registry) = Registry::open(REGISTRY_PATH) {
+        if let Some(entry) = registry.find_by_alias(to) {
+            return Ok(entry.last_addr);
+        }
+    }
+    format!("{to}:{DEFAULT_SERVICE_PORT}")
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve target address {to:?}"))?
+        .next()
+        .with_context(|| format!("{to:?} did not resolve to any address"))
+}
+
+/// Connects to `addr`, capping the outgoing rate at `limit` bytes/sec if
+/// given; `None` connects exactly as [`Master::connect`] would.
+async fn connect_with_limit(addr: SocketAddr, limit: Option<u64>) -> Result<Master> {
+    let mut builder = MasterBuilder::new();
+    if let Some(limit) = limit {
+        builder = builder.rate_limit(limit);
+    }
+    builder.connect(addr, CONNECT_TIMEOUT).await.with_context(|| format!("failed to connect to {addr}"))
+}
+
+/// Parses a bandwidth limit like `"5MB/s"`, `"500KB/s"`, or a bare number of
+/// bytes/sec, into bytes/sec. Units are decimal (1 MB = 1_000_000 bytes, not
+/// 1024*1024) and case-insensitive; the trailing `"/s"` is optional.
+pub(crate) fn parse_bandwidth(input: &str) -> std::result::Result<u64, String> {
+    let trimmed = input.trim();
+    let without_suffix = trimmed.strip_suffix("/s").or_else(|| trimmed.strip_suffix("/S")).unwrap_or(trimmed);
+    let lower = without_suffix.to_lowercase();
+    let (number, multiplier) = if let Some(n) = lower.strip_suffix("gb") {
+        (n, 1_000_000_000.0)
+    } else if let Some(n) = lower.strip_suffix("mb") {
+        (n, 1_000_000.0)
+    } else if let Some(n) = lower.strip_suffix("kb") {
+        (n, 1_000.0)
+    } else if let Some(n) = lower.strip_suffix('b') {
+        (n, 1.0)
+    } else {
+        (lower.as_str(), 1.0)
+    };
+    let value: f64 =
+        number.trim().parse().map_err(|_| format!("{input:?} is not a valid bandwidth limit (e.g. \"5MB/s\")"))?;
+    Ok((value * multiplier) as u64)
+}
+
+/// Expands `pattern` and sends every match to `to`: a single match gets a
+/// progress bar the same as before glob support existed, while several
+/// matches go through [`Master::send_files`] and an aggregate summary
+/// instead, since per-file progress bars for an unattended batch would
+/// just scroll past on a non-interactive terminal.
+async fn send(pattern: Option<&str>, to: &str, limit: Option<u64>, clipboard: bool, json: bool) -> Result<()> {
+    let config = Config::load_or_default(CONFIG_PATH).unwrap_or_default();
+    let limit = limit.or(config.send_rate_limit);
+
+    if clipboard {
+        return send_clipboard(to, limit, json).await;
+    }
+    let pattern = pattern.context("a file or glob pattern is required unless --clipboard is given")?;
+
+    let paths = expand_glob(pattern)?;
+    let [path] = paths.as_slice() else {
+        if paths.is_empty() {
+            println!("no files matched {pattern:?}");
+            return Ok(());
+        }
+        let addr = resolve_target(to)?;
+        let mut master = connect_with_limit(addr, limit).await?;
+        let started = Instant::now();
+        let batch = master.send_files(paths).await;
+        let duration = started.elapsed();
+        for file in &batch.files {
+            record_history(addr, &file.path, duration, &file.response);
+        }
+        print_batch_summary(&batch, json);
+        return Ok(());
+    };
+
+    let addr = resolve_target(to)?;
+    let mut master = connect_with_limit(addr, limit).await?;
+
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let bar = progress_bar(&file_name, 0);
+    let (tx, mut rx) = watch::channel(Progress::default());
+    let watcher = {
+        let bar = bar.clone();
+        tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let snapshot = *rx.borrow();
+                bar.set_length(snapshot.total);
+                bar.set_position(snapshot.bytes_confirmed);
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let response = master.send_a_file_with_progress(path, tx).await;
+    let duration = started.elapsed();
+    record_history(addr, path, duration, &response);
+    let _ = watcher.await;
+    bar.finish_and_clear();
+    let response = response?;
+    if json {
+        print_json(&FileResult { path: path.display().to_string(), response: Some(response), error: None });
+    } else {
+        println!("{response:?}");
+    }
+    Ok(())
+}
+
+/// Reads the system clipboard and sends it to `to`: text goes straight over
+/// [`Master::send_text`], while an image is written to a temporary PNG file
+/// and sent through the regular single-file path in [`send`], since the
+/// wire protocol has no separate binary clipboard message.
+async fn send_clipboard(to: &str, limit: Option<u64>, json: bool) -> Result<()> {
+    match crate::clipboard::read().context("failed to read the clipboard")? {
+        crate::clipboard::ClipboardContent::Text(content) => {
+            let addr = resolve_target(to)?;
+            let mut master = connect_with_limit(addr, limit).await?;
+            let response = master.send_text(content).await;
+            if json {
+                let (response, error) = match &response {
+                    Ok(response) => (Some(response.clone()), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+                print_json(&FileResult { path: "<clipboard>".to_string(), response, error });
+            } else {
+                println!("{response:?}");
+            }
+            Ok(())
+        }
+        crate::clipboard::ClipboardContent::ImagePng(png) => {
+            let path = std::env::temp_dir().join(format!("portal-clipboard-{}.png", std::process::id()));
+            std::fs::write(&path, png).context("failed to write the clipboard image to a temporary file")?;
+            let result = Box::pin(send(Some(&path.to_string_lossy()), to, limit, false, json)).await;
+            let _ = std::fs::remove_file(&path);
+            result
+        }
+    }
+}
+
+/// Records `path`'s outcome to the [`History`] at [`HISTORY_PATH`], sizing
+/// it from the filesystem since the response itself doesn't carry that;
+/// logs a warning rather than failing the send if the history can't be
+/// written to. For a batch, `duration` is the whole batch's wall-clock
+/// time rather than this one file's share of it, since
+/// [`portal_core::master::BatchHandle`] doesn't track per-file timing.
+pub(crate) fn record_history(
+    peer: SocketAddr,
+    path: &std::path::Path,
+    duration: Duration,
+    response: &portal_core::Result<portal_core::codec::SlaveResponse>,
+) {
+    let size = std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    let entry = HistoryEntry::new(path.to_path_buf(), peer, size, duration, response);
+    let result = History::open(HISTORY_PATH).and_then(|mut history| history.record(entry));
+    if let Err(err) = result {
+        tracing::warn!(%err, "failed to update the transfer history");
+    }
+}
+
+/// Prints every [`HistoryEntry`] in [`HISTORY_PATH`], oldest first.
+fn history(json: bool) -> Result<()> {
+    let history = History::open(HISTORY_PATH).context("failed to open the transfer history")?;
+    if history.entries().is_empty() {
+        if !json {
+            println!("no transfers recorded yet");
+        }
+        return Ok(());
+    }
+    for entry in history.entries() {
+        if json {
+            print_json(entry);
+        } else {
+            let status = if entry.succeeded { "ok" } else { "failed" };
+            println!(
+                "{:<21} {:>10} bytes  {:>6} ms  {status:<6} {}: {}",
+                entry.peer.to_string(),
+                entry.size,
+                entry.duration_ms,
+                entry.file.display(),
+                entry.result,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Scans for devices and prints what's found, noting each one's dial
+/// address (its [`broadcast::DiscoveredDevice::service_port`], not the
+/// ephemeral broadcast source port) in the [`Registry`] as it goes, so a
+/// later `portal alias` or a menu-driven `SetTarget` doesn't need to
+/// rescan just to find an address to connect to.
+async fn scan(seconds: u64, broadcast_port: Option<u16>, json: bool) -> Result<()> {
+    let config = Config::load_or_default(CONFIG_PATH).unwrap_or_default();
+    let broadcast_port = broadcast_port.unwrap_or(config.broadcast_port);
+    let mut listener = Listener::bind(&format!("0.0.0.0:{broadcast_port}")).await?;
+    listener
+        .async_scan_device(Duration::from_secs(seconds), CancellationToken::new())
+        .await;
+    if listener.scanned_devices.is_empty() {
+        if !json {
+            println!("no devices found");
+        }
+        return Ok(());
+    }
+    let mut registry = Registry::open(REGISTRY_PATH).context("failed to open the device registry")?;
+    for (device_id, device) in &listener.scanned_devices {
+        let dial_addr = SocketAddr::new(device.addr.ip(), device.service_port);
+        if let Err(err) = registry.note_seen(*device_id, dial_addr) {
+            tracing::warn!(%err, "failed to update the device registry");
+        }
+        let alias = registry.get(*device_id).and_then(|entry| entry.alias.as_deref()).unwrap_or("-").to_string();
+        if json {
+            print_json(&DeviceRecord::new(*device_id, device, (alias != "-").then_some(alias)));
+        } else {
+            println!(
+                "{device_id}  {:<21} {:<8} {:<8} {:<12} {}",
+                device.addr.to_string(),
+                device.platform,
+                device.version,
+                alias,
+                device.hostname,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Connects to `to`, learns its [`DeviceId`] from the handshake, and saves
+/// `name` as its alias in the [`Registry`], so a later `--to name` resolves
+/// to it without needing the address again.
+async fn alias(name: &str, to: &str) -> Result<()> {
+    let addr = resolve_target(to)?;
+    let master =
+        Master::connect(addr, CONNECT_TIMEOUT).await.with_context(|| format!("failed to connect to {addr}"))?;
+    let device_id = master.peer_device_id().context("peer did not report a device id during the handshake")?;
+
+    let mut registry = Registry::open(REGISTRY_PATH).context("failed to open the device registry")?;
+    registry.note_seen(device_id, addr)?;
+    registry.set_alias(device_id, Some(name.to_string()))?;
+    println!("aliased {addr} as {name:?}");
+    Ok(())
+}
+
+async fn receive(
+    service_port: Option<u16>,
+    broadcast_port: Option<u16>,
+    output: Option<PathBuf>,
+    auto_accept: bool,
+    qr: bool,
+    clipboard: bool,
+) -> Result<()> {
+    let device_id = DeviceId::load_or_create(DEVICE_ID_PATH)
+        .context("failed to load or create this device's persistent id")?;
+    // `--auto-accept`, `--output`, `--service-port` and `--broadcast-port`
+    // always win; otherwise fall back to whatever was configured via the
+    // interactive menu's `EditConfig`, so the two don't disagree about the
+    // same settings.
+    let config = Config::load_or_default(CONFIG_PATH).unwrap_or_default();
+    let port = service_port.unwrap_or(config.service_port);
+    let broadcast_port = broadcast_port.unwrap_or(config.broadcast_port);
+    let output = output.unwrap_or_else(|| config.received_dir.clone());
+
+    // Re-announced in the background for as long as this process runs, so a
+    // `portal scan` elsewhere on the LAN finds this device even if it was
+    // started after `portal receive` already was.
+    let mut sender = broadcast::Sender::new_on_port(port, broadcast_port, device_id)
+        .context("failed to set up discovery broadcasts")?;
+    if let Some(device_name) = &config.device_name {
+        sender = sender.with_hostname(device_name.clone());
+    }
+    tokio::spawn(async move {
+        sender
+            .async_send_loop(ANNOUNCE_INTERVAL, &AtomicUsize::new(0), CancellationToken::new())
+            .await;
+    });
+
+    let mut service = SlaveService::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("failed to bind port {port}"))?;
+    if let Some(limit) = config.max_inbound_bytes_per_sec {
+        service.set_max_inbound_bytes_per_sec(limit);
+    }
+
+    // A pairing code is only generated (and required) when `--qr` is given,
+    // so `portal receive` without it keeps behaving exactly as before.
+    let pairing_code = qr.then(|| DeviceId::generate().to_string().replace('-', "")[..6].to_uppercase());
+    if let Some(pairing_code) = &pairing_code {
+        let ip = broadcast::local_ipv4_addresses().into_iter().next().unwrap_or(Ipv4Addr::UNSPECIFIED);
+        qr::print_connect_qr(ip, port, pairing_code);
+    }
+
+    let bars: Arc<Mutex<HashMap<u32, ProgressBar>>> = Arc::new(Mutex::new(HashMap::new()));
+    service.configure(Arc::new(move |slave: &mut Slave| {
+        if let Some(pairing_code) = &pairing_code {
+            slave.set_pairing_key(pairing_code.clone());
+        }
+        slave.set_output_dir(output.clone());
+        slave.on_text(Arc::new(move |content: String| {
+            println!("received text: {content}");
+            if clipboard {
+                if let Err(err) = crate::clipboard::write_text(&content) {
+                    tracing::warn!(%err, "failed to place received text on the clipboard");
+                }
+            }
+        }));
+        let output = output.clone();
+        let config = config.clone();
+        slave.on_incoming_file(Arc::new(move |incoming: IncomingFile| {
+            let destination = output.join(&incoming.file_name);
+            let accept = auto_accept || config.auto_accept.accepts(&incoming.file_name);
+            Box::pin(accept_incoming_file(incoming, destination, accept))
+        }));
+        let bars = bars.clone();
+        slave.on_progress(Arc::new(move |transfer: ActiveTransfer| {
+            let mut bars = bars.lock().unwrap_or_else(|err| err.into_inner());
+            let done = transfer.bytes_received >= transfer.file_size;
+            {
+                let bar = bars
+                    .entry(transfer.file_id)
+                    .or_insert_with(|| progress_bar(&transfer.file_name, transfer.file_size));
+                bar.set_length(transfer.file_size);
+                bar.set_position(transfer.bytes_received);
+                if done {
+                    bar.finish_and_clear();
+                }
+            }
+            if done {
+                bars.remove(&transfer.file_id);
+                notify::notify_transfer(&transfer.file_name, true, "received");
+            }
+        }));
+    }));
+
+    println!("listening on port {port}, discoverable as {device_id}; press Ctrl+C to stop");
+    service.run().await;
+    Ok(())
+}
+
+/// Decides whether to accept `incoming`, auto-accepting it if `auto_accept`
+/// is set and otherwise asking on the terminal; either way, prints where it
+/// ends up (or that it was declined) so the operator running `portal
+/// receive` interactively can follow along.
+async fn accept_incoming_file(incoming: IncomingFile, destination: PathBuf, auto_accept: bool) -> bool {
+    let accepted = if auto_accept {
+        true
+    } else {
+        let prompt = format!("Accept {} ({} bytes)?", incoming.file_name, incoming.file_size);
+        tokio::task::spawn_blocking(move || inquire::Confirm::new(&prompt).with_default(true).prompt().unwrap_or(false))
+            .await
+            .unwrap_or(false)
+    };
+    if accepted {
+        println!("saving {} to {}", incoming.file_name, destination.display());
+    } else {
+        println!("declined {}", incoming.file_name);
+    }
+    accepted
+}
+
+/// Watches `dir` (non-recursively) and sends every new or modified file in
+/// it to `to`, until interrupted. A file is sent once its filesystem events
+/// have been quiet for [`WATCH_SETTLE`], so a still-copying file isn't read
+/// mid-write; a send that fails (e.g. the target is offline) is reported and
+/// skipped rather than retried, so one bad file doesn't block the ones after
+/// it.
+async fn watch(dir: PathBuf, to: &str, limit: Option<u64>, json: bool) -> Result<()> {
+    let config = Config::load_or_default(CONFIG_PATH).unwrap_or_default();
+    let limit = limit.or(config.send_rate_limit);
+    let addr = resolve_target(to)?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = fs_notify::recommended_watcher(move |event: fs_notify::Result<fs_notify::Event>| {
+        if let Ok(event) = event {
+            let _ = tx.send(event);
+        }
+    })
+    .context("failed to start watching the folder")?;
+    watcher
+        .watch(&dir, fs_notify::RecursiveMode::NonRecursive)
+        .with_context(|| format!("failed to watch {}", dir.display()))?;
+
+    println!("watching {} for new or changed files to send to {to}; press Ctrl+C to stop", dir.display());
+
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut tick = tokio::time::interval(WATCH_SETTLE / 4);
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else { break };
+                if !matches!(event.kind, fs_notify::EventKind::Create(_) | fs_notify::EventKind::Modify(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    if path.is_file() {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+            }
+            _ = tick.tick() => {
+                let settled: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, last_event)| last_event.elapsed() >= WATCH_SETTLE)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in settled {
+                    pending.remove(&path);
+                    if path.is_file() {
+                        send_watched_file(&path, addr, limit, json).await;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends one file found by [`watch`], reporting success or failure the same
+/// way `send` does rather than propagating the error, since `watch` keeps
+/// running afterward regardless of how this file's send went.
+async fn send_watched_file(path: &std::path::Path, addr: SocketAddr, limit: Option<u64>, json: bool) {
+    let result = async {
+        let mut master = connect_with_limit(addr, limit).await?;
+        let started = Instant::now();
+        let response = master.send_a_file(path).await;
+        record_history(addr, path, started.elapsed(), &response);
+        response.map_err(anyhow::Error::from)
+    }
+    .await;
+
+    match result {
+        Ok(response) => {
+            if json {
+                print_json(&FileResult { path: path.display().to_string(), response: Some(response), error: None });
+            } else {
+                println!("sent {}: {response:?}", path.display());
+            }
+        }
+        Err(err) => {
+            if json {
+                print_json(&FileResult { path: path.display().to_string(), response: None, error: Some(err.to_string()) });
+            } else {
+                eprintln!("failed to send {}: {err:#}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_units_case_insensitively() {
+        assert_eq!(parse_bandwidth("5MB/s").unwrap(), 5_000_000);
+        assert_eq!(parse_bandwidth("500kb/s").unwrap(), 500_000);
+        assert_eq!(parse_bandwidth("2GB").unwrap(), 2_000_000_000);
+        assert_eq!(parse_bandwidth("100B/s").unwrap(), 100);
+        assert_eq!(parse_bandwidth("12345").unwrap(), 12345);
+    }
+
+    #[test]
+    fn rejects_unparseable_input() {
+        assert!(parse_bandwidth("fast").is_err());
+        assert!(parse_bandwidth("").is_err());
+    }
+}
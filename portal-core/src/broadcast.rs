@@ -0,0 +1,1533 @@
+//! LAN discovery: a device periodically broadcasts its presence, and other
+//! devices listen for those broadcasts to learn who is reachable.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::UdpSocket as AsyncUdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "signed-broadcast")]
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::identity::DeviceId;
+
+/// Default prefix used to recognize portal discovery packets among arbitrary
+/// UDP traffic. Used as-is unless a deployment opts into a namespace via
+/// [`Sender::with_namespace`], [`Listener::with_namespace`], or
+/// [`Prober::with_namespace`]; see [`namespace_magic`].
+pub const MAGIC: &[u8] = b"PORTAL01";
+
+/// UDP port discovery packets are sent to and listened on.
+pub const DISCOVERY_PORT: u16 = 3000;
+
+/// Administratively-scoped IPv6 multicast group portal announces itself on
+/// when broadcasting isn't an option, e.g. an IPv6-only network or one
+/// segmented such that `255.255.255.255` doesn't reach every device. Chosen
+/// from the `ff05::/16` site-local scope and not registered with IANA, the
+/// same way [`MAGIC`] is a made-up prefix rather than a registered protocol
+/// number — only other portal instances are expected to join it.
+pub const MULTICAST_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0x504c);
+
+/// Version of [`BroadcastPayload`]'s bincode encoding, sent as a single byte
+/// right after [`MAGIC`]. Bumped whenever the struct's fields change in a way
+/// that would otherwise break an older listener trying to decode a newer
+/// payload (or vice versa); [`parse_payload`] rejects anything that doesn't
+/// match rather than guessing.
+const BROADCAST_FORMAT_VERSION: u8 = 1;
+
+/// A string field longer than this is truncated before being broadcast, so
+/// the packet stays well within a UDP datagram's comfortable size regardless
+/// of what's configured on the sending device.
+const MAX_FIELD_LEN: usize = 63;
+
+/// Byte appended right after the bincode-encoded [`BroadcastPayload`] to mark
+/// a signed packet, so [`parse_payload`] (which ignores anything past the
+/// payload it decodes) and a signature-aware listener agree on where the
+/// payload ends and the signature begins. Arbitrary, chosen only to not be
+/// mistaken for the start of another payload.
+#[cfg(feature = "signed-broadcast")]
+const SIGNATURE_MARKER: u8 = 0xaa;
+
+/// Length in bytes of an Ed25519 signature.
+#[cfg(feature = "signed-broadcast")]
+const SIGNATURE_LEN: usize = 64;
+
+/// Marker byte for a bare "who's there?" probe: [`MAGIC`] followed by this
+/// byte and nothing else. Sent by [`Sender::send_probe_request`] so a
+/// scanning device can ask to be answered on demand instead of waiting for
+/// others to announce themselves on their own schedule; answered by
+/// [`Listener::scan_device`] when [`Listener::set_probe_reply`] is enabled.
+/// Distinct from [`BROADCAST_FORMAT_VERSION`] so it's never mistaken for an
+/// announcement.
+const PROBE_REQUEST_MARKER: u8 = 0xff;
+
+/// Derives the magic prefix a [`Sender`], [`Listener`], or [`Prober`] places
+/// at the start of every packet when given `namespace`, so two independent
+/// portal deployments on the same LAN (e.g. separate office teams) that pick
+/// different namespaces never recognize each other's packets as valid
+/// — [`parse_payload`] and [`is_probe_request`] just see a magic mismatch,
+/// the same as any other stray UDP traffic. An empty namespace reproduces
+/// [`MAGIC`] unchanged, so deployments that don't care about isolation keep
+/// working exactly as before. A non-empty namespace is hashed together with
+/// [`MAGIC`] with SHA-256 and truncated to [`MAGIC`]'s length, rather than
+/// used verbatim, so the wire prefix stays a fixed, short size regardless of
+/// how long a namespace string someone picks.
+fn namespace_magic(namespace: &str) -> Vec<u8> {
+    if namespace.is_empty() {
+        return MAGIC.to_vec();
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(MAGIC);
+    hasher.update(namespace.as_bytes());
+    hasher.finalize()[..MAGIC.len()].to_vec()
+}
+
+/// Everything one broadcast packet advertises about its sender. Encoded with
+/// [`bincode`] behind [`MAGIC`] and [`BROADCAST_FORMAT_VERSION`], the same
+/// way [`crate::codec`] encodes the TCP protocol's requests and responses,
+/// so new fields can be added by bumping the format version instead of
+/// hand-rolling a new byte layout each time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BroadcastPayload {
+    service_port: u16,
+    device_id: DeviceId,
+    platform: String,
+    version: String,
+    hostname: String,
+}
+
+fn truncated(mut value: String) -> String {
+    if value.len() > MAX_FIELD_LEN {
+        value.truncate(MAX_FIELD_LEN);
+    }
+    value
+}
+
+fn local_hostname() -> String {
+    truncated(
+        hostname::get()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    )
+}
+
+/// The running binary's platform, e.g. `"macos"`, `"linux"`, `"windows"`.
+fn local_platform() -> String {
+    std::env::consts::OS.to_string()
+}
+
+/// The running binary's version, so a listener can warn about (or refuse to
+/// connect to) an incompatible peer before even dialing it.
+fn local_version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+/// Generates a fresh Ed25519 keypair for signing broadcast payloads; see
+/// [`Sender::with_signing_key`].
+#[cfg(feature = "signed-broadcast")]
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut rand_core::OsRng)
+}
+
+/// Periodically announces this device's presence to the LAN, either via
+/// IPv4 broadcast ([`Sender::new`]) or IPv6 multicast ([`Sender::new_v6`]);
+/// see [`BroadcastPayload`] for what's included.
+pub struct Sender {
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    service_port: u16,
+    device_id: DeviceId,
+    platform: String,
+    version: String,
+    hostname: String,
+    /// Prefix placed at the start of every packet; see [`Self::with_namespace`].
+    magic: Vec<u8>,
+    /// Signs every outgoing payload when set; see [`Self::with_signing_key`].
+    #[cfg(feature = "signed-broadcast")]
+    signing_key: Option<SigningKey>,
+}
+
+impl Sender {
+    /// `device_id` identifies this installation to listeners, independent of
+    /// whatever address the broadcast happens to arrive from; pass one
+    /// loaded via [`DeviceId::load_or_create`] so it stays stable across
+    /// restarts.
+    ///
+    /// Binds one socket per up, non-loopback IPv4 interface and announces on
+    /// each one's own directed broadcast address (e.g. `192.168.1.255`)
+    /// rather than only the limited broadcast address `255.255.255.255`,
+    /// since some routers and operating systems drop the latter and a
+    /// single unbound socket isn't guaranteed to egress on every interface
+    /// of a multi-homed machine (e.g. one with both Ethernet and Wi-Fi); see
+    /// [`directed_broadcast_interfaces`].
+    pub fn new(service_port: u16, device_id: DeviceId) -> io::Result<Self> {
+        Self::new_on_port(service_port, DISCOVERY_PORT, device_id)
+    }
+
+    /// Like [`Self::new`], but announces to `discovery_port` instead of
+    /// [`DISCOVERY_PORT`], so a [`Listener`] bound to a non-default
+    /// discovery port (e.g. `portal-cli`'s `--broadcast-port`) still hears
+    /// it.
+    pub fn new_on_port(service_port: u16, discovery_port: u16, device_id: DeviceId) -> io::Result<Self> {
+        let mut targets = Vec::new();
+        for (local_ip, broadcast) in directed_broadcast_interfaces() {
+            let socket = UdpSocket::bind((local_ip, 0))?;
+            socket.set_broadcast(true)?;
+            targets.push((socket, SocketAddr::from((broadcast, discovery_port))));
+        }
+        Self::with_targets(targets, service_port, device_id)
+    }
+
+    /// Like [`Self::new`], but multicasts to [`MULTICAST_GROUP_V6`] over
+    /// IPv6 instead of broadcasting over IPv4, for networks where
+    /// `255.255.255.255` doesn't reach anyone.
+    pub fn new_v6(service_port: u16, device_id: DeviceId) -> io::Result<Self> {
+        let socket = UdpSocket::bind("[::]:0")?;
+        let destination = (MULTICAST_GROUP_V6, DISCOVERY_PORT).into();
+        Self::with_targets(vec![(socket, destination)], service_port, device_id)
+    }
+
+    fn with_targets(
+        targets: Vec<(UdpSocket, SocketAddr)>,
+        service_port: u16,
+        device_id: DeviceId,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            targets,
+            service_port,
+            device_id,
+            platform: local_platform(),
+            version: local_version(),
+            hostname: local_hostname(),
+            magic: MAGIC.to_vec(),
+            #[cfg(feature = "signed-broadcast")]
+            signing_key: None,
+        })
+    }
+
+    /// Scopes every packet sent from now on to `namespace`, so only a
+    /// [`Listener`] or [`Prober`] configured with the same namespace
+    /// recognizes it — a separate deployment on the same LAN (e.g. another
+    /// team's office) using a different namespace (or none) just sees
+    /// unrecognized traffic; see [`namespace_magic`].
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.magic = namespace_magic(namespace);
+        self
+    }
+
+    /// Announces `hostname` instead of the OS-reported one from now on, so
+    /// a user-assigned device name (see [`DiscoveredDevice::hostname`])
+    /// shows up in scans rather than whatever the machine calls itself.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Signs every payload sent from now on with `signing_key`, so a
+    /// listener that already knows this device's [`VerifyingKey`] (e.g.
+    /// from a prior [`crate::trust::TrustStore`]-backed exchange) can
+    /// confirm an advertisement really came from it and not from another
+    /// machine on the LAN broadcasting the same [`DeviceId`].
+    #[cfg(feature = "signed-broadcast")]
+    pub fn with_signing_key(mut self, signing_key: SigningKey) -> Self {
+        self.signing_key = Some(signing_key);
+        self
+    }
+
+    /// Sends a single announcement advertising this device out of every
+    /// socket this sender holds, so a listener can show something more
+    /// useful than a bare address (e.g. "Alice's MacBook (portal 0.3)") when
+    /// several devices are discovered at once, and flag an incompatible
+    /// version before even dialing it. A machine with more than one active
+    /// interface (e.g. Ethernet and Wi-Fi) is announced on both.
+    ///
+    /// Succeeds as long as at least one target accepted the packet, since a
+    /// multi-homed machine shouldn't fail discovery entirely because one
+    /// interface (e.g. a disconnected one) rejected the send.
+    pub fn send_once(&self) -> io::Result<()> {
+        let payload = encode_payload(
+            &self.magic,
+            &BroadcastPayload {
+                service_port: self.service_port,
+                device_id: self.device_id,
+                platform: self.platform.clone(),
+                version: self.version.clone(),
+                hostname: self.hostname.clone(),
+            },
+        );
+        #[cfg(feature = "signed-broadcast")]
+        let payload = match &self.signing_key {
+            Some(signing_key) => sign_payload(payload, signing_key),
+            None => payload,
+        };
+        self.send_to_all(&payload)
+    }
+
+    /// Sends a bare "who's there?" probe instead of announcing this device's
+    /// own presence, so a scanning device can ask for replies on demand
+    /// rather than requiring every other device to broadcast continuously
+    /// just to be found; see [`Listener::set_probe_reply`] for what answers
+    /// it.
+    pub fn send_probe_request(&self) -> io::Result<()> {
+        self.send_to_all(&encode_probe_request(&self.magic))
+    }
+
+    /// Sends `payload` out of every socket this sender holds, succeeding as
+    /// long as at least one target accepted it; see [`Self::send_once`] for
+    /// why a multi-homed machine shouldn't fail outright if one interface
+    /// rejects the send.
+    fn send_to_all(&self, payload: &[u8]) -> io::Result<()> {
+        let mut last_error = None;
+        let mut sent_any = false;
+        for (socket, destination) in &self.targets {
+            match socket.send_to(payload, destination) {
+                Ok(_) => sent_any = true,
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) if !sent_any => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Repeatedly calls [`Self::send_once`] until `token` is cancelled,
+    /// waiting [`announce_interval`] between sends — jittered so many
+    /// devices starting up at the same time (e.g. after a power outage)
+    /// don't keep re-synchronizing on every announcement, and backed off
+    /// once `active_connections` reports at least one established
+    /// connection, since a device other peers have already found has less
+    /// reason to keep announcing itself at the same rate.
+    pub async fn async_send_loop(&self, interval: Duration, active_connections: &AtomicUsize, token: CancellationToken) {
+        loop {
+            if let Err(err) = self.send_once() {
+                tracing::debug!(%err, "failed to send a broadcast announcement");
+            }
+            let wait = crate::retry::full_jitter(
+                announce_interval(interval, active_connections.load(Ordering::Relaxed)).saturating_mul(2),
+            );
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+}
+
+/// Multiplier applied to the announce interval once at least one connection
+/// has been established; see [`Sender::async_send_loop`].
+const ANNOUNCE_BACKOFF_MULTIPLIER: u32 = 6;
+
+/// The interval [`Sender::async_send_loop`] waits between announcements,
+/// given how many connections are currently established.
+fn announce_interval(base: Duration, active_connections: usize) -> Duration {
+    if active_connections > 0 {
+        base.saturating_mul(ANNOUNCE_BACKOFF_MULTIPLIER)
+    } else {
+        base
+    }
+}
+
+/// The local address and directed broadcast address (e.g. `192.168.1.42` and
+/// `192.168.1.255` for an interface at `192.168.1.42/24`) of each up,
+/// non-loopback IPv4 interface, computed from the live interface list rather
+/// than hardcoded, since a multi-homed machine's addresses and subnets
+/// aren't known ahead of time. Falls back to a single `(UNSPECIFIED,
+/// BROADCAST)` pair if interfaces can't be enumerated or none report a
+/// broadcast address, so sending still has somewhere to go.
+/// The local, non-loopback IPv4 addresses of this machine's up interfaces,
+/// so a caller like `portal-cli`'s QR code display can show an address a
+/// peer could actually dial instead of `0.0.0.0`. A multi-homed machine may
+/// have more than one.
+pub fn local_ipv4_addresses() -> Vec<Ipv4Addr> {
+    directed_broadcast_interfaces().into_iter().map(|(local, _)| local).collect()
+}
+
+fn directed_broadcast_interfaces() -> Vec<(Ipv4Addr, Ipv4Addr)> {
+    let interfaces: Vec<(Ipv4Addr, Ipv4Addr)> = if_addrs::get_if_addrs()
+        .into_iter()
+        .flatten()
+        .filter(|iface| !iface.is_loopback())
+        .filter_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(v4) => v4.broadcast.map(|broadcast| (v4.ip, broadcast)),
+            if_addrs::IfAddr::V6(_) => None,
+        })
+        .collect();
+    if interfaces.is_empty() {
+        vec![(Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST)]
+    } else {
+        interfaces
+    }
+}
+
+/// Builds a bare probe request packet: `magic + PROBE_REQUEST_MARKER`.
+fn encode_probe_request(magic: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(magic.len() + 1);
+    buf.extend_from_slice(magic);
+    buf.push(PROBE_REQUEST_MARKER);
+    buf
+}
+
+/// Whether `buf` is exactly a probe request built by [`encode_probe_request`]
+/// with the same `magic`.
+fn is_probe_request(magic: &[u8], buf: &[u8]) -> bool {
+    buf.len() == magic.len() + 1 && buf[..magic.len()] == *magic && buf[magic.len()] == PROBE_REQUEST_MARKER
+}
+
+/// Builds the wire payload: `magic + BROADCAST_FORMAT_VERSION + bincode(payload)`.
+fn encode_payload(magic: &[u8], payload: &BroadcastPayload) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(magic);
+    buf.push(BROADCAST_FORMAT_VERSION);
+    bincode::serialize_into(&mut buf, payload).expect("serializing a BroadcastPayload cannot fail");
+    buf
+}
+
+/// Appends an Ed25519 signature over `encoded` (the output of
+/// [`encode_payload`]) computed with `signing_key`, so a listener holding
+/// the matching [`VerifyingKey`] can confirm the packet wasn't forged.
+#[cfg(feature = "signed-broadcast")]
+fn sign_payload(mut encoded: Vec<u8>, signing_key: &SigningKey) -> Vec<u8> {
+    let signature = signing_key.sign(&encoded);
+    encoded.push(SIGNATURE_MARKER);
+    encoded.extend_from_slice(&signature.to_bytes());
+    encoded
+}
+
+/// Checks whether `packet` carries a valid Ed25519 signature over its
+/// [`BroadcastPayload`] from `verifying_key`. `payload` must be the result
+/// of parsing `packet` with [`parse_payload`]; re-encoding it with
+/// [`encode_payload`] recovers exactly the bytes [`sign_payload`] signed,
+/// since [`BroadcastPayload`]'s bincode encoding is deterministic.
+#[cfg(feature = "signed-broadcast")]
+fn packet_signature_is_valid(
+    magic: &[u8],
+    packet: &[u8],
+    payload: &BroadcastPayload,
+    verifying_key: &VerifyingKey,
+) -> bool {
+    let signed_len = encode_payload(magic, payload).len();
+    let Some(marker_and_signature) = packet.get(signed_len..) else {
+        return false;
+    };
+    let (Some(&marker), Some(signature_bytes)) =
+        (marker_and_signature.first(), marker_and_signature.get(1..))
+    else {
+        return false;
+    };
+    if marker != SIGNATURE_MARKER {
+        return false;
+    }
+    let Ok(signature_bytes): Result<[u8; SIGNATURE_LEN], _> = signature_bytes.try_into() else {
+        return false;
+    };
+    let signature = Signature::from_bytes(&signature_bytes);
+    verifying_key.verify(&packet[..signed_len], &signature).is_ok()
+}
+
+/// Parses a received packet, returning `None` if it doesn't match `magic`,
+/// was encoded with a [`BROADCAST_FORMAT_VERSION`] this build doesn't know
+/// how to read, or fails to decode (e.g. truncated mid-transmission).
+fn parse_payload(magic: &[u8], buf: &[u8]) -> Option<BroadcastPayload> {
+    let version_offset = magic.len();
+    let fields_offset = version_offset + 1;
+    if buf.len() < fields_offset || buf[..magic.len()] != *magic {
+        return None;
+    }
+    if buf[version_offset] != BROADCAST_FORMAT_VERSION {
+        return None;
+    }
+    bincode::deserialize(&buf[fields_offset..]).ok()
+}
+
+/// A device discovered via [`Listener::scan_device`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    /// Where the broadcast was last received from. Not a stable identity by
+    /// itself (a DHCP lease renewal can hand the same address to a different
+    /// device), so callers that need to recognize a device across scans
+    /// should key off the [`DeviceId`] this is stored under instead.
+    pub addr: SocketAddr,
+    /// The port the device's [`crate::slave::SlaveService`] is listening on
+    /// for file transfers; dial `(addr.ip(), service_port)`, not `addr`
+    /// itself, since `addr`'s port is just wherever the broadcast or probe
+    /// reply happened to originate from.
+    pub service_port: u16,
+    /// E.g. `"macos"`, `"linux"`, `"windows"`.
+    pub platform: String,
+    /// The sender's portal version, e.g. `"0.1.0"`.
+    pub version: String,
+    /// Empty if the sender's hostname couldn't be determined.
+    pub hostname: String,
+    /// When this device's broadcast was last received. [`Listener::scan_device`]
+    /// drops entries that haven't been refreshed within [`DEVICE_TTL`], so a
+    /// device that's left the network eventually disappears instead of
+    /// lingering in [`Listener::scanned_devices`] forever.
+    pub last_seen: Instant,
+}
+
+/// A predicate applied to a newly-received [`DiscoveredDevice`] before
+/// [`Listener::scan_device`] keeps it in [`Listener::scanned_devices`], so a
+/// deployment on a large network only accumulates devices it actually cares
+/// about instead of every device broadcasting nearby. Added via
+/// [`Listener::add_filter`]; a device is kept only if it matches every
+/// filter added this way. Doesn't affect [`Listener::set_probe_reply`] —
+/// a probing peer is still answered regardless of filters.
+pub enum DeviceFilter {
+    /// Only keeps a device whose address falls within the IPv4 subnet
+    /// `network/prefix_len` (e.g. `(Ipv4Addr::new(192, 168, 1, 0), 24)`).
+    /// Always rejects a device seen over IPv6.
+    Subnet(Ipv4Addr, u32),
+    /// Only keeps a device whose hostname matches `pattern`, which may
+    /// contain `*` as a wildcard for any run of characters (e.g.
+    /// `"alice-*"` matches `"alice-macbook"`).
+    HostnamePattern(String),
+    /// Only keeps a device advertising exactly `version`.
+    Version(String),
+}
+
+impl DeviceFilter {
+    fn matches(&self, device: &DiscoveredDevice) -> bool {
+        match self {
+            DeviceFilter::Subnet(network, prefix_len) => match device.addr.ip() {
+                IpAddr::V4(ip) => ipv4_in_subnet(ip, *network, *prefix_len),
+                IpAddr::V6(_) => false,
+            },
+            DeviceFilter::HostnamePattern(pattern) => glob_match(pattern, &device.hostname),
+            DeviceFilter::Version(version) => device.version == *version,
+        }
+    }
+}
+
+/// Whether `ip` falls within `network/prefix_len`. A `prefix_len` over 32
+/// never matches, rather than panicking on the out-of-range shift.
+fn ipv4_in_subnet(ip: Ipv4Addr, network: Ipv4Addr, prefix_len: u32) -> bool {
+    if prefix_len > 32 {
+        return false;
+    }
+    let mask = (!0u32).checked_shl(32 - prefix_len).unwrap_or(0);
+    u32::from(ip) & mask == u32::from(network) & mask
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of
+/// characters (including none) and every other character must match
+/// literally. A small hand-rolled matcher rather than a dependency on a
+/// full glob or regex crate, since [`DeviceFilter::HostnamePattern`] only
+/// needs a single wildcard.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern.first() {
+            None => text.is_empty(),
+            Some(b'*') => matches(&pattern[1..], text) || (!text.is_empty() && matches(pattern, &text[1..])),
+            Some(&expected) => text.first() == Some(&expected) && matches(&pattern[1..], &text[1..]),
+        }
+    }
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// How long [`Listener::scan_device`] waits for a packet before giving up,
+/// so a caller with nothing arriving doesn't block forever.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a discovered device is kept without hearing from it again before
+/// it's expired, a few multiples of how often [`Sender::send_once`] is
+/// expected to be called, so one or two missed broadcasts don't make a still
+/// reachable device disappear.
+const DEVICE_TTL: Duration = Duration::from_secs(90);
+
+/// Listens for broadcast packets and accumulates the devices seen so far,
+/// keyed by [`DeviceId`] so the same device is recognized across scans even
+/// if its address changes between them. Built on [`tokio::net::UdpSocket`]
+/// so scanning doesn't block the async runtime it's awaited on.
+pub struct Listener {
+    socket: AsyncUdpSocket,
+    pub scanned_devices: HashMap<DeviceId, DiscoveredDevice>,
+    device_ttl: Duration,
+    /// Devices [`Self::scan_device`] requires a valid signature from before
+    /// accepting their advertisement; see [`Self::trust_signing_key`].
+    #[cfg(feature = "signed-broadcast")]
+    trusted_keys: HashMap<DeviceId, VerifyingKey>,
+    /// This device's own announcement, sent directly back to whoever
+    /// [`Self::scan_device`] just heard from when set; see
+    /// [`Self::set_probe_reply`].
+    probe_reply: Option<(u16, DeviceId)>,
+    /// Prefix a received packet must start with; see [`Self::with_namespace`].
+    magic: Vec<u8>,
+    /// A discovered device is only kept in [`Self::scanned_devices`] if it
+    /// matches every one of these; see [`Self::add_filter`].
+    filters: Vec<DeviceFilter>,
+    /// Notified with every newly-recorded device; see
+    /// [`Self::notify_on_discover`].
+    on_discovered: Option<mpsc::UnboundedSender<DiscoveredDevice>>,
+}
+
+impl Listener {
+    /// Binds a plain UDP socket for IPv4 broadcast discovery, e.g.
+    /// `"0.0.0.0:3000"`.
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        let socket = AsyncUdpSocket::bind(addr).await?;
+        Self::with_socket(socket)
+    }
+
+    /// Binds an IPv6 socket on `port` and joins [`MULTICAST_GROUP_V6`] on the
+    /// interface `interface_index` identifies (`0` lets the OS pick the
+    /// default), so it receives [`Sender::new_v6`]'s announcements.
+    /// [`Self::scan_device`] works identically afterwards — it doesn't care
+    /// which family delivered a given packet.
+    pub async fn bind_multicast_v6(port: u16, interface_index: u32) -> io::Result<Self> {
+        let socket = AsyncUdpSocket::bind((Ipv6Addr::UNSPECIFIED, port)).await?;
+        socket.join_multicast_v6(&MULTICAST_GROUP_V6, interface_index)?;
+        Self::with_socket(socket)
+    }
+
+    fn with_socket(socket: AsyncUdpSocket) -> io::Result<Self> {
+        Ok(Self {
+            socket,
+            scanned_devices: HashMap::new(),
+            device_ttl: DEVICE_TTL,
+            #[cfg(feature = "signed-broadcast")]
+            trusted_keys: HashMap::new(),
+            probe_reply: None,
+            magic: MAGIC.to_vec(),
+            filters: Vec::new(),
+            on_discovered: None,
+        })
+    }
+
+    /// Overrides how long a device is kept without hearing from it again
+    /// before [`Self::scan_device`] expires it; defaults to [`DEVICE_TTL`].
+    pub fn set_device_ttl(&mut self, ttl: Duration) {
+        self.device_ttl = ttl;
+    }
+
+    /// From now on, only recognizes packets scoped to `namespace` by a
+    /// matching [`Sender::with_namespace`] or [`Prober::with_namespace`];
+    /// see [`namespace_magic`]. Call before [`Self::scan_device`] or
+    /// [`Self::discover`] — changing it mid-scan would make an
+    /// already-expected reply on the old namespace go unrecognized.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.magic = namespace_magic(namespace);
+        self
+    }
+
+    /// From now on, [`Self::scan_device`] also announces this device —
+    /// identified by `service_port` and `device_id` — directly back to
+    /// whoever it just heard from, whether that was a [`Prober`] unicasting
+    /// an announcement on a network where broadcast or multicast delivery is
+    /// filtered, or a bare [`Sender::send_probe_request`] probe asking
+    /// "who's there?" over broadcast or multicast. Off by default, since a
+    /// passive listener otherwise has no reason to announce itself back to
+    /// every broadcaster or prober it overhears.
+    pub fn set_probe_reply(&mut self, service_port: u16, device_id: DeviceId) {
+        self.probe_reply = Some((service_port, device_id));
+    }
+
+    /// From now on, [`Self::scan_device`] only keeps a device in
+    /// [`Self::scanned_devices`] if it matches `filter`, in addition to any
+    /// filter already added — so, for example, adding a subnet filter and a
+    /// version filter only keeps devices matching both. No filters are
+    /// added by default, so every discovered device is kept.
+    pub fn add_filter(&mut self, filter: DeviceFilter) {
+        self.filters.push(filter);
+    }
+
+    /// From now on, every time [`Self::scan_device`] records a device (one
+    /// passing every [`DeviceFilter`] added via [`Self::add_filter`]), it
+    /// also sends a clone of it down `sender` — so a caller like
+    /// `portal-cli`'s `Manager`, watching a [`ScanHandle`] from outside the
+    /// background scan task, can print devices as they're found instead of
+    /// only seeing them once a scan window ends. Dropping the receiving end
+    /// just makes this a no-op on the next send, the same as never calling
+    /// this.
+    pub fn notify_on_discover(&mut self, sender: mpsc::UnboundedSender<DiscoveredDevice>) {
+        self.on_discovered = Some(sender);
+    }
+
+    /// From now on, [`Self::scan_device`] only accepts an advertisement
+    /// claiming `device_id` if it's signed with the matching
+    /// `verifying_key` (see [`Sender::with_signing_key`]), rejecting an
+    /// unsigned or wrongly-signed packet as a probable spoof rather than
+    /// recording it. Devices not listed here are accepted as before,
+    /// whether or not they happen to carry a signature.
+    #[cfg(feature = "signed-broadcast")]
+    pub fn trust_signing_key(&mut self, device_id: DeviceId, verifying_key: VerifyingKey) {
+        self.trusted_keys.insert(device_id, verifying_key);
+    }
+
+    /// Waits (up to [`SCAN_TIMEOUT`]) for a single broadcast packet and
+    /// records the sender if it decodes as a [`BroadcastPayload`]. Doesn't
+    /// block the runtime while waiting, unlike a blocking socket read.
+    ///
+    /// Also expires any device in [`Self::scanned_devices`] that hasn't been
+    /// heard from within [`DEVICE_TTL`], whether or not this call itself
+    /// discovers anything.
+    pub async fn scan_device(&mut self) -> io::Result<Option<DeviceId>> {
+        let mut buf = [0u8; 256];
+        let result = tokio::time::timeout(SCAN_TIMEOUT, self.socket.recv_from(&mut buf)).await;
+        self.expire_stale_devices();
+        let (len, addr) = result
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for a broadcast packet"))??;
+        if is_probe_request(&self.magic, &buf[..len]) {
+            self.send_probe_reply(addr).await;
+            return Ok(None);
+        }
+        let Some(payload) = parse_payload(&self.magic, &buf[..len]) else {
+            return Ok(None);
+        };
+        let device_id = payload.device_id;
+        #[cfg(feature = "signed-broadcast")]
+        if let Some(verifying_key) = self.trusted_keys.get(&device_id) {
+            if !packet_signature_is_valid(&self.magic, &buf[..len], &payload, verifying_key) {
+                tracing::warn!(%device_id, "rejected a broadcast claiming a trusted device's id without a valid signature");
+                return Ok(None);
+            }
+        }
+        let device = DiscoveredDevice {
+            addr,
+            service_port: payload.service_port,
+            platform: payload.platform,
+            version: payload.version,
+            hostname: payload.hostname,
+            last_seen: Instant::now(),
+        };
+        if !self.filters.iter().all(|filter| filter.matches(&device)) {
+            self.send_probe_reply(addr).await;
+            return Ok(None);
+        }
+        tracing::debug!(
+            peer = %addr,
+            %device_id,
+            platform = %device.platform,
+            version = %device.version,
+            hostname = %device.hostname,
+            "discovered a device via broadcast"
+        );
+        if let Some(sender) = &self.on_discovered {
+            let _ = sender.send(device.clone());
+        }
+        self.scanned_devices.insert(device_id, device);
+        self.send_probe_reply(addr).await;
+        Ok(Some(device_id))
+    }
+
+    /// Announces this device directly to `addr` if [`Self::set_probe_reply`]
+    /// is enabled; a no-op otherwise.
+    async fn send_probe_reply(&self, addr: SocketAddr) {
+        let Some((service_port, reply_device_id)) = self.probe_reply else {
+            return;
+        };
+        let reply = encode_payload(&self.magic, &BroadcastPayload {
+            service_port,
+            device_id: reply_device_id,
+            platform: local_platform(),
+            version: local_version(),
+            hostname: local_hostname(),
+        });
+        // Best-effort: a probing peer that doesn't get a reply just times
+        // out, the same as it would on a network where nothing is
+        // listening at all.
+        if let Err(err) = self.socket.send_to(&reply, addr).await {
+            tracing::debug!(peer = %addr, %err, "failed to send a probe reply");
+        }
+    }
+
+    /// Drops entries from [`Self::scanned_devices`] that haven't been
+    /// refreshed within [`DEVICE_TTL`].
+    fn expire_stale_devices(&mut self) {
+        let device_ttl = self.device_ttl;
+        self.scanned_devices
+            .retain(|_, device| device.last_seen.elapsed() < device_ttl);
+    }
+
+    /// Repeatedly calls [`Self::scan_device`] for `duration`. Stops early,
+    /// before `duration` elapses, if `token` is cancelled, so an embedding
+    /// application can shut scanning down promptly instead of waiting out
+    /// the deadline.
+    #[tracing::instrument(skip(self, token), fields(?duration, devices_found = tracing::field::Empty))]
+    pub async fn async_scan_device(&mut self, duration: Duration, token: CancellationToken) {
+        let deadline = tokio::time::Instant::now() + duration;
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = self.scan_device() => {}
+            }
+            if tokio::time::Instant::now() >= deadline {
+                break;
+            }
+        }
+        tracing::Span::current().record("devices_found", self.scanned_devices.len());
+    }
+
+    /// Turns this listener into a stream that yields a [`DiscoveredDevice`]
+    /// as each broadcast arrives, for a caller that wants to react to
+    /// devices as they show up instead of polling [`Self::scanned_devices`]
+    /// after a fixed scan window. Runs until a non-timeout I/O error occurs;
+    /// a read timeout just keeps the stream waiting for the next packet.
+    pub fn discover(self) -> impl Stream<Item = DiscoveredDevice> {
+        stream::unfold(self, |mut listener| async move {
+            loop {
+                match listener.scan_device().await {
+                    Ok(Some(device_id)) => {
+                        let device = listener.scanned_devices.get(&device_id)?.clone();
+                        return Some((device, listener));
+                    }
+                    Ok(None) => continue,
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(_) => return None,
+                }
+            }
+        })
+    }
+
+    /// Moves this listener onto its own tokio task that keeps calling
+    /// [`Self::scan_device`] until [`ScanHandle::stop`] is called, so a
+    /// caller that can't hold `&mut Listener` across an `.await` — e.g.
+    /// `portal-cli`'s `Manager`, driven one menu command at a time rather
+    /// than from a single long-lived async context — can still scan
+    /// continuously in the background and read back what's been discovered
+    /// through [`ScanHandle::devices`] whenever it needs to.
+    pub fn spawn_scan(mut self, token: CancellationToken) -> ScanHandle {
+        let devices = Arc::new(Mutex::new(HashMap::new()));
+        let task_devices = devices.clone();
+        let task_token = token.clone();
+        let join = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = task_token.cancelled() => break,
+                    result = self.scan_device() => {
+                        if let Err(err) = result {
+                            if err.kind() != io::ErrorKind::TimedOut {
+                                break;
+                            }
+                        }
+                    }
+                }
+                let snapshot = self.scanned_devices.clone();
+                *task_devices.lock().expect("scan task should not panic while holding the lock") = snapshot;
+            }
+        });
+        ScanHandle { devices, token, join }
+    }
+}
+
+/// A background scan started by [`Listener::spawn_scan`]. Unlike
+/// [`Listener::async_scan_device`], which needs `&mut Listener` held live
+/// for as long as the scan runs, this hands the listener off to its own
+/// task entirely and exposes the result through shared, lock-guarded state
+/// instead — the shape a caller that only gets occasional, non-overlapping
+/// turns to run async code (like a menu-driven CLI) actually needs.
+pub struct ScanHandle {
+    devices: Arc<Mutex<HashMap<DeviceId, DiscoveredDevice>>>,
+    token: CancellationToken,
+    join: JoinHandle<()>,
+}
+
+impl ScanHandle {
+    /// A snapshot of the devices discovered so far. Cloned out from behind
+    /// the lock rather than returning a guard, so a caller can hold onto it
+    /// without blocking the background scan from updating it next cycle.
+    pub fn devices(&self) -> HashMap<DeviceId, DiscoveredDevice> {
+        self.devices
+            .lock()
+            .expect("scan task should not panic while holding the lock")
+            .clone()
+    }
+
+    /// Signals the background scan to stop. It may take up to one more
+    /// [`SCAN_TIMEOUT`] for the task to actually notice and exit; call
+    /// [`Self::join`] to wait for that.
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+
+    /// Waits for the background scan task to exit, whether because
+    /// [`Self::stop`] was called or the task panicked.
+    pub async fn join(self) {
+        let _ = self.join.await;
+    }
+}
+
+/// Parses `spec` as either a single IPv4 address (e.g. `"192.168.1.42"`) or
+/// a CIDR range (e.g. `"192.168.1.0/24"`) and returns every host address in
+/// it, excluding the network and broadcast addresses for ranges wider than
+/// a /31. Returns `None` if `spec` doesn't parse, or if the range is wider
+/// than [`MAX_PROBE_RANGE`] hosts, so a mistyped `/0` can't make
+/// [`Prober::probe`] try to unicast to billions of addresses.
+pub fn expand_ipv4_range(spec: &str) -> Option<Vec<Ipv4Addr>> {
+    let Some((addr, prefix_len)) = spec.split_once('/') else {
+        return Some(vec![spec.parse().ok()?]);
+    };
+    let base: Ipv4Addr = addr.parse().ok()?;
+    let prefix_len: u32 = prefix_len.parse().ok()?;
+    if prefix_len > 32 {
+        return None;
+    }
+    let host_bits = 32 - prefix_len;
+    if host_bits > MAX_PROBE_RANGE.ilog2() {
+        return None;
+    }
+    let network = u32::from(base) & (!0u32 << host_bits);
+    let host_count = 1u32 << host_bits;
+    let hosts = if host_bits <= 1 {
+        0..host_count
+    } else {
+        1..host_count - 1
+    };
+    Some(hosts.map(|offset| Ipv4Addr::from(network + offset)).collect())
+}
+
+/// Upper bound on how many addresses [`expand_ipv4_range`] will expand a
+/// CIDR range into.
+const MAX_PROBE_RANGE: u32 = 1 << 16;
+
+/// Actively probes specific addresses with a unicast discovery payload and
+/// collects replies, for networks where broadcast or multicast traffic is
+/// filtered but plain unicast UDP gets through; see [`expand_ipv4_range`]
+/// for specifying the addresses to probe. A [`Listener`] with
+/// [`Listener::set_probe_reply`] enabled is what answers a probe.
+pub struct Prober {
+    socket: AsyncUdpSocket,
+    service_port: u16,
+    device_id: DeviceId,
+    platform: String,
+    version: String,
+    hostname: String,
+    /// Prefix placed at the start of every probe; see [`Self::with_namespace`].
+    magic: Vec<u8>,
+}
+
+impl Prober {
+    /// `device_id` and `service_port` are advertised to probed peers the
+    /// same way [`Sender`] advertises them over broadcast.
+    pub async fn bind(service_port: u16, device_id: DeviceId) -> io::Result<Self> {
+        let socket = AsyncUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        Ok(Self {
+            socket,
+            service_port,
+            device_id,
+            platform: local_platform(),
+            version: local_version(),
+            hostname: local_hostname(),
+            magic: MAGIC.to_vec(),
+        })
+    }
+
+    /// Scopes every probe sent from now on, and every reply accepted by
+    /// [`Self::collect_replies`], to `namespace`; see [`namespace_magic`].
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.magic = namespace_magic(namespace);
+        self
+    }
+
+    /// Sends the discovery payload directly to each of `targets` on
+    /// [`DISCOVERY_PORT`], instead of relying on broadcast or multicast
+    /// delivery to reach them.
+    pub async fn probe(&self, targets: impl IntoIterator<Item = Ipv4Addr>) -> io::Result<()> {
+        self.probe_port(targets, DISCOVERY_PORT).await
+    }
+
+    /// Like [`Self::probe`], but against an explicit port instead of
+    /// [`DISCOVERY_PORT`], so tests can probe a [`Listener`] bound to an
+    /// ephemeral port without contending for the well-known one.
+    async fn probe_port(&self, targets: impl IntoIterator<Item = Ipv4Addr>, port: u16) -> io::Result<()> {
+        let payload = encode_payload(
+            &self.magic,
+            &BroadcastPayload {
+                service_port: self.service_port,
+                device_id: self.device_id,
+                platform: self.platform.clone(),
+                version: self.version.clone(),
+                hostname: self.hostname.clone(),
+            },
+        );
+        for target in targets {
+            self.socket.send_to(&payload, (target, port)).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for replies from probed peers, returning every
+    /// [`DiscoveredDevice`] heard from. Unlike [`Listener::scan_device`],
+    /// a single call collects every reply that arrives within the window
+    /// rather than stopping at the first one, since [`Self::probe`] may
+    /// have reached several peers at once.
+    pub async fn collect_replies(&self, timeout: Duration) -> io::Result<HashMap<DeviceId, DiscoveredDevice>> {
+        let mut devices = HashMap::new();
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut buf = [0u8; 256];
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match tokio::time::timeout(remaining, self.socket.recv_from(&mut buf)).await {
+                Ok(Ok((len, addr))) => {
+                    if let Some(payload) = parse_payload(&self.magic, &buf[..len]) {
+                        devices.insert(
+                            payload.device_id,
+                            DiscoveredDevice {
+                                addr,
+                                service_port: payload.service_port,
+                                platform: payload.platform,
+                                version: payload.version,
+                                hostname: payload.hostname,
+                                last_seen: Instant::now(),
+                            },
+                        );
+                    }
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_) => break,
+            }
+        }
+        Ok(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn sample_payload() -> BroadcastPayload {
+        BroadcastPayload {
+            service_port: 4242,
+            device_id: DeviceId::generate(),
+            platform: "macos".to_string(),
+            version: "0.3.0".to_string(),
+            hostname: "my-laptop".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_device_records_the_sender_by_id_with_its_platform_version_and_hostname() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sender_addr = socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        let scanned = listener.scan_device().await.unwrap().unwrap();
+        assert_eq!(scanned, sent.device_id);
+        let device = listener.scanned_devices.get(&sent.device_id).unwrap();
+        assert_eq!(device.addr, sender_addr);
+        assert_eq!(device.service_port, sent.service_port);
+        assert_eq!(device.platform, sent.platform);
+        assert_eq!(device.version, sent.version);
+        assert_eq!(device.hostname, sent.hostname);
+    }
+
+    #[tokio::test]
+    async fn ignores_packets_that_dont_start_with_the_magic_prefix() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        socket.send_to(b"not a portal packet", listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[test]
+    fn round_trips_through_encode_and_parse() {
+        let sent = sample_payload();
+        assert_eq!(parse_payload(MAGIC, &encode_payload(MAGIC, &sent)), Some(sent));
+    }
+
+    #[test]
+    fn rejects_a_payload_from_an_unknown_format_version() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(BROADCAST_FORMAT_VERSION + 1);
+        bincode::serialize_into(&mut buf, &sample_payload()).unwrap();
+        assert_eq!(parse_payload(MAGIC, &buf), None);
+    }
+
+    #[test]
+    fn rejects_a_packet_too_short_to_carry_a_format_version() {
+        assert_eq!(parse_payload(MAGIC, MAGIC), None);
+    }
+
+    #[test]
+    fn sender_v6_targets_the_multicast_group() {
+        let sender = Sender::new_v6(4242, DeviceId::generate()).unwrap();
+        let destinations: Vec<SocketAddr> = sender.targets.iter().map(|(_, dest)| *dest).collect();
+        assert_eq!(destinations, vec![(MULTICAST_GROUP_V6, DISCOVERY_PORT).into()]);
+        // Joining a multicast group isn't guaranteed to be routable in every
+        // environment this runs in, but sending shouldn't fail regardless.
+        sender.send_once().unwrap();
+    }
+
+    #[test]
+    fn sender_v4_binds_a_socket_per_interface_targeting_its_directed_broadcast_address() {
+        let sender = Sender::new(4242, DeviceId::generate()).unwrap();
+        assert!(!sender.targets.is_empty());
+        assert!(sender.targets.iter().all(|(_, dest)| dest.port() == DISCOVERY_PORT));
+        sender.send_once().unwrap();
+    }
+
+    #[tokio::test]
+    async fn listener_binds_and_joins_the_multicast_group_on_loopback() {
+        // Interface 0 lets the OS pick a default, which is enough to exercise
+        // the join without depending on a specific interface being present.
+        Listener::bind_multicast_v6(0, 0).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_scan_device_stops_once_the_duration_elapses() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let started = tokio::time::Instant::now();
+        listener
+            .async_scan_device(Duration::from_millis(50), CancellationToken::new())
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn async_scan_device_stops_early_when_cancelled() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        let started = tokio::time::Instant::now();
+        listener.async_scan_device(Duration::from_secs(60), token).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn async_send_loop_stops_immediately_when_cancelled() {
+        let sender = Sender::new(4242, DeviceId::generate()).unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        let started = tokio::time::Instant::now();
+        sender
+            .async_send_loop(Duration::from_secs(60), &AtomicUsize::new(0), token)
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn async_send_loop_announces_at_least_once_before_cancelling() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener_addr = socket.local_addr().unwrap();
+        let sender = Sender::with_targets(
+            vec![(UdpSocket::bind("127.0.0.1:0").unwrap(), listener_addr)],
+            4242,
+            DeviceId::generate(),
+        )
+        .unwrap();
+        let token = CancellationToken::new();
+        let token_clone = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token_clone.cancel();
+        });
+        sender
+            .async_send_loop(Duration::from_millis(10), &AtomicUsize::new(0), token)
+            .await;
+
+        let mut buf = [0u8; 256];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        assert!(parse_payload(MAGIC, &buf[..len]).is_some());
+    }
+
+    #[test]
+    fn announce_interval_backs_off_once_a_connection_is_established() {
+        let base = Duration::from_secs(5);
+        assert_eq!(announce_interval(base, 0), base);
+        assert_eq!(announce_interval(base, 1), base * ANNOUNCE_BACKOFF_MULTIPLIER);
+        assert_eq!(announce_interval(base, 3), base * ANNOUNCE_BACKOFF_MULTIPLIER);
+    }
+
+    #[tokio::test]
+    async fn discover_yields_a_device_as_soon_as_its_broadcast_arrives() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        let mut devices = Box::pin(listener.discover());
+        let discovered = devices.next().await.unwrap();
+        assert_eq!(discovered.platform, sent.platform);
+        assert_eq!(discovered.version, sent.version);
+        assert_eq!(discovered.hostname, sent.hostname);
+    }
+
+    #[tokio::test]
+    async fn notify_on_discover_sends_every_newly_recorded_device() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        listener.notify_on_discover(tx);
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        listener.scan_device().await.unwrap();
+
+        let notified = rx.try_recv().unwrap();
+        assert_eq!(notified.platform, sent.platform);
+    }
+
+    #[tokio::test]
+    async fn notify_on_discover_is_not_sent_for_a_device_dropped_by_a_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        listener.add_filter(DeviceFilter::Version("9.9.9".to_string()));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        listener.notify_on_discover(tx);
+        let listener_addr = listener.socket.local_addr().unwrap();
+        socket.send_to(&encode_payload(MAGIC, &sample_payload()), listener_addr).unwrap();
+
+        listener.scan_device().await.unwrap();
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn spawn_scan_records_a_device_without_holding_the_listener_in_the_caller() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+
+        let handle = listener.spawn_scan(CancellationToken::new());
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        let device = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(device) = handle.devices().get(&sent.device_id).cloned() {
+                    return device;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(device.platform, sent.platform);
+
+        handle.stop();
+        handle.join().await;
+    }
+
+    #[tokio::test]
+    async fn spawn_scan_stops_once_told_to() {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let handle = listener.spawn_scan(CancellationToken::new());
+        handle.stop();
+        tokio::time::timeout(Duration::from_secs(5), handle.join()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expires_a_device_that_hasnt_broadcast_within_its_ttl() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        listener.set_device_ttl(Duration::from_millis(10));
+        let listener_addr = listener.socket.local_addr().unwrap();
+        socket.send_to(&encode_payload(MAGIC, &sample_payload()), listener_addr).unwrap();
+        listener.scan_device().await.unwrap();
+        assert_eq!(listener.scanned_devices.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // A second, unrelated packet drives another expiry sweep without
+        // needing a real device to refresh.
+        socket.send_to(b"not a portal packet", listener_addr).unwrap();
+        listener.scan_device().await.unwrap();
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[cfg(feature = "signed-broadcast")]
+    #[test]
+    fn a_correctly_signed_payload_verifies_against_its_own_key() {
+        let signing_key = generate_signing_key();
+        let payload = sample_payload();
+        let packet = sign_payload(encode_payload(MAGIC, &payload), &signing_key);
+        assert!(packet_signature_is_valid(MAGIC, &packet, &payload, &signing_key.verifying_key()));
+    }
+
+    #[cfg(feature = "signed-broadcast")]
+    #[test]
+    fn a_signature_from_a_different_key_does_not_verify() {
+        let payload = sample_payload();
+        let packet = sign_payload(encode_payload(MAGIC, &payload), &generate_signing_key());
+        let other_key = generate_signing_key();
+        assert!(!packet_signature_is_valid(MAGIC, &packet, &payload, &other_key.verifying_key()));
+    }
+
+    #[cfg(feature = "signed-broadcast")]
+    #[test]
+    fn an_unsigned_payload_does_not_verify() {
+        let payload = sample_payload();
+        let packet = encode_payload(MAGIC, &payload);
+        assert!(!packet_signature_is_valid(MAGIC, &packet, &payload, &generate_signing_key().verifying_key()));
+    }
+
+    #[cfg(feature = "signed-broadcast")]
+    #[tokio::test]
+    async fn scan_device_rejects_a_trusted_devices_id_without_a_valid_signature() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        listener.trust_signing_key(sent.device_id, generate_signing_key().verifying_key());
+
+        // Sent unsigned, as an attacker spoofing `sent.device_id` would, not
+        // holding the real device's private key.
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[cfg(feature = "signed-broadcast")]
+    #[tokio::test]
+    async fn scan_device_accepts_a_trusted_devices_id_with_a_valid_signature() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        let signing_key = generate_signing_key();
+        listener.trust_signing_key(sent.device_id, signing_key.verifying_key());
+
+        let packet = sign_payload(encode_payload(MAGIC, &sent), &signing_key);
+        socket.send_to(&packet, listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), Some(sent.device_id));
+        assert!(listener.scanned_devices.contains_key(&sent.device_id));
+    }
+
+    #[test]
+    fn expands_a_single_address() {
+        assert_eq!(
+            expand_ipv4_range("192.168.1.42").unwrap(),
+            vec!["192.168.1.42".parse::<Ipv4Addr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn expands_a_cidr_range_excluding_network_and_broadcast_addresses() {
+        let hosts = expand_ipv4_range("192.168.1.0/30").unwrap();
+        assert_eq!(
+            hosts,
+            vec![
+                "192.168.1.1".parse::<Ipv4Addr>().unwrap(),
+                "192.168.1.2".parse::<Ipv4Addr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_a_slash_32_to_just_that_address() {
+        assert_eq!(
+            expand_ipv4_range("10.0.0.5/32").unwrap(),
+            vec!["10.0.0.5".parse::<Ipv4Addr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_a_range_wider_than_the_probe_limit() {
+        assert_eq!(expand_ipv4_range("10.0.0.0/8"), None);
+    }
+
+    #[test]
+    fn rejects_garbage_input() {
+        assert_eq!(expand_ipv4_range("not an address"), None);
+        assert_eq!(expand_ipv4_range("10.0.0.0/not-a-prefix"), None);
+    }
+
+    #[tokio::test]
+    async fn prober_collects_a_reply_from_a_listener_in_probe_reply_mode() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let listener_device_id = DeviceId::generate();
+        listener.set_probe_reply(9000, listener_device_id);
+        let accept = tokio::spawn(async move {
+            listener.scan_device().await.unwrap();
+        });
+
+        let prober = Prober::bind(4242, DeviceId::generate()).await.unwrap();
+        let Some(listener_ip) = (match listener_addr.ip() {
+            std::net::IpAddr::V4(ip) => Some(ip),
+            std::net::IpAddr::V6(_) => None,
+        }) else {
+            panic!("expected an IPv4 listener address");
+        };
+        prober.probe_port(std::iter::once(listener_ip), listener_addr.port()).await.unwrap();
+
+        let replies = prober.collect_replies(Duration::from_secs(5)).await.unwrap();
+        accept.await.unwrap();
+        let reply = replies.get(&listener_device_id).unwrap();
+        assert_eq!(reply.platform, local_platform());
+    }
+
+    #[test]
+    fn round_trips_a_probe_request_through_encode_and_is_probe_request() {
+        assert!(is_probe_request(MAGIC, &encode_probe_request(MAGIC)));
+        assert!(!is_probe_request(MAGIC, &encode_payload(MAGIC, &sample_payload())));
+        assert!(!is_probe_request(MAGIC, b"not a portal packet"));
+    }
+
+    #[tokio::test]
+    async fn scan_device_answers_a_bare_probe_request_without_recording_a_device() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let listener_device_id = DeviceId::generate();
+        listener.set_probe_reply(9000, listener_device_id);
+        let accept = tokio::spawn(async move {
+            listener.scan_device().await.unwrap();
+            listener
+        });
+
+        let requester = AsyncUdpSocket::bind("127.0.0.1:0").await.unwrap();
+        requester.send_to(&encode_probe_request(MAGIC), listener_addr).await.unwrap();
+
+        let mut buf = [0u8; 256];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), requester.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let reply = parse_payload(MAGIC, &buf[..len]).unwrap();
+        assert_eq!(reply.device_id, listener_device_id);
+
+        let listener = accept.await.unwrap();
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scan_device_ignores_a_probe_request_when_probe_reply_is_disabled() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let listener_addr = listener.socket.local_addr().unwrap();
+
+        let requester = AsyncUdpSocket::bind("127.0.0.1:0").await.unwrap();
+        requester.send_to(&encode_probe_request(MAGIC), listener_addr).await.unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+
+        let mut buf = [0u8; 256];
+        let received = tokio::time::timeout(Duration::from_millis(100), requester.recv_from(&mut buf)).await;
+        assert!(received.is_err(), "expected no reply when probe_reply is disabled");
+    }
+
+    #[test]
+    fn sender_send_probe_request_reaches_every_target() {
+        let sender = Sender::new(4242, DeviceId::generate()).unwrap();
+        sender.send_probe_request().unwrap();
+    }
+
+    #[test]
+    fn an_empty_namespace_reproduces_the_default_magic() {
+        assert_eq!(namespace_magic(""), MAGIC.to_vec());
+    }
+
+    #[test]
+    fn different_namespaces_derive_different_magic() {
+        assert_ne!(namespace_magic("team-a"), namespace_magic("team-b"));
+        assert_ne!(namespace_magic("team-a"), MAGIC.to_vec());
+    }
+
+    #[test]
+    fn a_namespace_derives_the_same_magic_every_time() {
+        assert_eq!(namespace_magic("team-a"), namespace_magic("team-a"));
+    }
+
+    #[tokio::test]
+    async fn a_listener_in_one_namespace_ignores_a_sender_in_another() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap().with_namespace("team-a");
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket
+            .send_to(&encode_payload(&namespace_magic("team-b"), &sent), listener_addr)
+            .unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[test]
+    fn glob_match_requires_an_exact_match_without_a_wildcard() {
+        assert!(glob_match("alice-macbook", "alice-macbook"));
+        assert!(!glob_match("alice-macbook", "bob-macbook"));
+    }
+
+    #[test]
+    fn glob_match_lets_a_star_match_any_run_of_characters() {
+        assert!(glob_match("alice-*", "alice-macbook"));
+        assert!(glob_match("alice-*", "alice-"));
+        assert!(glob_match("*-macbook", "alice-macbook"));
+        assert!(glob_match("*", "anything"));
+        assert!(!glob_match("alice-*", "bob-macbook"));
+    }
+
+    #[test]
+    fn ipv4_in_subnet_checks_the_masked_network_matches() {
+        let network = "192.168.1.0".parse().unwrap();
+        assert!(ipv4_in_subnet("192.168.1.42".parse().unwrap(), network, 24));
+        assert!(!ipv4_in_subnet("192.168.2.42".parse().unwrap(), network, 24));
+        assert!(ipv4_in_subnet("10.0.0.1".parse().unwrap(), "0.0.0.0".parse().unwrap(), 0));
+    }
+
+    #[tokio::test]
+    async fn scan_device_drops_a_device_that_fails_a_subnet_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        listener.add_filter(DeviceFilter::Subnet("10.0.0.0".parse().unwrap(), 8));
+        let listener_addr = listener.socket.local_addr().unwrap();
+        socket.send_to(&encode_payload(MAGIC, &sample_payload()), listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scan_device_keeps_a_device_matching_every_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        listener.add_filter(DeviceFilter::Subnet("127.0.0.0".parse().unwrap(), 8));
+        listener.add_filter(DeviceFilter::HostnamePattern("my-*".to_string()));
+        listener.add_filter(DeviceFilter::Version("0.3.0".to_string()));
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket.send_to(&encode_payload(MAGIC, &sent), listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), Some(sent.device_id));
+    }
+
+    #[tokio::test]
+    async fn scan_device_drops_a_device_that_fails_a_version_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        listener.add_filter(DeviceFilter::Version("9.9.9".to_string()));
+        let listener_addr = listener.socket.local_addr().unwrap();
+        socket.send_to(&encode_payload(MAGIC, &sample_payload()), listener_addr).unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), None);
+        assert!(listener.scanned_devices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_listener_and_sender_sharing_a_namespace_discover_each_other() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap().with_namespace("team-a");
+        let listener_addr = listener.socket.local_addr().unwrap();
+        let sent = sample_payload();
+        socket
+            .send_to(&encode_payload(&namespace_magic("team-a"), &sent), listener_addr)
+            .unwrap();
+
+        assert_eq!(listener.scan_device().await.unwrap(), Some(sent.device_id));
+    }
+}
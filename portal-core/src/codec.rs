@@ -0,0 +1,343 @@
+//! Wire format for the portal protocol.
+//!
+//! Frames are length-delimited and carry a [`bincode`]-encoded [`MasterRequest`]
+//! or [`SlaveResponse`]. The content size bounds how much file data a single
+//! [`FileFragment`] may carry; larger files are simply split into more
+//! fragments. It defaults to [`DEFAULT_MAX_CONTENT_SIZE`] but is negotiated
+//! during [`MasterRequest::Hello`] / [`SlaveResponse::Hello`] down to the
+//! smaller of what each side is willing to use; see
+//! [`crate::master::MasterBuilder::max_content_size`] and
+//! [`crate::slave::Slave::set_max_content_size`].
+
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+use crate::error::Error;
+
+/// Historically sized to stay well under a 1500-byte Ethernet MTU. Either
+/// side may ask for something larger; see the module docs.
+pub const DEFAULT_MAX_CONTENT_SIZE: usize = 1498;
+
+/// Start of the numeric range reserved for embedder-defined custom requests.
+/// Kinds below this value are used by the built-in protocol and must never be
+/// registered by embedding applications.
+pub const CUSTOM_KIND_MIN: u16 = 0xF000;
+
+/// Version of the wire protocol spoken by this crate. Bumped whenever a
+/// breaking change is made to [`MasterRequest`] or [`SlaveResponse`].
+pub const PROTOCOL_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub file_name: String,
+    /// Identifies the fragments and `EndOfFile` belonging to this file,
+    /// allocated by the sender for the lifetime of one transfer. Wide enough,
+    /// and distinct enough from the content hash, that two files in flight at
+    /// once can't collide and corrupt each other the way deriving it from
+    /// `file_hash`'s first byte once could.
+    pub file_id: u32,
+    /// `None` if the content hash isn't known yet, i.e. a streamed send
+    /// still in progress. The slave expects a trailing
+    /// [`MasterRequest::FileHash`] before `EndOfFile` in that case.
+    pub file_hash: Option<[u8; 32]>,
+    /// Whether fragment data for this file is zstd-compressed; see the
+    /// `compression` feature. The slave decompresses before hashing and
+    /// saving.
+    pub compressed: bool,
+    /// Modification time of the source file, as seconds since the Unix
+    /// epoch, if it could be read. The slave applies this after writing.
+    pub modified: Option<i64>,
+    /// Unix permission bits (e.g. `0o644`) of the source file, if it could
+    /// be read. Ignored by slaves running on non-Unix platforms.
+    pub unix_mode: Option<u32>,
+    /// Size of the file in bytes, used for a disk-space preflight check
+    /// before the slave accepts any fragments; see
+    /// [`SlaveResponse::InsufficientSpace`]. `0` for a streamed send (see
+    /// [`crate::master::Master::send_reader`]) whose size isn't known
+    /// upfront, in which case the slave skips the check.
+    pub file_size: u64,
+    /// The sender's negotiated content size at the time this file's
+    /// fragments were chunked, i.e. every [`FileFragment::index`] for this
+    /// file lands at byte offset `index * fragment_size` except the last,
+    /// which may be shorter. Lets the slave seek straight to a fragment's
+    /// place in the part file instead of having to receive every earlier
+    /// one first; see [`crate::slave::Slave`].
+    pub fragment_size: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileFragment {
+    pub file_id: u32,
+    pub index: u32,
+    /// A [`bytes::Bytes`] rather than `Vec<u8>` so that slicing off a chunk
+    /// of an in-memory file (see [`crate::master::Master::send_reader`] and
+    /// `do_send_a_file_resuming`) is a cheap, reference-counted view instead
+    /// of a fresh allocation and copy.
+    pub data: Bytes,
+    /// Whether `data` is LZ4-compressed; see the `lz4` feature and
+    /// [`crate::master::MasterBuilder::lz4_fragments`]. Independent of
+    /// whole-file `compression` negotiation.
+    pub compressed: bool,
+}
+
+/// A symlink encountered under [`crate::master::Master::send_directory`],
+/// sent when [`crate::master::SymlinkPolicy::Recreate`] is in effect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymlinkEntry {
+    /// Path relative to the transfer root, using `/` as the separator.
+    pub path: String,
+    /// The link's target, exactly as read from the source filesystem.
+    pub target: String,
+}
+
+/// Requests sent from a [`crate::master::Master`] to a [`crate::slave::Slave`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MasterRequest {
+    /// Sent once, immediately after connecting, to negotiate compatibility
+    /// before any other request is exchanged. `max_content_size` is the
+    /// largest fragment content size the master is willing to send; see the
+    /// module docs. `device_id` identifies this installation regardless of
+    /// which address it dialed from; see [`crate::identity::DeviceId`].
+    Hello {
+        protocol_version: u16,
+        features: Vec<String>,
+        max_content_size: u32,
+        device_id: crate::identity::DeviceId,
+    },
+    /// A short text snippet or clipboard payload, sent without creating a
+    /// file; see [`crate::slave::Slave::on_text`].
+    Text { content: String },
+    FileMetadata(FileMetadata),
+    FileFragment(FileFragment),
+    /// Supplies the content hash for a file whose [`FileMetadata`] was sent
+    /// with `file_hash: None`, i.e. a streamed send; see
+    /// [`crate::master::Master::send_reader`]. Sent once, after the last
+    /// fragment and before `EndOfFile`.
+    FileHash { file_id: u32, file_hash: [u8; 32] },
+    EndOfFile { file_id: u32 },
+    /// Recreates a symlink encountered during a directory transfer; see
+    /// [`crate::master::SymlinkPolicy::Recreate`].
+    Symlink(SymlinkEntry),
+    /// Part of the challenge-response exchange guarding a paired slave; see
+    /// [`crate::slave::Slave::set_pairing_key`] and
+    /// [`crate::master::Master::authenticate`]. `None` asks the slave for a
+    /// fresh nonce (replied to with [`SlaveResponse::AuthRequired`]); `Some`
+    /// carries `HMAC-SHA256(key, nonce)` over the most recently issued one.
+    Auth { proof: Option<[u8; 32]> },
+    /// Asks the slave which fragment indices it already holds for `file_hash`,
+    /// so a master reconnecting after a dropped connection can resume instead
+    /// of resending a file from scratch.
+    QueryResumeState { file_hash: [u8; 32] },
+    Ping,
+    /// An embedder-defined request. `kind` must fall within
+    /// [`CUSTOM_KIND_MIN`]..=`u16::MAX`; see [`crate::slave::Slave::register_handler`].
+    Custom { kind: u16, payload: Vec<u8> },
+}
+
+/// Responses sent from a [`crate::slave::Slave`] back to a [`crate::master::Master`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SlaveResponse {
+    /// Reply to [`MasterRequest::Hello`] when the protocol versions match.
+    /// `max_content_size` is the largest fragment content size the slave is
+    /// willing to accept; see the module docs. `device_id` identifies this
+    /// installation regardless of which address accepted the connection;
+    /// see [`crate::identity::DeviceId`].
+    Hello {
+        protocol_version: u16,
+        features: Vec<String>,
+        max_content_size: u32,
+        device_id: crate::identity::DeviceId,
+    },
+    /// Reply to [`MasterRequest::Hello`] when the protocol versions differ;
+    /// the master should surface this as [`crate::error::Error::IncompatibleProtocol`].
+    IncompatibleProtocol { slave_version: u16, master_version: u16 },
+    Ok,
+    FileIdNotFound { file_id: u32 },
+    CannotSaveFile { file_id: u32 },
+    /// Reply to `EndOfFile` when `FileMetadata::file_name` sanitizes down to
+    /// nothing, e.g. it was only `..`, `/`, or similarly empty of real path
+    /// components once traversal and root segments are stripped.
+    InvalidFileName { file_id: u32 },
+    /// Reply to `EndOfFile` under [`crate::slave::CollisionPolicy::Fail`]
+    /// when the destination already exists.
+    FileExists { file_id: u32 },
+    /// Reply to [`MasterRequest::FileMetadata`] when the embedding
+    /// application's accept/reject hook declined the transfer; see
+    /// [`crate::slave::Slave::on_incoming_file`].
+    Rejected { file_id: u32 },
+    ChecksumNotMatched { file_id: u32 },
+    /// Reply to [`MasterRequest::Symlink`] when the link couldn't be
+    /// materialized (unsupported platform, bad path, or an I/O error).
+    SymlinkFailed,
+    /// Reply to [`MasterRequest::QueryResumeState`]: the fragment indices
+    /// already held for that file, or an empty list if nothing is retained.
+    ResumeState { have_indices: Vec<u32> },
+    /// Reply to `EndOfFile` when some fragments in between never arrived:
+    /// the missing indices, so the master can resend just those and ask the
+    /// slave to finalize again instead of restarting the whole transfer.
+    MissingFragments { file_id: u32, indices: Vec<u32> },
+    /// Reply to [`MasterRequest::FileMetadata`] when the destination
+    /// filesystem doesn't have enough free space for `required` bytes; the
+    /// master should surface this instead of sending fragments doomed to
+    /// fail partway through.
+    InsufficientSpace { file_id: u32, required: u64, available: u64 },
+    /// Reply to [`MasterRequest::FileMetadata`] when `FileMetadata::file_size`
+    /// exceeds [`crate::slave::Slave::set_max_file_size`].
+    FileTooLarge { file_id: u32, max_file_size: u64 },
+    /// Reply to [`MasterRequest::FileMetadata`] when the file's extension is
+    /// rejected by [`crate::slave::Slave::set_file_type_filter`].
+    FileTypeNotAllowed { file_id: u32 },
+    /// The slave requires pairing before accepting this request. `nonce` is
+    /// single-use: send it back as `HMAC-SHA256(key, nonce)` in
+    /// [`MasterRequest::Auth`] to prove the key without the key or a replay
+    /// of a past proof ever crossing the wire.
+    AuthRequired { nonce: [u8; 32] },
+    /// The proof sent with [`MasterRequest::Auth`] didn't match the nonce
+    /// most recently issued in [`SlaveResponse::AuthRequired`].
+    AuthFailed,
+    /// A [`crate::slave::SlaveService`] connection, file, or bandwidth limit
+    /// is currently saturated; see [`crate::slave::SlaveService::set_max_connections`],
+    /// [`crate::slave::SlaveService::set_max_concurrent_files`], and
+    /// [`crate::slave::SlaveService::set_max_inbound_bytes_per_sec`]. The
+    /// master should back off and retry rather than treat this as fatal.
+    Busy,
+    /// Acknowledges a [`MasterRequest::FileFragment`] in place of a plain
+    /// `Ok`, every so often, with the file's true received-bytes total
+    /// rather than however much the master has written to the socket; see
+    /// [`crate::slave::Slave::set_progress_interval`]. The master should
+    /// treat this the same as `Ok` for the purpose of advancing its
+    /// in-flight window.
+    Progress { file_id: u32, bytes_received: u64 },
+    Pong,
+    Custom { kind: u16, payload: Vec<u8> },
+}
+
+/// A bincode-over-length-delimited codec, generic over the item type so the
+/// same framing logic backs both directions of the protocol.
+pub struct MessageCodec<E, D> {
+    inner: LengthDelimitedCodec,
+    _encode: PhantomData<E>,
+    _decode: PhantomData<D>,
+}
+
+impl<E, D> Default for MessageCodec<E, D> {
+    fn default() -> Self {
+        Self {
+            inner: LengthDelimitedCodec::new(),
+            _encode: PhantomData,
+            _decode: PhantomData,
+        }
+    }
+}
+
+impl<E, D> MessageCodec<E, D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<E, D> Encoder<E> for MessageCodec<E, D>
+where
+    E: Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: E, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let payload = bincode::serialize(&item)?;
+        let crc = crc32fast::hash(&payload);
+
+        let mut buf = BytesMut::with_capacity(4 + payload.len());
+        buf.put_u32(crc);
+        buf.put_slice(&payload);
+        self.inner.encode(buf.freeze(), dst).map_err(Error::Io)
+    }
+}
+
+impl<E, D> Decoder for MessageCodec<E, D>
+where
+    D: DeserializeOwned,
+{
+    type Item = D;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(mut frame) = self.inner.decode(src)? else {
+            return Ok(None);
+        };
+        let expected = frame.get_u32();
+        let payload = frame.chunk();
+
+        let actual = crc32fast::hash(payload);
+        if actual != expected {
+            return Err(Error::CrcMismatch { expected, actual });
+        }
+
+        let item = bincode::deserialize(payload)?;
+        frame.advance(frame.len());
+        Ok(Some(item))
+    }
+}
+
+/// Capabilities this build advertises during [`MasterRequest::Hello`] /
+/// [`SlaveResponse::Hello`] negotiation. A peer only uses a capability if
+/// both sides advertise it.
+#[cfg(feature = "compression")]
+pub(crate) fn local_features() -> Vec<String> {
+    vec!["zstd".to_string()]
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn local_features() -> Vec<String> {
+    Vec::new()
+}
+
+/// Codec used by a [`crate::master::Master`]: encodes requests, decodes responses.
+pub type MasterCodec = MessageCodec<MasterRequest, SlaveResponse>;
+
+/// Codec used by a [`crate::slave::Slave`]: encodes responses, decodes requests.
+pub type SlaveCodec = MessageCodec<SlaveResponse, MasterRequest>;
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::Framed;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_a_request_over_a_real_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, SlaveCodec::new());
+            framed.next().await.unwrap().unwrap()
+        });
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        let mut framed = Framed::new(client, MasterCodec::new());
+        framed.send(MasterRequest::Ping).await.unwrap();
+
+        let received = accept.await.unwrap();
+        assert!(matches!(received, MasterRequest::Ping));
+    }
+
+    #[test]
+    fn rejects_a_frame_with_a_corrupted_byte() {
+        let mut buf = BytesMut::new();
+        let mut codec = MasterCodec::new();
+        codec.encode(MasterRequest::Ping, &mut buf).unwrap();
+
+        // Flip a byte inside the payload, past the length prefix and CRC.
+        let corrupt_at = buf.len() - 1;
+        buf[corrupt_at] ^= 0xFF;
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert!(matches!(err, Error::CrcMismatch { .. }));
+    }
+}
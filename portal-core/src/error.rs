@@ -0,0 +1,66 @@
+use std::io;
+
+/// Errors that can occur anywhere in the protocol, transport or transfer engine.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to encode/decode a frame: {0}")]
+    Codec(#[from] bincode::Error),
+
+    #[error("frame failed CRC32 check: expected {expected:#010x}, got {actual:#010x}")]
+    CrcMismatch { expected: u32, actual: u32 },
+
+    #[error("incompatible protocol version: we speak {ours}, peer speaks {theirs}")]
+    IncompatibleProtocol { ours: u16, theirs: u16 },
+
+    #[error("unexpected response from peer: {0:?}")]
+    UnexpectedResponse(crate::codec::SlaveResponse),
+
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[cfg(feature = "noise")]
+    #[error("Noise protocol error: {0}")]
+    Noise(String),
+
+    #[cfg(feature = "noise")]
+    #[error("device {0} presented a different identity than the one previously trusted for it")]
+    UntrustedPeer(String),
+
+    #[error("connection closed by peer")]
+    ConnectionClosed,
+
+    #[error("peer did not respond within the configured idle timeout")]
+    PeerUnresponsive,
+
+    #[error("operation cancelled")]
+    Cancelled,
+
+    #[error("background transfer task panicked before it could finish")]
+    TransferTaskPanicked,
+
+    #[error("no handler registered for custom request kind {0:#x}")]
+    UnknownCustomKind(u16),
+
+    #[error("custom request kind {0:#x} is outside the reserved range")]
+    CustomKindOutOfRange(u16),
+}
+
+impl Error {
+    /// Whether this plausibly stems from a transient network hiccup (a
+    /// dropped connection, an unresponsive peer, a corrupted frame) rather
+    /// than a permanent protocol or configuration problem, so retry
+    /// helpers like [`crate::retry::RetryPolicy`] know what's worth
+    /// retrying instead of failing outright.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(_) | Error::ConnectionClosed | Error::PeerUnresponsive | Error::CrcMismatch { .. }
+        )
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,140 @@
+//! A small on-disk log of finished transfers, so an embedding application
+//! (the CLI's `History` menu entry / `portal history` subcommand, for
+//! instance) can show what was sent and how it went after the fact, rather
+//! than only while a [`crate::task_manager::TaskManager`] is still tracking
+//! it.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::SlaveResponse;
+use crate::error::Result;
+
+/// One finished transfer, successful or not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub file: PathBuf,
+    pub peer: SocketAddr,
+    pub size: u64,
+    pub duration_ms: u64,
+    pub succeeded: bool,
+    /// The peer's final response, or the error that ended the transfer,
+    /// formatted for display; a plain `String` rather than
+    /// [`crate::error::Error`] so an entry can be loaded back from disk
+    /// without needing the error type to round-trip through serde.
+    pub result: String,
+}
+
+impl HistoryEntry {
+    pub fn new(file: PathBuf, peer: SocketAddr, size: u64, duration: Duration, response: &Result<SlaveResponse>) -> Self {
+        let (succeeded, result) = match response {
+            Ok(response) => (matches!(response, SlaveResponse::Ok), format!("{response:?}")),
+            Err(err) => (false, err.to_string()),
+        };
+        Self { file, peer, size, duration_ms: duration.as_millis() as u64, succeeded, result }
+    }
+}
+
+/// Appends [`HistoryEntry`] records, rewriting the backing file in full on
+/// every write, the same way [`crate::registry::Registry`] and
+/// [`crate::journal::Journal`] do. Unlike those, entries are never removed
+/// or looked up by key — a transfer history is just appended to and read
+/// back in full.
+pub struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Loads `path` if it exists (an empty or missing file means no
+    /// history yet), or starts a fresh history that will be created at
+    /// `path` on the first write.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) if bytes.is_empty() => Vec::new(),
+            Ok(bytes) => bincode::deserialize(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Every recorded transfer, oldest first.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Records `entry` and persists the history.
+    pub fn record(&mut self, entry: HistoryEntry) -> Result<()> {
+        self.entries.push(entry);
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<()> {
+        let bytes = bincode::serialize(&self.entries)?;
+        // Write to a sibling temp file and rename over the real path so a
+        // crash mid-write can't leave a half-written, unreadable history.
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, &bytes)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("portal-history-{name}-test-{:?}.bin", std::thread::current().id()))
+    }
+
+    #[test]
+    fn round_trips_entries_through_a_reopened_history() {
+        let path = test_path("round-trip");
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::open(&path).unwrap();
+        assert_eq!(history.entries().len(), 0);
+
+        let peer: SocketAddr = "192.168.1.10:4242".parse().unwrap();
+        history
+            .record(HistoryEntry::new(
+                PathBuf::from("a.txt"),
+                peer,
+                10,
+                Duration::from_millis(5),
+                &Ok(SlaveResponse::Ok),
+            ))
+            .unwrap();
+        history
+            .record(HistoryEntry::new(
+                PathBuf::from("b.txt"),
+                peer,
+                20,
+                Duration::from_millis(7),
+                &Err(crate::error::Error::ConnectionClosed),
+            ))
+            .unwrap();
+
+        let reopened = History::open(&path).unwrap();
+        assert_eq!(reopened.entries().len(), 2);
+        assert!(reopened.entries()[0].succeeded);
+        assert!(!reopened.entries()[1].succeeded);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn treats_a_missing_file_as_an_empty_history() {
+        let path = test_path("missing");
+        let _ = std::fs::remove_file(&path);
+
+        let history = History::open(&path).unwrap();
+        assert_eq!(history.entries().len(), 0);
+    }
+}
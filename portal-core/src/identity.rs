@@ -0,0 +1,95 @@
+//! A stable per-installation identity, so a device can be recognized across
+//! reconnects and address changes instead of by its current `SocketAddr`,
+//! which a DHCP lease renewal can hand to someone else entirely.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+
+/// Identifies one installation of portal. Exchanged during
+/// [`crate::master::Master::handshake`] and broadcast by
+/// [`crate::broadcast::Sender`], so a peer stays recognizable even after its
+/// address changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeviceId(Uuid);
+
+impl DeviceId {
+    /// A freshly generated id, not persisted anywhere. Good enough for a
+    /// one-off connection; use [`Self::load_or_create`] when the same
+    /// identity should survive a restart.
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Loads the id persisted at `path`, or generates and persists a new one
+    /// if the file doesn't exist yet.
+    pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                let uuid = contents
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_device_id_file())?;
+                Ok(Self(uuid))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let id = Self::generate();
+                std::fs::write(path, id.0.to_string())?;
+                Ok(id)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+fn invalid_device_id_file() -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "device id file does not contain a valid UUID",
+    ))
+}
+
+impl From<Uuid> for DeviceId {
+    fn from(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_and_reloads_the_same_id() {
+        let path = std::env::temp_dir().join(format!(
+            "portal-device-id-test-{:?}.txt",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let first = DeviceId::load_or_create(&path).unwrap();
+        let second = DeviceId::load_or_create(&path).unwrap();
+        assert_eq!(first, second);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn generated_ids_are_not_equal() {
+        assert_ne!(DeviceId::generate(), DeviceId::generate());
+    }
+}
@@ -0,0 +1,141 @@
+//! A small on-disk journal of in-flight transfers, so [`crate::task_manager::TaskManager`]
+//! can list and offer to resume transfers that were still running when the
+//! process crashed or was restarted, instead of losing track of them.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Enough about one transfer to list it after a restart and continue it
+/// with [`crate::master::Master::resume_a_file`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub task_id: u64,
+    pub path: PathBuf,
+    pub bytes_confirmed: u64,
+}
+
+/// Tracks [`JournalEntry`] records keyed by task id, rewriting the backing
+/// file in full on every change. Transfers are few and journal entries are
+/// tiny, so a rewrite-on-write design is simpler than an append log and
+/// doesn't need compaction.
+pub struct Journal {
+    path: PathBuf,
+    entries: HashMap<u64, JournalEntry>,
+}
+
+impl Journal {
+    /// Loads `path` if it exists (an empty or missing file means no
+    /// journaled transfers), or starts a fresh journal that will be created
+    /// at `path` on the first write.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) if bytes.is_empty() => HashMap::new(),
+            Ok(bytes) => {
+                let entries: Vec<JournalEntry> = bincode::deserialize(&bytes)?;
+                entries.into_iter().map(|entry| (entry.task_id, entry)).collect()
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Every transfer currently journaled, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.values()
+    }
+
+    /// Records (or updates) `entry` and persists the journal.
+    pub fn record(&mut self, entry: JournalEntry) -> Result<()> {
+        self.entries.insert(entry.task_id, entry);
+        self.flush()
+    }
+
+    /// Removes `task_id`'s entry, if any, and persists the journal.
+    pub fn forget(&mut self, task_id: u64) -> Result<()> {
+        self.entries.remove(&task_id);
+        self.flush()
+    }
+
+    /// The largest task id currently journaled, if any; used to seed
+    /// [`crate::task_manager::TaskManager`]'s own id counter so it doesn't
+    /// hand out an id that collides with an entry loaded from a previous
+    /// run.
+    pub fn max_task_id(&self) -> Option<u64> {
+        self.entries.keys().copied().max()
+    }
+
+    fn flush(&self) -> Result<()> {
+        let entries: Vec<&JournalEntry> = self.entries.values().collect();
+        let bytes = bincode::serialize(&entries)?;
+        // Write to a sibling temp file and rename over the real path so a
+        // crash mid-write can't leave a half-written, unreadable journal.
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, &bytes)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_entries_through_a_reopened_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "portal-journal-test-{:?}.bin",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.entries().count(), 0);
+
+        journal
+            .record(JournalEntry {
+                task_id: 1,
+                path: PathBuf::from("a.txt"),
+                bytes_confirmed: 10,
+            })
+            .unwrap();
+        journal
+            .record(JournalEntry {
+                task_id: 2,
+                path: PathBuf::from("b.txt"),
+                bytes_confirmed: 20,
+            })
+            .unwrap();
+
+        let reopened = Journal::open(&path).unwrap();
+        assert_eq!(reopened.max_task_id(), Some(2));
+        let mut paths: Vec<_> = reopened.entries().map(|e| e.path.clone()).collect();
+        paths.sort();
+        assert_eq!(paths, vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
+
+        journal.forget(1).unwrap();
+        let reopened = Journal::open(&path).unwrap();
+        assert_eq!(reopened.entries().count(), 1);
+        assert_eq!(reopened.max_task_id(), Some(2));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn treats_a_missing_file_as_an_empty_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "portal-journal-missing-test-{:?}.bin",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.entries().count(), 0);
+        assert_eq!(journal.max_task_id(), None);
+    }
+}
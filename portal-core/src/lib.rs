@@ -0,0 +1,21 @@
+pub mod broadcast;
+pub mod codec;
+pub mod error;
+pub mod history;
+pub mod identity;
+pub mod journal;
+pub mod master;
+pub(crate) mod metrics;
+#[cfg(feature = "noise")]
+pub mod noise;
+pub mod registry;
+pub mod retry;
+pub mod slave;
+pub mod task_manager;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+#[cfg(feature = "noise")]
+pub mod trust;
+
+pub use error::{Error, Result};
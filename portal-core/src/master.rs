@@ -0,0 +1,3211 @@
+//! The sending side of a portal transfer.
+
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+
+use crate::codec::{
+    FileFragment, FileMetadata, MasterCodec, MasterRequest, SlaveResponse, SymlinkEntry,
+    CUSTOM_KIND_MIN, DEFAULT_MAX_CONTENT_SIZE, PROTOCOL_VERSION,
+};
+use crate::error::{Error, Result};
+use crate::identity::DeviceId;
+use crate::retry::RetryPolicy;
+use crate::transport::{AsyncStream, BoxedStream};
+
+/// How many unacknowledged fragments may be in flight at once by default.
+pub const DEFAULT_ACK_WINDOW: usize = 32;
+
+/// How many frames [`Master::feed_request`] buffers before flushing, by
+/// default.
+pub const DEFAULT_FLUSH_WATERMARK: usize = 32;
+
+/// The outcome of sending one file as part of a [`Master::send_files`] batch.
+pub struct FileHandle {
+    pub path: PathBuf,
+    pub response: Result<SlaveResponse>,
+}
+
+/// The aggregate outcome of a [`Master::send_files`] batch.
+pub struct BatchHandle {
+    pub files: Vec<FileHandle>,
+}
+
+/// A transfer started by [`Master::spawn_send_a_file`], running on a
+/// background task that owns the `Master` for the transfer's duration.
+pub struct TransferHandle {
+    task: tokio::task::JoinHandle<Result<SlaveResponse>>,
+}
+
+impl TransferHandle {
+    /// Waits for the transfer to finish and returns what
+    /// [`Master::send_a_file`] itself would have returned had it been
+    /// awaited inline: the slave's answer to the terminal `EndOfFile`.
+    pub async fn join(self) -> Result<SlaveResponse> {
+        self.task.await.unwrap_or(Err(Error::TransferTaskPanicked))
+    }
+}
+
+/// One target's outcome from [`fan_out_file`].
+pub struct FanOutResult<T> {
+    pub target: T,
+    pub response: Result<SlaveResponse>,
+}
+
+impl BatchHandle {
+    /// Whether every file in the batch was accepted by the slave.
+    pub fn all_ok(&self) -> bool {
+        self.files
+            .iter()
+            .all(|file| matches!(file.response, Ok(SlaveResponse::Ok)))
+    }
+}
+
+/// A snapshot of an in-flight transfer, broadcast over the
+/// [`tokio::sync::watch`] channel passed to
+/// [`Master::send_a_file_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Progress {
+    pub bytes_sent: u64,
+    pub total: u64,
+    /// Bytes sent per second, averaged since the transfer started.
+    pub rate: f64,
+    /// The slave's own received-bytes total, last reported via
+    /// [`SlaveResponse::Progress`]; `0` until the first one arrives. Unlike
+    /// `bytes_sent`, which only counts what's been written to the socket,
+    /// this reflects what the slave has actually confirmed landed on disk.
+    pub bytes_confirmed: u64,
+}
+
+/// Tracks one file's progress through [`Master::send_files_interleaved`].
+struct InterleaveJob {
+    file_id: u32,
+    payload: Bytes,
+    offset: usize,
+    next_index: u32,
+}
+
+/// How [`Master::send_directory`] should handle symlinks it encounters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Don't transfer anything for the symlink.
+    #[default]
+    Skip,
+    /// Transfer the file the symlink points to, as if it were a regular file.
+    Follow,
+    /// Recreate the symlink itself on the slave, pointing at the same target.
+    Recreate,
+}
+
+/// Drives an outgoing connection to a [`crate::slave::Slave`].
+pub struct Master {
+    stream: Framed<BoxedStream, MasterCodec>,
+    ack_window: usize,
+    compression_enabled: bool,
+    #[cfg(feature = "lz4")]
+    lz4_fragments: bool,
+    symlink_policy: SymlinkPolicy,
+    /// Allocates each transfer's [`FileMetadata::file_id`]; see
+    /// [`Master::allocate_file_id`].
+    next_file_id: u32,
+    /// Largest fragment content size to use. Starts out as whatever
+    /// [`MasterBuilder::max_content_size`] was set to, then
+    /// [`Master::handshake`] narrows it down to whatever the slave is
+    /// willing to accept.
+    content_size: usize,
+    /// How long [`Master::recv_response`] will wait for a reply before
+    /// giving up on the slave; see [`MasterBuilder::idle_timeout`]. `None`
+    /// waits forever.
+    idle_timeout: Option<Duration>,
+    /// Lets an embedding application stop an active transfer or
+    /// [`Master::run_keepalive`] cleanly; see
+    /// [`MasterBuilder::cancellation_token`].
+    cancellation: Option<CancellationToken>,
+    /// Caps the average rate of outgoing file fragments, in bytes/sec; see
+    /// [`MasterBuilder::rate_limit`] and [`Master::set_rate_limit`]. `None`
+    /// sends as fast as the connection allows.
+    rate_limit: Option<u64>,
+    /// How many frames [`Master::feed_request`] buffers before flushing; see
+    /// [`MasterBuilder::flush_watermark`] and [`Master::set_flush_watermark`].
+    flush_watermark: usize,
+    /// How many frames have been fed since the last flush; see
+    /// `flush_watermark`.
+    pending_flush: usize,
+    /// Identifies this installation to the slave during [`Master::handshake`];
+    /// see [`MasterBuilder::device_id`].
+    device_id: DeviceId,
+    /// The slave's [`DeviceId`], learned from [`SlaveResponse::Hello`] during
+    /// [`Master::handshake`]. `None` until the handshake completes.
+    peer_device_id: Option<DeviceId>,
+}
+
+impl From<TcpStream> for Master {
+    fn from(stream: TcpStream) -> Self {
+        Self::from_stream(stream)
+    }
+}
+
+/// Builds a [`Master`] with non-default transfer settings.
+///
+/// ```ignore
+/// let master = MasterBuilder::new().ack_window(8).build(stream);
+/// ```
+#[derive(Clone)]
+pub struct MasterBuilder {
+    ack_window: usize,
+    #[cfg(feature = "lz4")]
+    lz4_fragments: bool,
+    symlink_policy: SymlinkPolicy,
+    max_content_size: usize,
+    idle_timeout: Option<Duration>,
+    cancellation: Option<CancellationToken>,
+    rate_limit: Option<u64>,
+    flush_watermark: usize,
+    nodelay: bool,
+    device_id: DeviceId,
+}
+
+impl Default for MasterBuilder {
+    fn default() -> Self {
+        Self {
+            ack_window: DEFAULT_ACK_WINDOW,
+            #[cfg(feature = "lz4")]
+            lz4_fragments: false,
+            symlink_policy: SymlinkPolicy::default(),
+            max_content_size: DEFAULT_MAX_CONTENT_SIZE,
+            idle_timeout: None,
+            cancellation: None,
+            rate_limit: None,
+            flush_watermark: DEFAULT_FLUSH_WATERMARK,
+            nodelay: true,
+            device_id: DeviceId::generate(),
+        }
+    }
+}
+
+impl MasterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`Master::set_ack_window`].
+    pub fn ack_window(mut self, depth: usize) -> Self {
+        self.ack_window = depth.max(1);
+        self
+    }
+
+    /// Compresses each fragment individually with LZ4 instead of sending it
+    /// raw. Cheaper than whole-file `compression` but saves less; suited to
+    /// low-latency links where compressing per-fragment shouldn't stall the
+    /// ack window.
+    #[cfg(feature = "lz4")]
+    pub fn lz4_fragments(mut self, enabled: bool) -> Self {
+        self.lz4_fragments = enabled;
+        self
+    }
+
+    /// How [`Master::send_directory`] should handle symlinks it encounters.
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// The largest fragment content size this master is willing to send,
+    /// before negotiating down to whatever the slave advertises in
+    /// [`SlaveResponse::Hello`] during [`Master::handshake`]. Larger values
+    /// trade more per-fragment memory and latency-to-first-ack for fewer
+    /// round trips on links that can carry more than an Ethernet MTU's
+    /// worth of payload per write.
+    pub fn max_content_size(mut self, size: usize) -> Self {
+        self.max_content_size = size.max(1);
+        self
+    }
+
+    /// Fails [`Master::recv_response`] with [`Error::PeerUnresponsive`] if
+    /// the slave doesn't reply within `timeout`, instead of waiting on a
+    /// peer that may have hung or vanished without closing the connection.
+    /// Disabled by default; see also [`Master::run_keepalive`].
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Lets `token` stop an active transfer or [`Master::run_keepalive`]
+    /// cleanly: the next cancellation check fails with
+    /// [`Error::Cancelled`] instead of the transfer running to completion,
+    /// so an embedding application can shut down without waiting it out.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// See [`Master::set_rate_limit`]. Sets the default for every transfer
+    /// sent by the built `Master`, rather than just the next one.
+    pub fn rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// See [`Master::set_flush_watermark`].
+    pub fn flush_watermark(mut self, frames: usize) -> Self {
+        self.flush_watermark = frames.max(1);
+        self
+    }
+
+    /// Whether [`MasterBuilder::connect`] should set `TCP_NODELAY` on the
+    /// dialed socket. Enabled by default: fragments and acks are already
+    /// explicitly sized and windowed, so letting Nagle's algorithm buffer
+    /// them further only adds latency without saving round trips. Has no
+    /// effect on a stream supplied directly to [`MasterBuilder::build`].
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Identifies this installation to the slave during [`Master::handshake`],
+    /// so it can be recognized across reconnects even if its address
+    /// changes. Defaults to a freshly generated [`DeviceId`] that isn't
+    /// persisted anywhere; pass one loaded via [`DeviceId::load_or_create`]
+    /// if the same identity should survive a restart.
+    pub fn device_id(mut self, id: DeviceId) -> Self {
+        self.device_id = id;
+        self
+    }
+
+    /// Wraps an already-established stream as a `Master` with this builder's
+    /// settings applied.
+    pub fn build(self, stream: impl AsyncStream + 'static) -> Master {
+        crate::metrics::connection_opened();
+        Master {
+            stream: Framed::new(Box::new(stream) as BoxedStream, MasterCodec::new()),
+            ack_window: self.ack_window,
+            compression_enabled: false,
+            #[cfg(feature = "lz4")]
+            lz4_fragments: self.lz4_fragments,
+            symlink_policy: self.symlink_policy,
+            next_file_id: 0,
+            content_size: self.max_content_size,
+            idle_timeout: self.idle_timeout,
+            cancellation: self.cancellation,
+            rate_limit: self.rate_limit,
+            flush_watermark: self.flush_watermark,
+            pending_flush: 0,
+            device_id: self.device_id,
+            peer_device_id: None,
+        }
+    }
+
+    /// Dials `addr`, handshakes, and returns a ready-to-use `Master` with
+    /// this builder's settings applied, all within `timeout`. Fails with
+    /// [`Error::PeerUnresponsive`] if dialing or the handshake doesn't
+    /// finish in time.
+    pub async fn connect(self, addr: impl tokio::net::ToSocketAddrs, timeout: Duration) -> Result<Master> {
+        tokio::time::timeout(timeout, async move {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(self.nodelay)?;
+            let mut master = self.build(stream);
+            master.handshake().await?;
+            Ok(master)
+        })
+        .await
+        .map_err(|_| Error::PeerUnresponsive)?
+    }
+}
+
+impl Master {
+    /// How many times [`Master::resolve_missing_fragments`] will resend
+    /// reported gaps before giving up and returning the slave's
+    /// [`SlaveResponse::MissingFragments`] response as-is, bounding retries
+    /// against a slave that keeps reporting the same gap.
+    const MAX_MISSING_FRAGMENT_RETRIES: usize = 5;
+
+    /// Wraps an already-established stream (plain TCP, TLS, or anything
+    /// else implementing [`AsyncStream`]) as a `Master`.
+    pub fn from_stream(stream: impl AsyncStream + 'static) -> Self {
+        MasterBuilder::new().build(stream)
+    }
+
+    /// Dials `addr`, handshakes, and returns a ready-to-use `Master`, all
+    /// within `timeout`. A convenience over [`Master::from_stream`] plus
+    /// [`Master::handshake`] for callers who don't need a custom
+    /// [`MasterBuilder`].
+    pub async fn connect(addr: impl tokio::net::ToSocketAddrs, timeout: Duration) -> Result<Self> {
+        MasterBuilder::new().connect(addr, timeout).await
+    }
+
+    /// Allocates the next [`FileMetadata::file_id`] for a transfer on this
+    /// connection. Wrapping is fine: by the time it wraps around, whatever
+    /// earlier transfer held the reused id is long since finalized.
+    fn allocate_file_id(&mut self) -> u32 {
+        let id = self.next_file_id;
+        self.next_file_id = self.next_file_id.wrapping_add(1);
+        id
+    }
+
+    /// Sets how many fragments may be sent without having been acknowledged
+    /// yet. A smaller window reacts to slave-side errors sooner; a larger
+    /// one tolerates more latency before throttling.
+    pub fn set_ack_window(&mut self, depth: usize) {
+        self.ack_window = depth.max(1);
+    }
+
+    /// Caps the average rate of outgoing file fragments to `bytes_per_sec`,
+    /// or removes the cap if `None`. Overrides [`MasterBuilder::rate_limit`]
+    /// for every transfer sent from this point on; call it again with a
+    /// different value (or `None`) before the next task to change it
+    /// per-task instead of for the `Master`'s whole lifetime.
+    pub fn set_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.rate_limit = bytes_per_sec;
+    }
+
+    /// Sleeps just long enough to keep the average rate of a transfer that
+    /// has sent `bytes_sent` bytes since `start` at or below
+    /// [`Self::rate_limit`]. No-op if no limit is set.
+    async fn throttle(&self, bytes_sent: u64, start: Instant) {
+        let Some(rate_limit) = self.rate_limit else {
+            return;
+        };
+        let target = Duration::from_secs_f64(bytes_sent as f64 / rate_limit as f64);
+        let elapsed = start.elapsed();
+        if let Some(remaining) = target.checked_sub(elapsed) {
+            tokio::time::sleep(remaining).await;
+        }
+    }
+
+    /// Sets how many frames [`Master::feed_request`] buffers, via
+    /// [`futures::SinkExt::feed`], before flushing them to the underlying
+    /// stream in one go. Larger values trade a little extra buffering for
+    /// far fewer syscalls on large transfers; `1` flushes every frame, the
+    /// same as [`Master::send_request`] always does.
+    pub fn set_flush_watermark(&mut self, frames: usize) {
+        self.flush_watermark = frames.max(1);
+    }
+
+    /// Buffers `request` without flushing, flushing automatically once
+    /// [`Self::set_flush_watermark`] frames have accumulated. [`SinkExt::feed`]
+    /// still waits for the sink to have room, so this respects backpressure
+    /// the same way [`Master::send_request`] does. Only safe for frames the
+    /// caller doesn't need the peer to have received yet, e.g. fragments
+    /// sent ahead of their ack; call [`Master::flush`] before waiting on a
+    /// response to anything fed this way.
+    async fn feed_request(&mut self, request: MasterRequest) -> Result<()> {
+        self.stream.feed(request).await?;
+        self.pending_flush += 1;
+        if self.pending_flush >= self.flush_watermark {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes any frames buffered by [`Master::feed_request`].
+    async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await?;
+        self.pending_flush = 0;
+        Ok(())
+    }
+
+    #[cfg(feature = "lz4")]
+    fn maybe_compress_fragment(&self, chunk: Bytes) -> (Bytes, bool) {
+        if self.lz4_fragments {
+            (lz4_flex::compress_prepend_size(&chunk).into(), true)
+        } else {
+            (chunk, false)
+        }
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    fn maybe_compress_fragment(&self, chunk: Bytes) -> (Bytes, bool) {
+        (chunk, false)
+    }
+
+    /// Sends `request` and flushes immediately, including any frames still
+    /// buffered by a prior [`Master::feed_request`].
+    pub async fn send_request(&mut self, request: MasterRequest) -> Result<()> {
+        self.stream.send(request).await?;
+        self.pending_flush = 0;
+        Ok(())
+    }
+
+    pub async fn recv_response(&mut self) -> Result<SlaveResponse> {
+        let next = match self.cancellation.clone() {
+            Some(token) => tokio::select! {
+                _ = token.cancelled() => return Err(Error::Cancelled),
+                next = self.recv_raw() => next,
+            },
+            None => self.recv_raw().await,
+        }?;
+        next.ok_or(Error::ConnectionClosed)?
+    }
+
+    /// Waits for the next response, subject to [`MasterBuilder::idle_timeout`].
+    async fn recv_raw(&mut self) -> Result<Option<Result<SlaveResponse>>> {
+        match self.idle_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.stream.next())
+                .await
+                .map_err(|_| Error::PeerUnresponsive),
+            None => Ok(self.stream.next().await),
+        }
+    }
+
+    /// Fails with [`Error::Cancelled`] if [`MasterBuilder::cancellation_token`]
+    /// was set and has since been cancelled. Checked once per fragment in
+    /// transfer loops so a cancelled transfer stops promptly instead of
+    /// running to completion.
+    fn check_cancelled(&self) -> Result<()> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Negotiates protocol compatibility with the slave. Should be called
+    /// once, right after connecting, before any other request.
+    pub async fn handshake(&mut self) -> Result<Vec<String>> {
+        self.send_request(MasterRequest::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: crate::codec::local_features(),
+            max_content_size: self.content_size as u32,
+            device_id: self.device_id,
+        })
+        .await?;
+        match self.recv_response().await? {
+            SlaveResponse::Hello {
+                features,
+                max_content_size,
+                device_id,
+                ..
+            } => {
+                self.compression_enabled =
+                    cfg!(feature = "compression") && features.iter().any(|f| f == "zstd");
+                self.content_size = self.content_size.min(max_content_size as usize);
+                self.peer_device_id = Some(device_id);
+                Ok(features)
+            }
+            SlaveResponse::IncompatibleProtocol {
+                slave_version,
+                master_version,
+            } => Err(Error::IncompatibleProtocol {
+                ours: master_version,
+                theirs: slave_version,
+            }),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// This installation's [`DeviceId`], as sent to the slave during
+    /// [`Master::handshake`].
+    pub fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+
+    /// The slave's [`DeviceId`], learned from [`Master::handshake`]. `None`
+    /// before the handshake completes.
+    pub fn peer_device_id(&self) -> Option<DeviceId> {
+        self.peer_device_id
+    }
+
+    /// Proves knowledge of the slave's pairing key, as set via
+    /// [`crate::slave::Slave::set_pairing_key`]. Must be called before any
+    /// file or custom request if the slave requires pairing.
+    ///
+    /// Asks the slave for a nonce first, then proves the key by sending back
+    /// `HMAC-SHA256(key, nonce)` rather than the key (or a fixed hash of it)
+    /// directly, so neither the key nor a captured proof is any use to
+    /// someone who observes this exchange or a past one.
+    pub async fn authenticate(&mut self, key: &str) -> Result<()> {
+        self.send_request(MasterRequest::Auth { proof: None }).await?;
+        let nonce = match self.recv_response().await? {
+            SlaveResponse::AuthRequired { nonce } => nonce,
+            // No pairing key set on the slave at all.
+            SlaveResponse::Ok => return Ok(()),
+            other => return Err(Error::UnexpectedResponse(other)),
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+            .expect("HMAC-SHA256 accepts a key of any length");
+        mac.update(&nonce);
+        let proof = mac.finalize().into_bytes().into();
+        self.send_request(MasterRequest::Auth { proof: Some(proof) }).await?;
+        match self.recv_response().await? {
+            SlaveResponse::Ok => Ok(()),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Sends a file to the connected slave, awaiting the transfer inline.
+    ///
+    /// The returned [`SlaveResponse`] is the slave's answer to the terminal
+    /// `EndOfFile`, so it reflects whether the slave actually finished
+    /// saving the file (`Ok`) or not (`ChecksumNotMatched`,
+    /// `CannotSaveFile`, ...), not just that every fragment made it onto the
+    /// wire.
+    ///
+    /// The connection only ever has one transfer in flight at a time, so
+    /// this borrows `self` for as long as the transfer takes; use
+    /// [`Master::spawn_send_a_file`] if the caller needs to keep running
+    /// while the transfer is in flight, at the cost of giving up the
+    /// `Master` to the background task for the duration.
+    pub async fn send_a_file(&mut self, path: impl AsRef<Path>) -> Result<SlaveResponse> {
+        self.do_send_a_file(path.as_ref()).await
+    }
+
+    async fn do_send_a_file(&mut self, path: &Path) -> Result<SlaveResponse> {
+        self.do_send_a_file_resuming(path, file_name_of(path), &HashSet::new(), None)
+            .await
+    }
+
+    /// Like [`Master::send_a_file`], but runs the transfer on a spawned
+    /// background task and returns a [`TransferHandle`] immediately instead
+    /// of waiting for it inline. Since this connection can only drive one
+    /// transfer at a time anyway, the background task takes ownership of
+    /// `self` for the transfer's duration; join the handle to get the
+    /// slave's final response once it's done.
+    pub fn spawn_send_a_file(self, path: impl AsRef<Path> + Send + 'static) -> TransferHandle {
+        let task = tokio::spawn(async move {
+            let mut this = self;
+            this.do_send_a_file(path.as_ref()).await
+        });
+        TransferHandle { task }
+    }
+
+    /// Like [`Master::send_a_file`], but also reports progress on `progress`
+    /// as the transfer proceeds (after every fragment is sent, plus once
+    /// more at completion), so an embedding CLI or GUI can render it
+    /// without polling internals.
+    pub async fn send_a_file_with_progress(
+        &mut self,
+        path: impl AsRef<Path>,
+        progress: watch::Sender<Progress>,
+    ) -> Result<SlaveResponse> {
+        let path = path.as_ref();
+        self.do_send_a_file_resuming(path, file_name_of(path), &HashSet::new(), Some(&progress))
+            .await
+    }
+
+    /// Walks `root` recursively and sends every regular file it contains,
+    /// naming each one with its path relative to `root` (using `/` as the
+    /// separator) so the slave can recreate the directory structure under
+    /// its received-files folder.
+    ///
+    /// Symlinks are handled per [`Master::symlink_policy`] (skipped by
+    /// default); see [`MasterBuilder::symlink_policy`] to change it.
+    pub async fn send_directory(&mut self, root: PathBuf) -> BatchHandle {
+        let mut files = Vec::new();
+        let mut symlinks = Vec::new();
+        let mut stack = vec![root.clone()];
+        while let Some(dir) = stack.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                match entry.file_type().await {
+                    Ok(file_type) if file_type.is_symlink() => match self.symlink_policy {
+                        SymlinkPolicy::Skip => {}
+                        SymlinkPolicy::Follow => files.push(path),
+                        SymlinkPolicy::Recreate => symlinks.push(path),
+                    },
+                    Ok(file_type) if file_type.is_dir() => stack.push(path),
+                    Ok(file_type) if file_type.is_file() => files.push(path),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut handles = Vec::with_capacity(files.len() + symlinks.len());
+        for path in files {
+            let relative = relative_name(&root, &path);
+            let response = self
+                .do_send_a_file_resuming(&path, relative, &HashSet::new(), None)
+                .await;
+            handles.push(FileHandle { path, response });
+        }
+        for path in symlinks {
+            let relative = relative_name(&root, &path);
+            let response = self.send_symlink(&path, relative).await;
+            handles.push(FileHandle { path, response });
+        }
+        BatchHandle { files: handles }
+    }
+
+    async fn send_symlink(&mut self, path: &Path, relative: String) -> Result<SlaveResponse> {
+        let target = tokio::fs::read_link(path).await?;
+        self.send_request(MasterRequest::Symlink(SymlinkEntry {
+            path: relative,
+            target: target.to_string_lossy().into_owned(),
+        }))
+        .await?;
+        self.recv_response().await
+    }
+
+    /// Sends several files over this connection, one after another, instead
+    /// of the caller looping over [`Master::send_a_file`] itself.
+    ///
+    /// A failure sending one file doesn't stop the batch; every file is
+    /// attempted and gets its own [`FileHandle`] in the returned
+    /// [`BatchHandle`].
+    pub async fn send_files(&mut self, paths: Vec<PathBuf>) -> BatchHandle {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let response = self.send_a_file(&path).await;
+            files.push(FileHandle { path, response });
+        }
+        BatchHandle { files }
+    }
+
+    /// Sends several files over this connection like [`Master::send_files`],
+    /// but interleaves their fragments round-robin by `file_id` instead of
+    /// finishing one file before starting the next. This way a large file
+    /// queued first doesn't hold up the completion of a small, urgent one
+    /// queued behind it.
+    pub async fn send_files_interleaved(&mut self, paths: Vec<PathBuf>) -> BatchHandle {
+        let mut files: Vec<FileHandle> = Vec::with_capacity(paths.len());
+        let mut jobs: Vec<InterleaveJob> = Vec::new();
+        let mut job_handle: Vec<usize> = Vec::new();
+
+        for path in paths {
+            match self.send_file_metadata(&path).await {
+                Ok((file_id, payload, SlaveResponse::Ok)) => {
+                    job_handle.push(files.len());
+                    files.push(FileHandle {
+                        path,
+                        response: Ok(SlaveResponse::Ok),
+                    });
+                    jobs.push(InterleaveJob {
+                        file_id,
+                        payload,
+                        offset: 0,
+                        next_index: 0,
+                    });
+                }
+                Ok((_, _, other)) => files.push(FileHandle {
+                    path,
+                    response: Ok(other),
+                }),
+                Err(err) => files.push(FileHandle {
+                    path,
+                    response: Err(err),
+                }),
+            }
+        }
+
+        let mut inflight: VecDeque<usize> = VecDeque::with_capacity(self.ack_window);
+        let mut settled = vec![false; jobs.len()];
+
+        if let Err(mut err) = self
+            .run_interleaved_fragments(&mut jobs, &mut inflight, &mut settled, &job_handle, &mut files)
+            .await
+        {
+            for (job_idx, done) in settled.iter().enumerate() {
+                if !done {
+                    let response_err = std::mem::replace(&mut err, Error::ConnectionClosed);
+                    files[job_handle[job_idx]].response = Err(response_err);
+                }
+            }
+            return BatchHandle { files };
+        }
+
+        for (job_idx, job) in jobs.into_iter().enumerate() {
+            if settled[job_idx] {
+                continue;
+            }
+            let outcome = match self
+                .send_request(MasterRequest::EndOfFile {
+                    file_id: job.file_id,
+                })
+                .await
+            {
+                Ok(()) => self.recv_response().await,
+                Err(err) => Err(err),
+            };
+            let outcome = self
+                .resolve_missing_fragments(job.file_id, &job.payload, outcome)
+                .await;
+            files[job_handle[job_idx]].response = outcome;
+        }
+
+        BatchHandle { files }
+    }
+
+    /// Sends a file's [`MasterRequest::FileMetadata`] and returns its
+    /// `file_id`, prepared payload, and the slave's ack, so the caller can
+    /// decide whether to proceed to fragments (used by
+    /// [`Master::send_files_interleaved`]).
+    async fn send_file_metadata(&mut self, path: &Path) -> Result<(u32, Bytes, SlaveResponse)> {
+        let (payload, file_hash, compressed, file_size) = self.load_payload(path).await?;
+        let (modified, unix_mode) = file_timestamps_of(path).await;
+        let file_id = self.allocate_file_id();
+
+        self.send_request(MasterRequest::FileMetadata(FileMetadata {
+            file_name: file_name_of(path),
+            file_id,
+            file_hash: Some(file_hash),
+            compressed,
+            modified,
+            unix_mode,
+            file_size,
+            fragment_size: self.content_size as u32,
+        }))
+        .await?;
+        let ack = self.recv_response().await?;
+        Ok((file_id, payload, ack))
+    }
+
+    /// Round-robins fragments of every not-yet-settled job in `jobs` until
+    /// each has either sent its whole payload or gotten back a non-`Ok` ack
+    /// (recorded into `files` via `job_handle`, and marked in `settled`).
+    async fn run_interleaved_fragments(
+        &mut self,
+        jobs: &mut [InterleaveJob],
+        inflight: &mut VecDeque<usize>,
+        settled: &mut [bool],
+        job_handle: &[usize],
+        files: &mut [FileHandle],
+    ) -> Result<()> {
+        let mut remaining: Vec<usize> = (0..jobs.len())
+            .filter(|&i| !settled[i] && !jobs[i].payload.is_empty())
+            .collect();
+
+        while !remaining.is_empty() {
+            let mut next_remaining = Vec::with_capacity(remaining.len());
+            for job_idx in remaining {
+                if settled[job_idx] {
+                    continue;
+                }
+                self.check_cancelled()?;
+
+                let start = jobs[job_idx].offset;
+                let end = (start + self.content_size).min(jobs[job_idx].payload.len());
+                let chunk = jobs[job_idx].payload.slice(start..end);
+                let index = jobs[job_idx].next_index;
+                let file_id = jobs[job_idx].file_id;
+
+                let (data, fragment_compressed) = self.maybe_compress_fragment(chunk);
+                self.feed_request(MasterRequest::FileFragment(FileFragment {
+                    file_id,
+                    index,
+                    data,
+                    compressed: fragment_compressed,
+                }))
+                .await?;
+
+                jobs[job_idx].offset = end;
+                jobs[job_idx].next_index += 1;
+                inflight.push_back(job_idx);
+                if jobs[job_idx].offset < jobs[job_idx].payload.len() {
+                    next_remaining.push(job_idx);
+                }
+
+                if inflight.len() >= self.ack_window {
+                    self.flush().await?;
+                    self.settle_one_ack(inflight, settled, job_handle, files)
+                        .await?;
+                }
+            }
+            remaining = next_remaining.into_iter().filter(|&i| !settled[i]).collect();
+        }
+
+        self.flush().await?;
+        while !inflight.is_empty() {
+            self.settle_one_ack(inflight, settled, job_handle, files)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Awaits one fragment ack and, if it's neither a plain `Ok` nor a
+    /// [`SlaveResponse::Progress`], records it as the final response for the
+    /// job it belonged to and marks that job settled so no more fragments
+    /// are sent for it.
+    async fn settle_one_ack(
+        &mut self,
+        inflight: &mut VecDeque<usize>,
+        settled: &mut [bool],
+        job_handle: &[usize],
+        files: &mut [FileHandle],
+    ) -> Result<()> {
+        let job_idx = inflight
+            .pop_front()
+            .expect("settle_one_ack called with an empty inflight queue");
+        match self.recv_response().await? {
+            SlaveResponse::Ok => Ok(()),
+            SlaveResponse::Progress { .. } => Ok(()),
+            other => {
+                settled[job_idx] = true;
+                files[job_handle[job_idx]].response = Ok(other);
+                Ok(())
+            }
+        }
+    }
+
+    /// Resends a file that was interrupted mid-transfer, asking the slave
+    /// which fragments it already has (by content hash) and skipping them.
+    pub async fn resume_a_file(&mut self, path: impl AsRef<Path>) -> Result<SlaveResponse> {
+        let path = path.as_ref();
+        let data = tokio::fs::read(path).await?;
+        let file_hash = hash_of(&data);
+
+        self.send_request(MasterRequest::QueryResumeState { file_hash })
+            .await?;
+        let have_indices = match self.recv_response().await? {
+            SlaveResponse::ResumeState { have_indices } => have_indices.into_iter().collect(),
+            _ => HashSet::new(),
+        };
+
+        self.do_send_a_file_resuming(path, file_name_of(path), &have_indices, None)
+            .await
+    }
+
+    /// Reads `path` and prepares it for sending: the (possibly
+    /// whole-file-compressed) payload, its uncompressed content hash, and
+    /// whether compression was applied.
+    /// Returns the prepared payload (possibly zstd-compressed), its content
+    /// hash (always over the uncompressed bytes), whether it's compressed,
+    /// and its uncompressed size — the last of which is what the slave will
+    /// actually write to disk, so it's what gets sent as
+    /// [`FileMetadata::file_size`] rather than the (possibly smaller)
+    /// compressed payload length.
+    async fn load_payload(&self, path: &Path) -> Result<(Bytes, [u8; 32], bool, u64)> {
+        let data = read_whole_file(path).await?;
+        let file_hash = hash_of(&data);
+        let file_size = data.len() as u64;
+        let (payload, compressed) = self.compress_payload(data)?;
+        Ok((payload, file_hash, compressed, file_size))
+    }
+
+    /// Whole-file-compresses `data` with zstd if [`Master::compression_enabled`]
+    /// (negotiated per connection by [`Master::handshake`]), otherwise
+    /// passes it through unchanged. Split out of [`Master::load_payload`] so
+    /// [`fan_out_file`] can apply it per target after reading a file once.
+    fn compress_payload(&self, data: Bytes) -> Result<(Bytes, bool)> {
+        let compressed = self.compression_enabled;
+
+        #[cfg(feature = "compression")]
+        let payload: Bytes = if compressed {
+            zstd::stream::encode_all(&data[..], 0)?.into()
+        } else {
+            data
+        };
+        #[cfg(not(feature = "compression"))]
+        let payload: Bytes = data;
+
+        Ok((payload, compressed))
+    }
+
+    async fn do_send_a_file_resuming(
+        &mut self,
+        path: &Path,
+        file_name: String,
+        have_indices: &HashSet<u32>,
+        progress: Option<&watch::Sender<Progress>>,
+    ) -> Result<SlaveResponse> {
+        let (payload, file_hash, compressed, file_size) = self.load_payload(path).await?;
+        let (modified, unix_mode) = file_timestamps_of(path).await;
+        self.send_prepared_payload(
+            file_name,
+            payload,
+            file_hash,
+            compressed,
+            file_size,
+            modified,
+            unix_mode,
+            have_indices,
+            progress,
+        )
+        .await
+    }
+
+    /// Sends an already-prepared payload (read and, if applicable,
+    /// compressed ahead of time) as a single file. Split out of
+    /// [`Master::do_send_a_file_resuming`] so [`fan_out_file`] can send the
+    /// same source file's bytes to several targets without re-reading it
+    /// from disk for each one, even though each target still negotiates its
+    /// own compression and allocates its own `file_id`.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(
+        skip(self, payload, file_hash, have_indices, progress),
+        fields(file_name = %file_name, file_size, file_id = tracing::field::Empty)
+    )]
+    async fn send_prepared_payload(
+        &mut self,
+        file_name: String,
+        payload: Bytes,
+        file_hash: [u8; 32],
+        compressed: bool,
+        file_size: u64,
+        modified: Option<i64>,
+        unix_mode: Option<u32>,
+        have_indices: &HashSet<u32>,
+        progress: Option<&watch::Sender<Progress>>,
+    ) -> Result<SlaveResponse> {
+        let file_id = self.allocate_file_id();
+        tracing::Span::current().record("file_id", file_id);
+        let total = payload.len() as u64;
+        let start = Instant::now();
+
+        self.send_request(MasterRequest::FileMetadata(FileMetadata {
+            file_name,
+            file_id,
+            file_hash: Some(file_hash),
+            compressed,
+            modified,
+            unix_mode,
+            file_size,
+            fragment_size: self.content_size as u32,
+        }))
+        .await?;
+        // A rejection here (`FileTooLarge`, `FileTypeNotAllowed`, ...) means
+        // the slave never opened a `PendingFile` for this transfer, so there's
+        // no point sending it a single fragment.
+        match self.recv_response().await? {
+            SlaveResponse::Ok => {}
+            other => return Ok(other),
+        }
+
+        let mut inflight: VecDeque<u32> = VecDeque::with_capacity(self.ack_window);
+        let mut index = 0u32;
+        let mut offset = 0;
+        while offset < payload.len() {
+            self.check_cancelled()?;
+            let end = (offset + self.content_size).min(payload.len());
+            let chunk = payload.slice(offset..end);
+            offset = end;
+
+            if !have_indices.contains(&index) {
+                let (data, fragment_compressed) = self.maybe_compress_fragment(chunk);
+                crate::metrics::record_fragment_sent();
+                crate::metrics::record_bytes_sent(data.len() as u64);
+                self.feed_request(MasterRequest::FileFragment(FileFragment {
+                    file_id,
+                    index,
+                    data,
+                    compressed: fragment_compressed,
+                }))
+                .await?;
+                inflight.push_back(index);
+                report_progress(progress, offset as u64, total, start);
+                self.throttle(offset as u64, start).await;
+
+                if inflight.len() >= self.ack_window {
+                    self.flush().await?;
+                    if let Some(error_response) = self.await_one_ack(&mut inflight, progress).await? {
+                        return Ok(error_response);
+                    }
+                }
+            }
+            index += 1;
+        }
+        self.flush().await?;
+        while !inflight.is_empty() {
+            if let Some(error_response) = self.await_one_ack(&mut inflight, progress).await? {
+                return Ok(error_response);
+            }
+        }
+
+        self.send_request(MasterRequest::EndOfFile { file_id })
+            .await?;
+        let response = self.recv_response().await;
+        let response = self
+            .resolve_missing_fragments(file_id, &payload, response)
+            .await;
+        if matches!(response, Ok(SlaveResponse::Ok)) {
+            report_progress(progress, total, total, start);
+            crate::metrics::record_transfer_duration(start.elapsed());
+        }
+        response
+    }
+
+    /// Streams an arbitrary [`AsyncRead`] source (stdin, generated data, a
+    /// network socket, ...) as a file named `name`, without first buffering
+    /// it to disk. `len_hint`, if known, is only used for diagnostics; the
+    /// transfer itself doesn't depend on it.
+    ///
+    /// Since the content hash can't be known until the source is fully
+    /// read, it's computed on the fly and sent in a trailing
+    /// [`MasterRequest::FileHash`] frame after the last fragment, instead of
+    /// up front like [`Master::send_a_file`] does.
+    pub async fn send_reader(
+        &mut self,
+        name: impl Into<String>,
+        mut reader: impl AsyncRead + Unpin,
+        len_hint: Option<u64>,
+    ) -> Result<SlaveResponse> {
+        let file_name = name.into();
+        tracing::debug!(file_name, ?len_hint, "streaming file");
+
+        let file_id = self.allocate_file_id();
+
+        self.send_request(MasterRequest::FileMetadata(FileMetadata {
+            file_name,
+            file_id,
+            file_hash: None,
+            compressed: false,
+            modified: None,
+            unix_mode: None,
+            // Unknown until the reader is fully drained; the slave skips its
+            // disk-space preflight check when this is 0.
+            file_size: len_hint.unwrap_or(0),
+            fragment_size: self.content_size as u32,
+        }))
+        .await?;
+        // As in `do_send_a_file_resuming`, a rejected metadata means there's
+        // no `PendingFile` on the other end to feed fragments into.
+        match self.recv_response().await? {
+            SlaveResponse::Ok => {}
+            other => return Ok(other),
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; self.content_size];
+        let mut index = 0u32;
+        let mut inflight: VecDeque<u32> = VecDeque::with_capacity(self.ack_window);
+        loop {
+            self.check_cancelled()?;
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+
+            let chunk = Bytes::copy_from_slice(&buf[..read]);
+            let (data, fragment_compressed) = self.maybe_compress_fragment(chunk);
+            self.feed_request(MasterRequest::FileFragment(FileFragment {
+                file_id,
+                index,
+                data,
+                compressed: fragment_compressed,
+            }))
+            .await?;
+            inflight.push_back(index);
+            index += 1;
+
+            if inflight.len() >= self.ack_window {
+                self.flush().await?;
+                if let Some(error_response) = self.await_one_ack(&mut inflight, None).await? {
+                    return Ok(error_response);
+                }
+            }
+        }
+        self.flush().await?;
+        while !inflight.is_empty() {
+            if let Some(error_response) = self.await_one_ack(&mut inflight, None).await? {
+                return Ok(error_response);
+            }
+        }
+
+        self.send_request(MasterRequest::FileHash {
+            file_id,
+            file_hash: hasher.finalize().into(),
+        })
+        .await?;
+        self.recv_response().await?;
+
+        self.send_request(MasterRequest::EndOfFile { file_id })
+            .await?;
+        self.recv_response().await
+    }
+
+    /// Waits for one fragment acknowledgment and pops the oldest in-flight
+    /// index on success. [`SlaveResponse::Progress`] counts as success too,
+    /// updating `progress`'s receiver-confirmed byte count along the way.
+    /// Returns `Some(response)` if the slave reported an actual error, so
+    /// the caller can abort the transfer instead of pushing more fragments
+    /// into the void.
+    async fn await_one_ack(
+        &mut self,
+        inflight: &mut VecDeque<u32>,
+        progress: Option<&watch::Sender<Progress>>,
+    ) -> Result<Option<SlaveResponse>> {
+        match self.recv_response().await? {
+            SlaveResponse::Ok => {
+                inflight.pop_front();
+                Ok(None)
+            }
+            SlaveResponse::Progress { bytes_received, .. } => {
+                inflight.pop_front();
+                report_confirmed_bytes(progress, bytes_received);
+                Ok(None)
+            }
+            other => Ok(Some(other)),
+        }
+    }
+
+    /// If `response` is [`SlaveResponse::MissingFragments`], resends just
+    /// the reported indices out of `payload` and asks the slave to finalize
+    /// again, repeating (up to [`Self::MAX_MISSING_FRAGMENT_RETRIES`] times)
+    /// in case the slave reports more gaps. Returns whatever the slave
+    /// finally replies with, unchanged if `response` was never
+    /// `MissingFragments` in the first place.
+    async fn resolve_missing_fragments(
+        &mut self,
+        file_id: u32,
+        payload: &Bytes,
+        mut response: Result<SlaveResponse>,
+    ) -> Result<SlaveResponse> {
+        for _ in 0..Self::MAX_MISSING_FRAGMENT_RETRIES {
+            let Ok(SlaveResponse::MissingFragments { indices, .. }) = response else {
+                break;
+            };
+            for index in indices {
+                self.check_cancelled()?;
+                let start = index as usize * self.content_size;
+                if start >= payload.len() {
+                    continue;
+                }
+                let end = (start + self.content_size).min(payload.len());
+                let (data, fragment_compressed) =
+                    self.maybe_compress_fragment(payload.slice(start..end));
+                self.feed_request(MasterRequest::FileFragment(FileFragment {
+                    file_id,
+                    index,
+                    data,
+                    compressed: fragment_compressed,
+                }))
+                .await?;
+            }
+            self.flush().await?;
+            self.send_request(MasterRequest::EndOfFile { file_id })
+                .await?;
+            response = self.recv_response().await;
+        }
+        response
+    }
+
+    /// Sends a short text snippet or clipboard payload without creating a
+    /// file; see [`crate::slave::Slave::on_text`].
+    pub async fn send_text(&mut self, content: impl Into<String>) -> Result<SlaveResponse> {
+        self.send_request(MasterRequest::Text {
+            content: content.into(),
+        })
+        .await?;
+        self.recv_response().await
+    }
+
+    /// Sends an embedder-defined custom request; see
+    /// [`crate::slave::Slave::register_handler`].
+    pub async fn send_custom(&mut self, kind: u16, payload: Vec<u8>) -> Result<SlaveResponse> {
+        if kind < CUSTOM_KIND_MIN {
+            return Err(Error::CustomKindOutOfRange(kind));
+        }
+        self.send_request(MasterRequest::Custom { kind, payload })
+            .await?;
+        self.recv_response().await
+    }
+
+    /// Sends a `Ping` and waits for the matching `Pong`, subject to
+    /// [`MasterBuilder::idle_timeout`] like any other request, returning the
+    /// measured round-trip time. Useful as a liveness and latency probe
+    /// independent of [`Master::run_keepalive`], which calls this on a
+    /// timer instead of once on demand.
+    pub async fn ping(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        self.send_request(MasterRequest::Ping).await?;
+        match self.recv_response().await? {
+            SlaveResponse::Pong => Ok(start.elapsed()),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Sends a `Ping` and waits for `Pong`, subject to
+    /// [`MasterBuilder::idle_timeout`] like any other request. Intended to
+    /// be called on a timer (e.g. from a background task spawned alongside
+    /// this `Master`, in the same style as
+    /// [`crate::broadcast::Listener::async_scan_device`]) so an otherwise
+    /// idle connection stays alive and a dead slave is caught even when
+    /// nothing else is being sent.
+    pub async fn run_keepalive(&mut self, interval: Duration) -> Result<()> {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            match self.cancellation.clone() {
+                Some(token) => tokio::select! {
+                    _ = token.cancelled() => return Ok(()),
+                    _ = ticker.tick() => {}
+                },
+                None => {
+                    ticker.tick().await;
+                }
+            };
+            // A cancellation racing with this round trip surfaces as
+            // `Error::Cancelled` from `ping` too; treat it the same as
+            // catching it at the top of the loop instead of propagating it
+            // as a failure.
+            match self.ping().await {
+                Ok(_) => {}
+                Err(Error::Cancelled) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for Master {
+    /// Balances the `portal_active_connections` gauge incremented in
+    /// [`MasterBuilder::build`]; see [`crate::metrics`].
+    fn drop(&mut self) {
+        crate::metrics::connection_closed();
+    }
+}
+
+/// Sends `path` to every target in `targets` concurrently, each over its own
+/// already-connected stream and built from a clone of `builder`. The file is
+/// only read from disk once; each target still runs its own
+/// [`Master::handshake`] and negotiates its own compression and `file_id`,
+/// since those are inherently per-connection.
+///
+/// Returns one [`FanOutResult`] per target, in the same order as `targets`,
+/// regardless of whether that target's send succeeded — check
+/// `FanOutResult::response` for the individual outcomes. A target whose
+/// background task panicked reports [`Error::TransferTaskPanicked`] rather
+/// than failing the whole fan-out.
+pub async fn fan_out_file<T: Send + 'static>(
+    builder: MasterBuilder,
+    targets: Vec<(T, impl AsyncStream + 'static)>,
+    path: impl AsRef<Path>,
+) -> Result<Vec<FanOutResult<T>>> {
+    let path = path.as_ref();
+    let data = read_whole_file(path).await?;
+    let file_hash = hash_of(&data);
+    let file_size = data.len() as u64;
+    let file_name = file_name_of(path);
+    let (modified, unix_mode) = file_timestamps_of(path).await;
+
+    let mut handles = Vec::with_capacity(targets.len());
+    for (target, stream) in targets {
+        let mut master = builder.clone().build(stream);
+        let data = data.clone();
+        let file_name = file_name.clone();
+        let task = tokio::spawn(async move {
+            master.handshake().await?;
+            let (payload, compressed) = master.compress_payload(data)?;
+            master
+                .send_prepared_payload(
+                    file_name,
+                    payload,
+                    file_hash,
+                    compressed,
+                    file_size,
+                    modified,
+                    unix_mode,
+                    &HashSet::new(),
+                    None,
+                )
+                .await
+        });
+        handles.push((target, task));
+    }
+
+    let mut results = Vec::with_capacity(handles.len());
+    for (target, task) in handles {
+        let response = task.await.unwrap_or(Err(Error::TransferTaskPanicked));
+        results.push(FanOutResult { target, response });
+    }
+    Ok(results)
+}
+
+/// Establishes a connection by calling `connect` repeatedly according to
+/// `policy` until it succeeds, so a transient failure (a DNS hiccup, a
+/// connection refused while the slave is still starting up, ...) doesn't
+/// have to be handled by every caller individually.
+pub async fn connect_with_retry<S, E, F, Fut>(
+    policy: &RetryPolicy,
+    connect: F,
+) -> std::result::Result<S, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<S, E>>,
+{
+    policy.retry(connect).await
+}
+
+/// Sends `path` to a slave, retrying according to `policy` if the
+/// connection can't be established or drops partway through the transfer
+/// (a dropped Wi-Fi connection, for instance) instead of giving up after a
+/// single failure. `connect` is called again before every attempt after
+/// the first, since a dropped stream can't be reused; every attempt after
+/// the first resumes via [`Master::resume_a_file`] rather than restarting
+/// the file from scratch.
+///
+/// Only [`Error::is_transient`] failures are retried; anything else (an
+/// incompatible protocol version, a rejected file, ...) is returned right
+/// away since retrying wouldn't change the outcome.
+pub async fn send_a_file_with_retry<F, Fut>(
+    policy: &RetryPolicy,
+    builder: MasterBuilder,
+    path: impl AsRef<Path>,
+    mut connect: F,
+) -> Result<SlaveResponse>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<BoxedStream>>,
+{
+    let path = path.as_ref();
+    let mut last_err = None;
+    for attempt in 1..=policy.max_attempts_allowed() {
+        let stream = match connect().await {
+            Ok(stream) => stream,
+            Err(err) if err.is_transient() => {
+                last_err = Some(err);
+                crate::metrics::record_retry();
+                if attempt < policy.max_attempts_allowed() {
+                    tokio::time::sleep(policy.delay_for(attempt)).await;
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut master = builder.clone().build(stream);
+        let result = if attempt == 1 {
+            master.send_a_file(path).await
+        } else {
+            master.resume_a_file(path).await
+        };
+
+        match result {
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_transient() => {
+                last_err = Some(err);
+                crate::metrics::record_retry();
+                if attempt < policy.max_attempts_allowed() {
+                    tokio::time::sleep(policy.delay_for(attempt)).await;
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err.expect("max_attempts_allowed() >= 1 guarantees at least one iteration"))
+}
+
+/// A state change reported by [`send_a_file_resilient`] while it works
+/// through a dropped connection, for callers that want to surface
+/// something better than silence while it retries (a "reconnecting..."
+/// indicator, say) instead of only seeing the final result. Mirrors how
+/// [`Master::send_a_file_with_progress`] reports fragment progress on its
+/// own `watch` channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// Redialing the peer directly at its last known address.
+    Redialing { attempt: usize },
+    /// Direct redialing failed enough times that a broadcast rescan for
+    /// the peer (see [`crate::broadcast`]) is being tried instead, in case
+    /// its address changed.
+    Rediscovering,
+    /// A new connection was established and handshaked; resuming the
+    /// transfer from where the slave says it left off.
+    Resumed,
+    /// Every retry in the policy was used up; the transfer has given up.
+    GaveUp,
+}
+
+/// Like [`send_a_file_with_retry`], but with two connection strategies
+/// instead of one: `redial` (reconnecting directly, e.g. to the peer's
+/// last known address) is tried first; once it's failed more than half of
+/// `policy`'s attempts, `rediscover` is tried instead for the rest (e.g.
+/// listening for the peer's [`crate::broadcast`] announcement and dialing
+/// whatever address it's announcing now, in case a DHCP renewal moved it).
+/// Every reconnect re-[`Master::handshake`]s before resuming, so
+/// compression and protocol-version negotiation happen again on the new
+/// connection rather than carrying over stale values from the old one.
+///
+/// Each state change is sent on `events` as it happens; the receiver end
+/// can be dropped if the caller isn't interested; failures to send are
+/// ignored the same way progress updates are. As in
+/// [`send_a_file_with_retry`], only [`Error::is_transient`] failures are
+/// retried.
+pub async fn send_a_file_resilient<F, Fut, G, GFut>(
+    policy: &RetryPolicy,
+    builder: MasterBuilder,
+    path: impl AsRef<Path>,
+    mut redial: F,
+    mut rediscover: G,
+    events: watch::Sender<ReconnectEvent>,
+) -> Result<SlaveResponse>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<BoxedStream>>,
+    G: FnMut() -> GFut,
+    GFut: Future<Output = Result<BoxedStream>>,
+{
+    let path = path.as_ref();
+    let max_attempts = policy.max_attempts_allowed();
+    let redial_attempts = max_attempts.div_ceil(2).max(1);
+    let mut last_err = None;
+
+    for attempt in 1..=max_attempts {
+        let use_redial = attempt <= redial_attempts;
+        let _ = events.send(if use_redial {
+            ReconnectEvent::Redialing { attempt }
+        } else {
+            ReconnectEvent::Rediscovering
+        });
+
+        let stream = if use_redial {
+            redial().await
+        } else {
+            rediscover().await
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) if err.is_transient() => {
+                last_err = Some(err);
+                crate::metrics::record_retry();
+                if attempt < max_attempts {
+                    tokio::time::sleep(policy.delay_for(attempt)).await;
+                }
+                continue;
+            }
+            Err(err) => {
+                let _ = events.send(ReconnectEvent::GaveUp);
+                return Err(err);
+            }
+        };
+
+        let mut master = builder.clone().build(stream);
+        let result = if attempt == 1 {
+            master.send_a_file(path).await
+        } else {
+            match master.handshake().await {
+                Ok(_) => {
+                    let _ = events.send(ReconnectEvent::Resumed);
+                    master.resume_a_file(path).await
+                }
+                Err(err) => Err(err),
+            }
+        };
+
+        match result {
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_transient() => {
+                last_err = Some(err);
+                crate::metrics::record_retry();
+                if attempt < max_attempts {
+                    tokio::time::sleep(policy.delay_for(attempt)).await;
+                }
+            }
+            Err(err) => {
+                let _ = events.send(ReconnectEvent::GaveUp);
+                return Err(err);
+            }
+        }
+    }
+    let _ = events.send(ReconnectEvent::GaveUp);
+    Err(last_err.expect("max_attempts_allowed() >= 1 guarantees at least one iteration"))
+}
+
+/// Reads a whole file to send as one buffer. With the `mmap` feature, the
+/// file is memory-mapped on a blocking thread instead of read through a
+/// syscall per buffer-full, so the fragment-chunking loop slices pages in
+/// directly rather than copying through an intermediate `Vec`.
+#[cfg(feature = "mmap")]
+async fn read_whole_file(path: &Path) -> Result<Bytes> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || -> Result<Bytes> {
+        let file = std::fs::File::open(&path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Bytes::new());
+        }
+        // SAFETY: the mapped file isn't expected to be truncated by another
+        // process while we're sending it; a concurrent truncation could
+        // raise SIGBUS on access, which is the inherent risk of mmap-ing a
+        // file we don't otherwise hold a lock on.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Bytes::from_owner(mmap))
+    })
+    .await
+    .map_err(|err| Error::Io(std::io::Error::other(err)))?
+}
+
+#[cfg(not(feature = "mmap"))]
+async fn read_whole_file(path: &Path) -> Result<Bytes> {
+    Ok(tokio::fs::read(path).await?.into())
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `path`'s components relative to `root`, joined with `/` regardless of
+/// platform, for use as a [`FileMetadata::file_name`] or
+/// [`SymlinkEntry::path`] during a directory transfer.
+fn relative_name(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Reads `path`'s modification time and (on Unix) permission bits, so the
+/// slave can restore them after writing. Both are `None` if the metadata
+/// can't be read, rather than failing the whole transfer.
+async fn file_timestamps_of(path: &Path) -> (Option<i64>, Option<u32>) {
+    let Ok(metadata) = tokio::fs::metadata(path).await else {
+        return (None, None);
+    };
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64);
+
+    #[cfg(unix)]
+    let unix_mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode())
+    };
+    #[cfg(not(unix))]
+    let unix_mode = None;
+
+    (modified, unix_mode)
+}
+
+fn hash_of(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Publishes a [`Progress`] update on `progress`, if one was supplied. No-op
+/// if the receiving end has been dropped.
+fn report_progress(
+    progress: Option<&watch::Sender<Progress>>,
+    bytes_sent: u64,
+    total: u64,
+    start: Instant,
+) {
+    let Some(progress) = progress else {
+        return;
+    };
+    let elapsed = start.elapsed().as_secs_f64();
+    let rate = if elapsed > 0.0 {
+        bytes_sent as f64 / elapsed
+    } else {
+        0.0
+    };
+    progress.send_modify(|snapshot| {
+        snapshot.bytes_sent = bytes_sent;
+        snapshot.total = total;
+        snapshot.rate = rate;
+    });
+}
+
+/// Records the slave's own received-bytes total from a
+/// [`SlaveResponse::Progress`] ack, without disturbing the locally-tracked
+/// fields [`report_progress`] maintains.
+fn report_confirmed_bytes(progress: Option<&watch::Sender<Progress>>, bytes_received: u64) {
+    let Some(progress) = progress else {
+        return;
+    };
+    progress.send_modify(|snapshot| snapshot.bytes_confirmed = bytes_received);
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn negotiates_compression_and_delivers_a_compressible_file() {
+        let dir = tempdir();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "a".repeat(64 * 1024)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let features = master.handshake().await.unwrap();
+        assert!(features.iter().any(|f| f == "zstd"));
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("source.txt")).await.unwrap();
+        assert_eq!(received, "a".repeat(64 * 1024).into_bytes());
+    }
+
+    fn tempdir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-compression-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod lz4_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn delivers_a_file_sent_with_lz4_fragments() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-lz4-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "b".repeat(64 * 1024)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().lz4_fragments(true).build(stream);
+        master.handshake().await.unwrap();
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("source.txt")).await.unwrap();
+        assert_eq!(received, "b".repeat(64 * 1024).into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn sends_every_file_in_the_batch() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-batch-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = dir.join("first.txt");
+        let second = dir.join("second.txt");
+        tokio::fs::write(&first, "one").await.unwrap();
+        tokio::fs::write(&second, "two").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let batch = master.send_files(vec![first, second]).await;
+        assert!(batch.all_ok());
+        assert_eq!(batch.files.len(), 2);
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(tokio::fs::read(dir.join("first.txt")).await.unwrap(), b"one");
+        assert_eq!(tokio::fs::read(dir.join("second.txt")).await.unwrap(), b"two");
+    }
+}
+
+#[cfg(test)]
+mod interleave_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn delivers_every_file_with_fragments_interleaved() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-interleave-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let big = dir.join("big.txt");
+        let small = dir.join("small.txt");
+        tokio::fs::write(&big, "b".repeat(10_000)).await.unwrap();
+        tokio::fs::write(&small, "s").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().max_content_size(100).build(stream);
+        let batch = master
+            .send_files_interleaved(vec![big.clone(), small.clone()])
+            .await;
+        assert!(batch.all_ok());
+        assert_eq!(batch.files.len(), 2);
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read(dir.join("big.txt")).await.unwrap(),
+            "b".repeat(10_000).into_bytes()
+        );
+        assert_eq!(tokio::fs::read(dir.join("small.txt")).await.unwrap(), b"s");
+    }
+
+    #[tokio::test]
+    async fn tolerates_an_empty_file_in_the_batch() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-interleave-empty-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let empty = dir.join("empty.txt");
+        let normal = dir.join("normal.txt");
+        tokio::fs::write(&empty, b"").await.unwrap();
+        tokio::fs::write(&normal, "hello").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let batch = master
+            .send_files_interleaved(vec![empty, normal])
+            .await;
+        assert!(batch.all_ok());
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read(dir.join("empty.txt")).await.unwrap(),
+            b""
+        );
+        assert_eq!(
+            tokio::fs::read(dir.join("normal.txt")).await.unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn delivers_two_files_with_identical_content_without_colliding() {
+        // Same content means the same hash, so a `file_id` derived from it
+        // (as this crate used to do) would be identical for both, letting
+        // the second file's fragments land in the first one's `PendingFile`.
+        // An allocated `file_id` keeps them distinct regardless of content.
+        let dir = std::env::temp_dir().join(format!(
+            "portal-interleave-identical-content-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = dir.join("first.txt");
+        let second = dir.join("second.txt");
+        tokio::fs::write(&first, "same content".repeat(1000))
+            .await
+            .unwrap();
+        tokio::fs::write(&second, "same content".repeat(1000))
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().max_content_size(100).build(stream);
+        let batch = master
+            .send_files_interleaved(vec![first.clone(), second.clone()])
+            .await;
+        assert!(batch.all_ok());
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read(dir.join("first.txt")).await.unwrap(),
+            "same content".repeat(1000).into_bytes()
+        );
+        assert_eq!(
+            tokio::fs::read(dir.join("second.txt")).await.unwrap(),
+            "same content".repeat(1000).into_bytes()
+        );
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use tokio::net::TcpListener;
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn reports_progress_up_to_completion() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-progress-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "p".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().max_content_size(100).build(stream);
+        let (tx, mut rx) = watch::channel(Progress::default());
+        let response = master.send_a_file_with_progress(&src, tx).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        rx.changed().await.unwrap();
+        let last = *rx.borrow_and_update();
+        assert_eq!(last.bytes_sent, 1000);
+        assert_eq!(last.total, 1000);
+
+        drop(master);
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_receiver_confirmed_progress() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-confirmed-progress-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "p".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            // Every accepted fragment gets a `Progress` ack instead of
+            // waiting for the default interval to elapse.
+            slave.set_progress_interval(1, u64::MAX);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().max_content_size(100).build(stream);
+        let (tx, mut rx) = watch::channel(Progress::default());
+        let response = master.send_a_file_with_progress(&src, tx).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        rx.changed().await.unwrap();
+        let last = *rx.borrow_and_update();
+        assert_eq!(last.bytes_confirmed, 1000);
+
+        drop(master);
+        accept.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn paces_a_transfer_to_the_configured_rate() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-rate-limit-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "p".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new()
+            .max_content_size(100)
+            .rate_limit(2000)
+            .build(stream);
+
+        let start = Instant::now();
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+        // 1000 bytes at 2000 bytes/sec should take roughly 500ms; a generous
+        // lower bound catches a limiter that isn't throttling at all without
+        // making the test sensitive to scheduler jitter.
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        drop(master);
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn set_rate_limit_overrides_the_builder_default_per_task() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-rate-limit-override-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "p".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new()
+            .max_content_size(100)
+            .rate_limit(2000)
+            .build(stream);
+        master.set_rate_limit(None);
+
+        let start = Instant::now();
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        drop(master);
+        accept.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod flush_watermark_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn delivers_a_file_with_a_low_flush_watermark() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-flush-watermark-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "p".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        // A watermark below the ack window exercises an in-loop flush as
+        // well as the drain-loop flush, instead of the single flush a
+        // watermark at or above the ack window would settle for.
+        let mut master = MasterBuilder::new()
+            .max_content_size(100)
+            .ack_window(8)
+            .flush_watermark(3)
+            .build(stream);
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        let received = dir.join("source.txt");
+        assert_eq!(tokio::fs::read(&received).await.unwrap(), vec![b'p'; 1000]);
+
+        drop(master);
+        accept.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod reader_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn delivers_a_file_streamed_from_an_in_memory_reader() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-reader-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let content = "streamed content".repeat(1024);
+        let source = dir.join("source.txt");
+        tokio::fs::write(&source, &content).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let reader = tokio::fs::File::open(&source).await.unwrap();
+        let response = master
+            .send_reader("streamed.txt", reader, Some(content.len() as u64))
+            .await
+            .unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("streamed.txt")).await.unwrap();
+        assert_eq!(received, content.into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod text_tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn surfaces_text_to_the_registered_handler() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let received = Arc::new(Mutex::new(None));
+        let received_in_handler = received.clone();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.on_text(Arc::new(move |content| {
+                *received_in_handler.lock().unwrap() = Some(content);
+            }));
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let response = master.send_text("hello from the clipboard").await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(
+            received.lock().unwrap().as_deref(),
+            Some("hello from the clipboard")
+        );
+    }
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod mmap_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn delivers_a_file_read_via_mmap() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-mmap-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "m".repeat(64 * 1024)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("source.txt")).await.unwrap();
+        assert_eq!(received, "m".repeat(64 * 1024).into_bytes());
+    }
+
+    #[tokio::test]
+    async fn delivers_an_empty_file_read_via_mmap() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-mmap-empty-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("empty.txt");
+        tokio::fs::write(&src, b"").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("empty.txt")).await.unwrap();
+        assert!(received.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod content_size_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn negotiates_down_to_the_slaves_advertised_limit() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-content-size-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "x".repeat(1000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.set_max_content_size(100);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        master.handshake().await.unwrap();
+        assert_eq!(master.content_size, 100);
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = tokio::fs::read(dir.join("source.txt")).await.unwrap();
+        assert_eq!(received, "x".repeat(1000).into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod directory_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn recreates_the_directory_structure_on_the_slave() {
+        let base = std::env::temp_dir().join(format!(
+            "portal-directory-test-{:?}",
+            std::thread::current().id()
+        ));
+        let src_dir = base.join("src");
+        let recv_dir = base.join("recv");
+        std::fs::create_dir_all(src_dir.join("nested")).unwrap();
+        std::fs::create_dir_all(&recv_dir).unwrap();
+
+        tokio::fs::write(src_dir.join("top.txt"), "top")
+            .await
+            .unwrap();
+        tokio::fs::write(src_dir.join("nested").join("deep.txt"), "deep")
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_recv_dir = recv_dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_recv_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let batch = master.send_directory(src_dir).await;
+        assert!(batch.all_ok());
+        assert_eq!(batch.files.len(), 2);
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read(recv_dir.join("top.txt")).await.unwrap(),
+            b"top"
+        );
+        assert_eq!(
+            tokio::fs::read(recv_dir.join("nested").join("deep.txt"))
+                .await
+                .unwrap(),
+            b"deep"
+        );
+    }
+}
+
+#[cfg(all(test, unix))]
+mod symlink_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn recreates_a_symlink_when_policy_is_recreate() {
+        let base = std::env::temp_dir().join(format!(
+            "portal-symlink-test-{:?}",
+            std::thread::current().id()
+        ));
+        let src_dir = base.join("src");
+        let recv_dir = base.join("recv");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        std::fs::create_dir_all(&recv_dir).unwrap();
+
+        tokio::fs::write(src_dir.join("target.txt"), "hello")
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(src_dir.join("link.txt"));
+        std::os::unix::fs::symlink("target.txt", src_dir.join("link.txt")).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_recv_dir = recv_dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_recv_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new()
+            .symlink_policy(SymlinkPolicy::Recreate)
+            .build(stream);
+        let batch = master.send_directory(src_dir).await;
+        assert!(batch.all_ok());
+        assert_eq!(batch.files.len(), 2);
+
+        drop(master);
+        accept.await.unwrap();
+
+        let link = recv_dir.join("link.txt");
+        let link_meta = std::fs::symlink_metadata(&link).unwrap();
+        assert!(link_meta.file_type().is_symlink());
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            std::path::PathBuf::from("target.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_symlinks_by_default() {
+        let base = std::env::temp_dir().join(format!(
+            "portal-symlink-skip-test-{:?}",
+            std::thread::current().id()
+        ));
+        let src_dir = base.join("src");
+        let recv_dir = base.join("recv");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        std::fs::create_dir_all(&recv_dir).unwrap();
+
+        tokio::fs::write(src_dir.join("target.txt"), "hello")
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(src_dir.join("link.txt"));
+        std::os::unix::fs::symlink("target.txt", src_dir.join("link.txt")).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_recv_dir = recv_dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_recv_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let batch = master.send_directory(src_dir).await;
+        assert!(batch.all_ok());
+        assert_eq!(batch.files.len(), 1);
+
+        drop(master);
+        accept.await.unwrap();
+
+        assert!(!recv_dir.join("link.txt").exists());
+    }
+}
+
+#[cfg(test)]
+mod keepalive_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn surfaces_peer_unresponsive_when_the_slave_goes_quiet() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            // Accept the connection but never reply, simulating a hung slave.
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new()
+            .idle_timeout(Duration::from_millis(50))
+            .build(stream);
+
+        let err = master.ping().await;
+        assert!(matches!(err, Err(Error::PeerUnresponsive)));
+
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn keeps_a_responsive_connection_alive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new()
+            .idle_timeout(Duration::from_secs(5))
+            .build(stream);
+
+        tokio::time::timeout(Duration::from_millis(200), async {
+            master.run_keepalive(Duration::from_millis(20)).await
+        })
+        .await
+        .expect_err("run_keepalive should loop forever against a responsive slave");
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn slave_surfaces_peer_unresponsive_when_the_master_goes_quiet() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_idle_timeout(Duration::from_millis(50));
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let _master = Master::from_stream(stream);
+
+        let err = accept.await.unwrap();
+        assert!(matches!(err, Err(Error::PeerUnresponsive)));
+    }
+
+    #[tokio::test]
+    async fn ping_waits_for_pong_and_reports_the_round_trip_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+
+        let rtt = master.ping().await.unwrap();
+        assert!(rtt < Duration::from_secs(1));
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod cancellation_tests {
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn aborts_a_transfer_once_cancelled() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-cancel-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "c".repeat(10_000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            let _ = slave.recv_request_thread().await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let token = CancellationToken::new();
+        let mut master = MasterBuilder::new()
+            .max_content_size(100)
+            .cancellation_token(token.clone())
+            .build(stream);
+        token.cancel();
+
+        let err = master.send_a_file(&src).await;
+        assert!(matches!(err, Err(Error::Cancelled)));
+
+        drop(master);
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn stops_run_keepalive_once_cancelled() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            let _ = slave.recv_request_thread().await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let token = CancellationToken::new();
+        let mut master = MasterBuilder::new()
+            .cancellation_token(token.clone())
+            .build(stream);
+
+        let keepalive = tokio::spawn(async move { master.run_keepalive(Duration::from_millis(10)).await });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        token.cancel();
+
+        let result = tokio::time::timeout(Duration::from_secs(1), keepalive)
+            .await
+            .expect("run_keepalive should return promptly once cancelled")
+            .unwrap();
+        assert!(matches!(result, Ok(())));
+
+        accept.abort();
+    }
+}
+
+#[cfg(test)]
+mod finalize_ack_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn surfaces_the_slaves_terminal_response_not_just_a_plain_ok() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-finalize-ack-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello world").await.unwrap();
+
+        // A file sitting where the slave expects its output *directory* to
+        // be makes `create_dir_all` fail once the transfer tries to save,
+        // well after every fragment has already been acked.
+        let blocked_output_dir = dir.join("not-a-directory");
+        tokio::fs::write(&blocked_output_dir, "occupied").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(blocked_output_dir);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().build(stream);
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::CannotSaveFile { .. }));
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod metadata_rejection_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn stops_at_a_rejected_metadata_instead_of_streaming_fragments_into_the_void() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-metadata-rejection-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        // Large enough to span several ack windows, so a master that ignores
+        // the metadata ack and keeps feeding fragments would, before getting
+        // back the `FileTooLarge` it should have surfaced immediately, first
+        // see a `FileIdNotFound` for one of those orphaned fragments instead.
+        tokio::fs::write(&src, "x".repeat(50_000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_max_file_size(10);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = MasterBuilder::new().max_content_size(100).build(stream);
+
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::FileTooLarge { .. }));
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod spawn_send_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn returns_before_the_transfer_finishes_instead_of_blocking_the_caller() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-spawn-send-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello world").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // The slave only starts answering once it's slept well past the
+        // point where a caller that *blocked* on the transfer (as with
+        // `send_a_file`) would have already returned, so measuring how long
+        // `spawn_send_a_file` itself takes to return proves it didn't wait
+        // on this.
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let master = MasterBuilder::new().build(stream);
+
+        let started = Instant::now();
+        let handle = master.spawn_send_a_file(src);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        let response = handle.join().await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        accept.await.unwrap().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod fan_out_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    async fn spawn_slave(output_dir: PathBuf) -> (u16, tokio::task::JoinHandle<Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(output_dir);
+            slave.recv_request_thread().await
+        });
+        (port, accept)
+    }
+
+    #[tokio::test]
+    async fn sends_one_file_to_every_target_independently() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-fan-out-test-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let src_dir = dir.join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        let src = src_dir.join("source.txt");
+        tokio::fs::write(&src, "hello fan-out").await.unwrap();
+
+        let out_a = dir.join("out-a");
+        let out_b = dir.join("out-b");
+        std::fs::create_dir_all(&out_a).unwrap();
+        std::fs::create_dir_all(&out_b).unwrap();
+
+        let (port_a, accept_a) = spawn_slave(out_a.clone()).await;
+        let (port_b, accept_b) = spawn_slave(out_b.clone()).await;
+
+        let stream_a = TcpStream::connect(("127.0.0.1", port_a)).await.unwrap();
+        let stream_b = TcpStream::connect(("127.0.0.1", port_b)).await.unwrap();
+
+        let results = fan_out_file(
+            MasterBuilder::new(),
+            vec![("a", stream_a), ("b", stream_b)],
+            &src,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            assert!(matches!(result.response, Ok(SlaveResponse::Ok)));
+        }
+
+        accept_a.await.unwrap().unwrap();
+        accept_b.await.unwrap().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(out_a.join("source.txt")).unwrap(),
+            "hello fan-out"
+        );
+        assert_eq!(
+            std::fs::read_to_string(out_b.join("source.txt")).unwrap(),
+            "hello fan-out"
+        );
+    }
+}
+
+#[cfg(test)]
+mod retry_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn retries_past_transient_connect_failures_before_succeeding() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-retry-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello retry").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await
+        });
+
+        let attempts = AtomicUsize::new(0);
+        let policy = RetryPolicy::new()
+            .max_attempts(5)
+            .base_delay(Duration::from_millis(1));
+
+        let response = send_a_file_with_retry(&policy, MasterBuilder::new(), &src, || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                if attempt < 3 {
+                    Err(Error::ConnectionClosed)
+                } else {
+                    Ok(Box::new(TcpStream::connect(addr).await.unwrap()) as BoxedStream)
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(response, SlaveResponse::Ok));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        accept.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_immediately_on_a_non_transient_connect_error() {
+        let attempts = AtomicUsize::new(0);
+        let policy = RetryPolicy::new()
+            .max_attempts(5)
+            .base_delay(Duration::from_millis(1));
+
+        let result = send_a_file_with_retry(&policy, MasterBuilder::new(), "unused.txt", || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                Err(Error::IncompatibleProtocol {
+                    ours: PROTOCOL_VERSION,
+                    theirs: PROTOCOL_VERSION + 1,
+                })
+            }
+        })
+        .await;
+
+        assert!(matches!(result, Err(Error::IncompatibleProtocol { .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[cfg(test)]
+mod resilient_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use crate::slave::Slave;
+
+    /// `watch` only ever holds the latest value, so to see every state a
+    /// test cares about we collect them into a plain `Vec` via a background
+    /// task that drains `changed()` as they arrive. Returns the shared
+    /// history and a handle that resolves once the sender side is dropped,
+    /// so callers can await it to be sure every event has landed.
+    fn collect_events(
+        mut rx: watch::Receiver<ReconnectEvent>,
+    ) -> (Arc<Mutex<Vec<ReconnectEvent>>>, tokio::task::JoinHandle<()>) {
+        let seen = Arc::new(Mutex::new(vec![*rx.borrow()]));
+        let seen_clone = seen.clone();
+        let handle = tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                seen_clone.lock().unwrap().push(*rx.borrow());
+            }
+        });
+        (seen, handle)
+    }
+
+    #[tokio::test]
+    async fn redials_past_transient_drops_then_resumes_successfully() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-resilient-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello resilient").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await
+        });
+
+        let redial_attempts = AtomicUsize::new(0);
+        let policy = RetryPolicy::new()
+            .max_attempts(5)
+            .base_delay(Duration::from_millis(1));
+        let (tx, rx) = watch::channel(ReconnectEvent::Redialing { attempt: 1 });
+        let (events, events_task) = collect_events(rx);
+
+        let response = send_a_file_resilient(
+            &policy,
+            MasterBuilder::new(),
+            &src,
+            || {
+                let attempt = redial_attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    if attempt < 2 {
+                        Err(Error::ConnectionClosed)
+                    } else {
+                        Ok(Box::new(TcpStream::connect(addr).await.unwrap()) as BoxedStream)
+                    }
+                }
+            },
+            || async { panic!("rediscover should not be needed in this test") },
+            tx,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(response, SlaveResponse::Ok));
+        accept.await.unwrap().unwrap();
+        events_task.await.unwrap();
+
+        assert!(events.lock().unwrap().contains(&ReconnectEvent::Resumed));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_rediscover_once_redial_attempts_are_exhausted() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-resilient-rediscover-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello rediscover").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await
+        });
+
+        let policy = RetryPolicy::new()
+            .max_attempts(4)
+            .base_delay(Duration::from_millis(1));
+        let (tx, rx) = watch::channel(ReconnectEvent::Redialing { attempt: 1 });
+        let (events, events_task) = collect_events(rx);
+
+        let response = send_a_file_resilient(
+            &policy,
+            MasterBuilder::new(),
+            &src,
+            || async { Err(Error::ConnectionClosed) },
+            || async { Ok(Box::new(TcpStream::connect(addr).await.unwrap()) as BoxedStream) },
+            tx,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(response, SlaveResponse::Ok));
+        accept.await.unwrap().unwrap();
+        events_task.await.unwrap();
+
+        assert!(events
+            .lock()
+            .unwrap()
+            .contains(&ReconnectEvent::Rediscovering));
+    }
+
+    #[tokio::test]
+    async fn gives_up_and_reports_gave_up_on_a_non_transient_error() {
+        let policy = RetryPolicy::new()
+            .max_attempts(5)
+            .base_delay(Duration::from_millis(1));
+        let (tx, rx) = watch::channel(ReconnectEvent::Redialing { attempt: 1 });
+        let (events, events_task) = collect_events(rx);
+
+        let result = send_a_file_resilient(
+            &policy,
+            MasterBuilder::new(),
+            "unused.txt",
+            || async {
+                Err(Error::IncompatibleProtocol {
+                    ours: PROTOCOL_VERSION,
+                    theirs: PROTOCOL_VERSION + 1,
+                })
+            },
+            || async { panic!("rediscover should not be needed in this test") },
+            tx,
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::IncompatibleProtocol { .. })));
+        events_task.await.unwrap();
+        assert!(events.lock().unwrap().contains(&ReconnectEvent::GaveUp));
+    }
+}
+
+#[cfg(test)]
+mod connect_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn dials_handshakes_and_sends_a_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-connect-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello connect").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await
+        });
+
+        let mut master = Master::connect(addr, Duration::from_secs(1)).await.unwrap();
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("source.txt")).unwrap(),
+            "hello connect"
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_if_nothing_is_listening_on_the_address() {
+        // Port 0 never accepts connections, so dialing it either fails
+        // immediately or hangs, depending on the platform; either way
+        // `connect` should not take longer than the configured timeout.
+        let result = Master::connect(("127.0.0.1", 0), Duration::from_millis(200)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn applies_builder_settings_to_a_dialed_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.recv_request_thread().await
+        });
+
+        let master = MasterBuilder::new()
+            .nodelay(false)
+            .ack_window(4)
+            .connect(addr, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(master.ack_window, 4);
+
+        drop(master);
+        accept.await.unwrap().unwrap();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod metadata_tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn preserves_mtime_and_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-metadata-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello").await.unwrap();
+        std::fs::set_permissions(&src, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_000_000, 0);
+        filetime::set_file_mtime(&src, mtime).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave_dir = dir.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+        let response = master.send_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        drop(master);
+        accept.await.unwrap();
+
+        let received = dir.join("source.txt");
+        let received_meta = std::fs::metadata(&received).unwrap();
+        assert_eq!(received_meta.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&received_meta).unix_seconds(),
+            1_000_000
+        );
+    }
+}
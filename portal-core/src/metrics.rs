@@ -0,0 +1,65 @@
+//! Optional instrumentation via the [`metrics`] crate facade (`metrics`
+//! feature). Recording a sample here only has an effect if the embedding
+//! application also installs a recorder (e.g.
+//! `metrics-exporter-prometheus`) to collect and expose it; without one
+//! installed, or with the feature disabled entirely, every function below
+//! is a no-op, so call sites never need to be wrapped in
+//! `#[cfg(feature = "metrics")]` themselves.
+
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_bytes_sent(bytes: u64) {
+    metrics::counter!("portal_bytes_sent_total").increment(bytes);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_bytes_sent(_bytes: u64) {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_bytes_received(bytes: u64) {
+    metrics::counter!("portal_bytes_received_total").increment(bytes);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_bytes_received(_bytes: u64) {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_fragment_sent() {
+    metrics::counter!("portal_fragments_sent_total").increment(1);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_fragment_sent() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_fragment_received() {
+    metrics::counter!("portal_fragments_received_total").increment(1);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_fragment_received() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_retry() {
+    metrics::counter!("portal_retries_total").increment(1);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_retry() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn connection_opened() {
+    metrics::gauge!("portal_active_connections").increment(1.0);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn connection_opened() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn connection_closed() {
+    metrics::gauge!("portal_active_connections").decrement(1.0);
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn connection_closed() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_transfer_duration(duration: Duration) {
+    metrics::histogram!("portal_transfer_duration_seconds").record(duration.as_secs_f64());
+}
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_transfer_duration(_duration: Duration) {}
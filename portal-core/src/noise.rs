@@ -0,0 +1,372 @@
+//! Optional lightweight encrypted transport via the Noise XX pattern
+//! (`noise` feature), as an alternative to the heavier [`crate::tls`]
+//! module. Encryption happens at the byte-stream level via [`NoiseStream`],
+//! so it plugs into [`crate::master::Master`] and [`crate::slave::Slave`]
+//! exactly like a plain or TLS-wrapped `TcpStream`.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use snow::{Keypair, TransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::error::{Error, Result};
+use crate::master::Master;
+use crate::slave::Slave;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Noise caps a single transport message at 65535 bytes, 16 of which are
+/// the AEAD tag.
+const MAX_MESSAGE_LEN: usize = 65535;
+const MAX_PAYLOAD_LEN: usize = MAX_MESSAGE_LEN - 16;
+
+fn to_io_error(err: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Generates a fresh static Curve25519 keypair for use as a Noise identity.
+pub fn generate_keypair() -> Result<Keypair> {
+    snow::Builder::new(NOISE_PARAMS.parse().unwrap())
+        .generate_keypair()
+        .map_err(|e| Error::Noise(e.to_string()))
+}
+
+async fn write_len_prefixed(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    stream.write_u16(data.len() as u16).await?;
+    stream.write_all(data).await
+}
+
+async fn read_len_prefixed(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let len = stream.read_u16().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Runs the Noise XX handshake and returns the resulting transport state
+/// along with the static public key the peer presented, so callers that
+/// care about peer identity (see [`crate::trust`]) can check it without
+/// having to duplicate the handshake themselves.
+async fn handshake(
+    stream: &mut TcpStream,
+    local_private_key: &[u8],
+    is_initiator: bool,
+) -> Result<(TransportState, Vec<u8>)> {
+    let builder = snow::Builder::new(NOISE_PARAMS.parse().unwrap()).local_private_key(local_private_key);
+    let mut handshake_state = if is_initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+    .map_err(|e| Error::Noise(e.to_string()))?;
+
+    let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+    if is_initiator {
+        let len = handshake_state
+            .write_message(&[], &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+        write_len_prefixed(stream, &buf[..len]).await?;
+
+        let msg = read_len_prefixed(stream).await?;
+        handshake_state
+            .read_message(&msg, &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+
+        let len = handshake_state
+            .write_message(&[], &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+        write_len_prefixed(stream, &buf[..len]).await?;
+    } else {
+        let msg = read_len_prefixed(stream).await?;
+        handshake_state
+            .read_message(&msg, &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+
+        let len = handshake_state
+            .write_message(&[], &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+        write_len_prefixed(stream, &buf[..len]).await?;
+
+        let msg = read_len_prefixed(stream).await?;
+        handshake_state
+            .read_message(&msg, &mut buf)
+            .map_err(|e| Error::Noise(e.to_string()))?;
+    }
+
+    let remote_static = handshake_state
+        .get_remote_static()
+        .ok_or_else(|| Error::Noise("peer did not present a static public key".to_string()))?
+        .to_vec();
+
+    let transport = handshake_state
+        .into_transport_mode()
+        .map_err(|e| Error::Noise(e.to_string()))?;
+
+    Ok((transport, remote_static))
+}
+
+enum ReadState {
+    Len { buf: [u8; 2], filled: usize },
+    Body { buf: Vec<u8>, filled: usize },
+}
+
+/// Wraps a `TcpStream` (or anything `AsyncRead + AsyncWrite + Unpin`) with a
+/// completed Noise transport session, encrypting every byte written and
+/// decrypting every byte read.
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: TransportState,
+    read_state: ReadState,
+    plaintext_in: Vec<u8>,
+    plaintext_in_pos: usize,
+    pending_out: Vec<u8>,
+}
+
+impl<S> NoiseStream<S> {
+    fn new(inner: S, transport: TransportState) -> Self {
+        Self {
+            inner,
+            transport,
+            read_state: ReadState::Len {
+                buf: [0; 2],
+                filled: 0,
+            },
+            plaintext_in: Vec::new(),
+            plaintext_in_pos: 0,
+            pending_out: Vec::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.plaintext_in_pos < this.plaintext_in.len() {
+                let available = &this.plaintext_in[this.plaintext_in_pos..];
+                let n = available.len().min(buf.remaining());
+                buf.put_slice(&available[..n]);
+                this.plaintext_in_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            match &mut this.read_state {
+                ReadState::Len { buf: len_buf, filled } => {
+                    let mut read_buf = ReadBuf::new(&mut len_buf[*filled..]);
+                    match Pin::new(&mut this.inner).poll_read(cx, &mut read_buf)? {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(()) => {
+                            let n = read_buf.filled().len();
+                            if n == 0 {
+                                return Poll::Ready(Ok(()));
+                            }
+                            *filled += n;
+                            if *filled == len_buf.len() {
+                                let len = u16::from_be_bytes(*len_buf) as usize;
+                                this.read_state = ReadState::Body {
+                                    buf: vec![0u8; len],
+                                    filled: 0,
+                                };
+                            }
+                        }
+                    }
+                }
+                ReadState::Body { buf: body_buf, filled } => {
+                    let mut read_buf = ReadBuf::new(&mut body_buf[*filled..]);
+                    match Pin::new(&mut this.inner).poll_read(cx, &mut read_buf)? {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(()) => {
+                            let n = read_buf.filled().len();
+                            if n == 0 {
+                                return Poll::Ready(Err(io::Error::new(
+                                    io::ErrorKind::UnexpectedEof,
+                                    "connection closed mid Noise frame",
+                                )));
+                            }
+                            *filled += n;
+                            if *filled == body_buf.len() {
+                                let mut plaintext = vec![0u8; body_buf.len()];
+                                let plain_len = this
+                                    .transport
+                                    .read_message(body_buf, &mut plaintext)
+                                    .map_err(to_io_error)?;
+                                plaintext.truncate(plain_len);
+                                this.plaintext_in = plaintext;
+                                this.plaintext_in_pos = 0;
+                                this.read_state = ReadState::Len {
+                                    buf: [0; 2],
+                                    filled: 0,
+                                };
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while !this.pending_out.is_empty() {
+            match Pin::new(&mut this.inner).poll_write(cx, &this.pending_out)? {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(n) => {
+                    this.pending_out.drain(..n);
+                }
+            }
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let chunk = &buf[..buf.len().min(MAX_PAYLOAD_LEN)];
+        let mut ciphertext = vec![0u8; chunk.len() + 16];
+        let len = this
+            .transport
+            .write_message(chunk, &mut ciphertext)
+            .map_err(to_io_error)?;
+        ciphertext.truncate(len);
+
+        this.pending_out.reserve(2 + len);
+        this.pending_out.extend_from_slice(&(len as u16).to_be_bytes());
+        this.pending_out.extend_from_slice(&ciphertext);
+
+        while !this.pending_out.is_empty() {
+            match Pin::new(&mut this.inner).poll_write(cx, &this.pending_out)? {
+                Poll::Pending => break,
+                Poll::Ready(n) => {
+                    this.pending_out.drain(..n);
+                }
+            }
+        }
+        Poll::Ready(Ok(chunk.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while !this.pending_out.is_empty() {
+            match Pin::new(&mut this.inner).poll_write(cx, &this.pending_out)? {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(n) => {
+                    this.pending_out.drain(..n);
+                }
+            }
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl Master {
+    /// Performs a Noise XX handshake as the initiator over `stream`, then
+    /// returns a `Master` whose traffic is encrypted and authenticated.
+    pub async fn connect_noise(mut stream: TcpStream, local_keypair: &Keypair) -> Result<Self> {
+        let (transport, _remote_static) = handshake(&mut stream, &local_keypair.private, true).await?;
+        Ok(Self::from_stream(NoiseStream::new(stream, transport)))
+    }
+
+    /// Like [`Self::connect_noise`], but also returns the static public key
+    /// the peer presented during the handshake, so the caller can feed it
+    /// to a [`crate::trust::TrustStore`] once [`Self::handshake`] has
+    /// learned the peer's [`crate::identity::DeviceId`].
+    pub async fn connect_noise_with_key(
+        mut stream: TcpStream,
+        local_keypair: &Keypair,
+    ) -> Result<(Self, Vec<u8>)> {
+        let (transport, remote_static) = handshake(&mut stream, &local_keypair.private, true).await?;
+        Ok((Self::from_stream(NoiseStream::new(stream, transport)), remote_static))
+    }
+}
+
+impl Slave {
+    /// Performs a Noise XX handshake as the responder over `stream`, then
+    /// returns a `Slave` whose traffic is encrypted and authenticated.
+    pub async fn accept_noise(mut stream: TcpStream, local_keypair: &Keypair) -> Result<Self> {
+        let (transport, _remote_static) = handshake(&mut stream, &local_keypair.private, false).await?;
+        Ok(Self::from_stream(NoiseStream::new(stream, transport)))
+    }
+
+    /// Like [`Self::accept_noise`], but also returns the static public key
+    /// the peer presented during the handshake, so the caller can feed it
+    /// to a [`crate::trust::TrustStore`] once [`Self::recv_request_thread`]
+    /// (or similar) has learned the peer's [`crate::identity::DeviceId`].
+    pub async fn accept_noise_with_key(
+        mut stream: TcpStream,
+        local_keypair: &Keypair,
+    ) -> Result<(Self, Vec<u8>)> {
+        let (transport, remote_static) = handshake(&mut stream, &local_keypair.private, false).await?;
+        Ok((Self::from_stream(NoiseStream::new(stream, transport)), remote_static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn completes_a_noise_handshake_and_exchanges_a_request() {
+        let responder_keys = generate_keypair().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::accept_noise(stream, &responder_keys).await.unwrap();
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let initiator_keys = generate_keypair().unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::connect_noise(stream, &initiator_keys).await.unwrap();
+        master.ping().await.unwrap();
+
+        drop(master);
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn with_key_variants_return_the_peers_static_public_key() {
+        let responder_keys = generate_keypair().unwrap();
+        let responder_public = responder_keys.public.clone();
+        let initiator_keys = generate_keypair().unwrap();
+        let initiator_public = initiator_keys.public.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (mut slave, remote_key) = Slave::accept_noise_with_key(stream, &responder_keys).await.unwrap();
+            slave.recv_request_thread().await.unwrap();
+            remote_key
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut master, remote_key) = Master::connect_noise_with_key(stream, &initiator_keys).await.unwrap();
+        master.ping().await.unwrap();
+        assert_eq!(remote_key, responder_public);
+
+        drop(master);
+        let slave_saw = accept.await.unwrap();
+        assert_eq!(slave_saw, initiator_public);
+    }
+}
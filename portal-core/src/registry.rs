@@ -0,0 +1,214 @@
+//! A small on-disk registry of known devices, so a user-assigned alias and
+//! last-known address survive a restart without rescanning the network.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::identity::DeviceId;
+
+/// What's remembered about one device between runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub device_id: DeviceId,
+    /// A user-assigned name, e.g. `"Laptop"`, so callers can offer "send to
+    /// Laptop" instead of making someone pick a UUID out of a scan.
+    pub alias: Option<String>,
+    /// Where this device was last seen; a starting point for reconnecting
+    /// without rescanning, though it can go stale if the device's address
+    /// has since changed.
+    pub last_addr: SocketAddr,
+}
+
+/// Tracks [`RegistryEntry`] records keyed by [`DeviceId`], rewriting the
+/// backing file in full on every change, the same way
+/// [`crate::journal::Journal`] does — known devices are few, so a
+/// rewrite-on-write design doesn't need compaction.
+pub struct Registry {
+    path: PathBuf,
+    entries: HashMap<DeviceId, RegistryEntry>,
+}
+
+impl Registry {
+    /// Starts an empty registry bound to `path`, without reading anything
+    /// already there. Useful as a fallback when [`Self::open`] fails (e.g.
+    /// a corrupted file) and starting fresh is preferable to failing
+    /// outright; the next write still goes to `path`, same as `open` would.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), entries: HashMap::new() }
+    }
+
+    /// Loads `path` if it exists (an empty or missing file means no
+    /// registered devices yet), or starts a fresh registry that will be
+    /// created at `path` on the first write.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) if bytes.is_empty() => HashMap::new(),
+            Ok(bytes) => {
+                let entries: Vec<RegistryEntry> = bincode::deserialize(&bytes)?;
+                entries.into_iter().map(|entry| (entry.device_id, entry)).collect()
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Every device currently registered, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &RegistryEntry> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, device_id: DeviceId) -> Option<&RegistryEntry> {
+        self.entries.get(&device_id)
+    }
+
+    /// Finds the device registered under `alias`, e.g. so "send to Laptop"
+    /// can be resolved to an address without the caller knowing its
+    /// [`DeviceId`].
+    pub fn find_by_alias(&self, alias: &str) -> Option<&RegistryEntry> {
+        self.entries.values().find(|entry| entry.alias.as_deref() == Some(alias))
+    }
+
+    /// Records `device_id`'s latest known address, e.g. after discovering it
+    /// via [`crate::broadcast::Listener`], preserving any alias already
+    /// assigned to it.
+    pub fn note_seen(&mut self, device_id: DeviceId, addr: SocketAddr) -> Result<()> {
+        self.entries
+            .entry(device_id)
+            .or_insert_with(|| RegistryEntry {
+                device_id,
+                alias: None,
+                last_addr: addr,
+            })
+            .last_addr = addr;
+        self.flush()
+    }
+
+    /// Assigns `alias` to `device_id`, registering it first (with no known
+    /// address) if it isn't already. Pass `None` to clear an existing alias.
+    pub fn set_alias(&mut self, device_id: DeviceId, alias: Option<String>) -> Result<()> {
+        self.entries
+            .entry(device_id)
+            .or_insert_with(|| RegistryEntry {
+                device_id,
+                alias: None,
+                last_addr: unspecified_addr(),
+            })
+            .alias = alias;
+        self.flush()
+    }
+
+    /// Removes `device_id` from the registry.
+    pub fn forget(&mut self, device_id: DeviceId) -> Result<()> {
+        self.entries.remove(&device_id);
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<()> {
+        let entries: Vec<&RegistryEntry> = self.entries.values().collect();
+        let bytes = bincode::serialize(&entries)?;
+        // Write to a sibling temp file and rename over the real path so a
+        // crash mid-write can't leave a half-written, unreadable registry.
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, &bytes)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn unspecified_addr() -> SocketAddr {
+    SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("portal-registry-{name}-test-{:?}.bin", std::thread::current().id()))
+    }
+
+    #[test]
+    fn round_trips_entries_through_a_reopened_registry() {
+        let path = test_path("round-trip");
+        let _ = std::fs::remove_file(&path);
+
+        let mut registry = Registry::open(&path).unwrap();
+        assert_eq!(registry.entries().count(), 0);
+
+        let alice = DeviceId::generate();
+        let addr: SocketAddr = "192.168.1.10:9000".parse().unwrap();
+        registry.note_seen(alice, addr).unwrap();
+        registry.set_alias(alice, Some("Laptop".to_string())).unwrap();
+
+        let reopened = Registry::open(&path).unwrap();
+        let entry = reopened.get(alice).unwrap();
+        assert_eq!(entry.alias.as_deref(), Some("Laptop"));
+        assert_eq!(entry.last_addr, addr);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn new_starts_empty_regardless_of_what_is_already_at_the_path() {
+        let path = test_path("new-starts-empty");
+        let _ = std::fs::remove_file(&path);
+
+        let mut seeded = Registry::open(&path).unwrap();
+        seeded.set_alias(DeviceId::generate(), Some("Laptop".to_string())).unwrap();
+
+        let fresh = Registry::new(&path);
+        assert_eq!(fresh.entries().count(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn treats_a_missing_file_as_an_empty_registry() {
+        let path = test_path("missing");
+        let _ = std::fs::remove_file(&path);
+
+        let registry = Registry::open(&path).unwrap();
+        assert_eq!(registry.entries().count(), 0);
+        assert!(registry.get(DeviceId::generate()).is_none());
+    }
+
+    #[test]
+    fn finds_a_device_by_its_alias() {
+        let path = test_path("find-by-alias");
+        let _ = std::fs::remove_file(&path);
+
+        let mut registry = Registry::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        registry.set_alias(device_id, Some("Desktop".to_string())).unwrap();
+
+        assert_eq!(registry.find_by_alias("Desktop").unwrap().device_id, device_id);
+        assert!(registry.find_by_alias("Nonexistent").is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forgetting_a_device_removes_it() {
+        let path = test_path("forget");
+        let _ = std::fs::remove_file(&path);
+
+        let mut registry = Registry::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        registry.note_seen(device_id, "127.0.0.1:1".parse().unwrap()).unwrap();
+        assert!(registry.get(device_id).is_some());
+
+        registry.forget(device_id).unwrap();
+        assert!(registry.get(device_id).is_none());
+
+        let reopened = Registry::open(&path).unwrap();
+        assert_eq!(reopened.entries().count(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! A small exponential-backoff retry policy, shared by anything that wants
+//! to ride out a transient failure instead of giving up immediately — e.g.
+//! [`crate::master::connect_with_retry`] for establishing a connection, or
+//! [`crate::master::send_a_file_with_retry`] for a transfer interrupted
+//! partway through.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// How many times to retry an operation, and how long to wait between
+/// attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many times to attempt the operation in total, including the
+    /// first try. Clamped to at least 1.
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// The delay before the second attempt; later attempts double it, up
+    /// to [`Self::max_delay`].
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// An upper bound on the backoff delay, regardless of how many
+    /// attempts have already failed.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Whether to randomize each delay between zero and its backoff bound
+    /// (full jitter), so that many callers retrying at once don't all land
+    /// on the wire in the same instant. Enabled by default.
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    pub fn max_attempts_allowed(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// The delay to wait after attempt number `attempt` (1-based) has just
+    /// failed, before making the next one.
+    pub fn delay_for(&self, attempt: usize) -> Duration {
+        let exponent = attempt.min(32) as u32;
+        let backoff = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(exponent.saturating_sub(1)).unwrap_or(u32::MAX));
+        let capped = backoff.min(self.max_delay);
+        if self.jitter {
+            full_jitter(capped)
+        } else {
+            capped
+        }
+    }
+
+    /// Retries `attempt` until it succeeds or [`Self::max_attempts`] tries
+    /// have been made, sleeping [`Self::delay_for`] between them. Returns
+    /// the last error if every attempt fails.
+    pub async fn retry<T, E, F, Fut>(&self, mut attempt: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut last_err = None;
+        for attempt_number in 1..=self.max_attempts {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    last_err = Some(err);
+                    if attempt_number < self.max_attempts {
+                        crate::metrics::record_retry();
+                        tokio::time::sleep(self.delay_for(attempt_number)).await;
+                    }
+                }
+            }
+        }
+        Err(last_err.expect("max_attempts >= 1 guarantees at least one iteration"))
+    }
+}
+
+/// Picks a uniformly random duration in `[0, bound]`. Not cryptographic —
+/// it only needs to vary from call to call, not resist prediction — so a
+/// dependency on a full RNG crate isn't worth pulling in just for this.
+/// `pub(crate)` so [`crate::broadcast`] can reuse it to jitter its
+/// announcement interval instead of re-implementing the same scheme.
+pub(crate) fn full_jitter(bound: Duration) -> Duration {
+    if bound.is_zero() {
+        return bound;
+    }
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0);
+    let spread = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1) % (bound.as_nanos().max(1));
+    Duration::from_nanos(spread as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn succeeds_without_retrying_once_the_attempt_stops_failing() {
+        let calls = AtomicUsize::new(0);
+        let policy = RetryPolicy::new()
+            .max_attempts(5)
+            .base_delay(Duration::from_millis(0));
+
+        let result: Result<&str, &str> = policy
+            .retry(|| {
+                let attempt = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    if attempt < 3 {
+                        Err("not yet")
+                    } else {
+                        Ok("ok")
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(result, Ok("ok"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts_and_returns_the_last_error() {
+        let calls = AtomicUsize::new(0);
+        let policy = RetryPolicy::new()
+            .max_attempts(3)
+            .base_delay(Duration::from_millis(0));
+
+        let result: Result<(), usize> = policy
+            .retry(|| {
+                let attempt = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move { Err(attempt) }
+            })
+            .await;
+
+        assert_eq!(result, Err(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backoff_doubles_each_attempt_up_to_the_cap() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350))
+            .jitter(false);
+
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(350));
+        assert_eq!(policy.delay_for(4), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn jittered_delay_never_exceeds_the_cap() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_secs(10))
+            .jitter(true);
+
+        for attempt in 1..10 {
+            assert!(policy.delay_for(attempt) <= Duration::from_secs(10));
+        }
+    }
+}
@@ -0,0 +1,2597 @@
+//! The receiving side of a portal transfer.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::future::Future;
+use std::io::{Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+
+use crate::codec::{
+    FileMetadata, MasterRequest, SlaveCodec, SlaveResponse, CUSTOM_KIND_MIN,
+    DEFAULT_MAX_CONTENT_SIZE, PROTOCOL_VERSION,
+};
+use crate::error::{Error, Result};
+use crate::identity::DeviceId;
+use crate::transport::{AsyncStream, BoxedStream};
+
+/// How a [`Slave`] should react when the destination for an incoming file
+/// already exists; see [`Slave::set_collision_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Overwrite whatever's there. The default, matching this crate's
+    /// historical behavior.
+    #[default]
+    Overwrite,
+    /// Keep both: append ` (1)`, ` (2)`, ... to the name until one doesn't
+    /// collide with an existing file.
+    RenameWithSuffix,
+    /// Keep the existing file and discard the incoming one, replying as if
+    /// it had been saved.
+    Skip,
+    /// Reject the transfer with [`SlaveResponse::FileExists`] instead of
+    /// touching anything on disk.
+    Fail,
+}
+
+/// Restricts incoming files by the extension of `FileMetadata::file_name`;
+/// see [`Slave::set_file_type_filter`]. The wire protocol carries no MIME
+/// type, so extension is all there is to go on. Matching is case-insensitive
+/// and ignores the leading `.`; a file with no extension never matches
+/// either list.
+#[derive(Debug, Clone)]
+pub enum FileTypeFilter {
+    /// Only these extensions are accepted; everything else is rejected.
+    Allow(HashSet<String>),
+    /// Every extension is accepted except these.
+    Deny(HashSet<String>),
+}
+
+impl FileTypeFilter {
+    fn permits(&self, file_name: &str) -> bool {
+        let extension = Path::new(file_name).extension().and_then(|e| e.to_str());
+        match (self, extension) {
+            (FileTypeFilter::Allow(allowed), Some(extension)) => {
+                allowed.iter().any(|candidate| candidate.eq_ignore_ascii_case(extension))
+            }
+            (FileTypeFilter::Allow(_), None) => false,
+            (FileTypeFilter::Deny(denied), Some(extension)) => {
+                !denied.iter().any(|candidate| candidate.eq_ignore_ascii_case(extension))
+            }
+            (FileTypeFilter::Deny(_), None) => true,
+        }
+    }
+}
+
+/// How often [`Slave::handle_request`] acknowledges an accepted
+/// `FileFragment` with [`SlaveResponse::Progress`] instead of a plain `Ok`;
+/// see [`Slave::set_progress_interval`]. Whichever threshold is crossed
+/// first triggers the next one.
+#[derive(Debug, Clone, Copy)]
+struct ProgressInterval {
+    fragments: u32,
+    bytes: u64,
+}
+
+impl Default for ProgressInterval {
+    /// Every 16 fragments or every 1 MiB, whichever comes first.
+    fn default() -> Self {
+        Self {
+            fragments: 16,
+            bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// A handler for an embedder-defined [`MasterRequest::Custom`] request.
+///
+/// Handlers are looked up by `kind` and given the raw request payload; their
+/// return value becomes the `SlaveResponse` sent back to the master.
+pub type CustomHandler = Arc<dyn Fn(Vec<u8>) -> SlaveResponse + Send + Sync>;
+
+/// A callback invoked with the content of every [`MasterRequest::Text`]; see
+/// [`Slave::on_text`].
+pub type TextHandler = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Details about an incoming file offered to an [`AcceptHandler`], enough
+/// for the embedding application to decide whether to accept it without
+/// inspecting protocol internals.
+#[derive(Debug, Clone)]
+pub struct IncomingFile {
+    pub file_name: String,
+    pub file_size: u64,
+    pub file_hash: Option<[u8; 32]>,
+    /// The master's address, if the underlying transport exposes one (a
+    /// plain [`TcpStream`]); `None` otherwise.
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// Whether an [`ActiveTransfer`] is actively receiving fragments on this
+/// connection, or sitting in a [`SlaveService`]'s shared resume pool
+/// waiting for a master to reconnect and finish it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferState {
+    Receiving,
+    Paused,
+}
+
+/// A file transfer in progress, or parked waiting to be resumed; see
+/// [`Slave::active_transfers`].
+#[derive(Debug, Clone)]
+pub struct ActiveTransfer {
+    pub file_id: u32,
+    pub file_name: String,
+    /// The master's address, if known; see [`IncomingFile::peer_addr`].
+    /// `None` for a transfer parked in the resume pool, whose original
+    /// connection is already gone.
+    pub peer_addr: Option<SocketAddr>,
+    pub bytes_received: u64,
+    pub file_size: u64,
+    pub state: TransferState,
+}
+
+/// A callback invoked with an [`IncomingFile`] before any of its fragments
+/// are accepted; see [`Slave::on_incoming_file`]. Returns whether to accept
+/// the transfer.
+pub type AcceptHandler =
+    Arc<dyn Fn(IncomingFile) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// A callback invoked with an [`ActiveTransfer`] snapshot every time a
+/// [`SlaveResponse::Progress`] is sent for it, so an embedding CLI or GUI
+/// can drive a progress bar without polling [`Slave::active_transfers`]
+/// from outside, which isn't possible once a [`SlaveService`] has moved the
+/// `Slave` into its own connection task; see [`Slave::on_progress`].
+pub type ProgressHandler = Arc<dyn Fn(ActiveTransfer) + Send + Sync>;
+
+/// Where incoming files and symlinks land when [`Slave::set_output_dir`]
+/// hasn't been called.
+fn default_output_dir() -> PathBuf {
+    if cfg!(debug_assertions) {
+        PathBuf::from("./received")
+    } else {
+        PathBuf::from("/tmp/portal-received")
+    }
+}
+
+/// Checks whether `output_dir` has room for `required` more bytes, creating
+/// it first if it doesn't exist yet (mirroring what finalizing a file does
+/// anyway). `required == 0` means the size wasn't known upfront (a streamed
+/// send), so the check is skipped. Best-effort: if the directory can't be
+/// created or the filesystem can't report free space, the transfer is
+/// allowed to proceed rather than failing it on a preflight glitch.
+fn insufficient_space(required: u64, output_dir: &Path) -> Option<u64> {
+    if required == 0 {
+        return None;
+    }
+    if std::fs::create_dir_all(output_dir).is_err() {
+        return None;
+    }
+    match fs2::available_space(output_dir) {
+        Ok(available) if available < required => Some(available),
+        _ => None,
+    }
+}
+
+/// A simple one-second sliding window, shared across every `Slave` a
+/// [`SlaveService`] spawns: rolls over to a fresh window once a second has
+/// elapsed since it started, then admits `amount` bytes if doing so wouldn't
+/// push the window's running total past `max`. Returns whether `amount` was
+/// admitted.
+fn admit_bytes(window: &std::sync::Mutex<(Instant, u64)>, max: u64, amount: u64) -> bool {
+    let mut window = window.lock().unwrap_or_else(|err| err.into_inner());
+    let (started, admitted) = &mut *window;
+    if started.elapsed() >= Duration::from_secs(1) {
+        *started = Instant::now();
+        *admitted = 0;
+    }
+    if *admitted + amount > max {
+        return false;
+    }
+    *admitted += amount;
+    true
+}
+
+/// Decompresses a single fragment's payload, rejecting one whose prepended
+/// LZ4 size header claims more than `max_len` bytes before allocating
+/// anything for it. `lz4_flex::decompress_size_prepended` trusts that header
+/// unconditionally and allocates straight from it, so an attacker-controlled
+/// fragment a few bytes long can claim a multi-gigabyte uncompressed size
+/// and force that allocation per fragment — the same decompression-bomb
+/// shape `decompress_bounded` guards against for the whole-file zstd path.
+/// `max_len` is `pending.metadata.fragment_size`, since no fragment's
+/// decompressed payload is ever larger than that.
+#[cfg(feature = "lz4")]
+fn decompress_fragment(data: Bytes, compressed: bool, max_len: u32) -> std::result::Result<Bytes, ()> {
+    if compressed {
+        let (uncompressed_size, rest) = lz4_flex::block::uncompressed_size(&data).map_err(|_| ())?;
+        if uncompressed_size > max_len as usize {
+            return Err(());
+        }
+        lz4_flex::block::decompress(rest, uncompressed_size)
+            .map(Bytes::from)
+            .map_err(|_| ())
+    } else {
+        Ok(data)
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+fn decompress_fragment(data: Bytes, compressed: bool, _max_len: u32) -> std::result::Result<Bytes, ()> {
+    if compressed {
+        Err(())
+    } else {
+        Ok(data)
+    }
+}
+
+/// A fresh 32-byte nonce for the [`MasterRequest::Auth`] challenge-response,
+/// drawn from [`uuid::Uuid::new_v4`]'s CSPRNG rather than pulling in a
+/// dedicated `rand` dependency for 32 random bytes.
+fn generate_nonce() -> [u8; 32] {
+    let mut nonce = [0u8; 32];
+    nonce[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    nonce[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    nonce
+}
+
+/// Keeps only the normal (non-root, non-`..`) components of `raw`, so a
+/// master can't make us write outside the received-files folder. Returns
+/// `None` if nothing normal is left.
+fn sanitize_relative_path(raw: &str) -> Option<PathBuf> {
+    let relative: PathBuf = Path::new(raw)
+        .components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    if relative.as_os_str().is_empty() {
+        None
+    } else {
+        Some(relative)
+    }
+}
+
+/// Resolves `path` against `policy` when something already exists there.
+/// Returns the path to actually write the incoming file to, `Ok(None)` if
+/// the transfer should be treated as a no-op success (`Skip`), or `Err(())`
+/// if it should be rejected (`Fail`).
+fn resolve_collision(path: PathBuf, policy: CollisionPolicy) -> std::result::Result<Option<PathBuf>, ()> {
+    if !path.exists() {
+        return Ok(Some(path));
+    }
+    match policy {
+        CollisionPolicy::Overwrite => Ok(Some(path)),
+        CollisionPolicy::Skip => Ok(None),
+        CollisionPolicy::Fail => Err(()),
+        CollisionPolicy::RenameWithSuffix => Ok(Some(unique_path(path))),
+    }
+}
+
+/// Appends ` (1)`, ` (2)`, ... before `path`'s extension until one doesn't
+/// collide with an existing file.
+fn unique_path(path: PathBuf) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file").to_string();
+    let extension = path.extension().and_then(|e| e.to_str()).map(ToString::to_string);
+    let parent = path.parent().map(PathBuf::from).unwrap_or_default();
+    let mut suffix = 1u32;
+    loop {
+        let name = match &extension {
+            Some(extension) => format!("{stem} ({suffix}).{extension}"),
+            None => format!("{stem} ({suffix})"),
+        };
+        let candidate = parent.join(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        suffix += 1;
+    }
+}
+
+/// The fragment indices missing from `received`, assuming a complete file
+/// holds every index from `0` up to the highest one seen. Empty if nothing
+/// is missing (including if `received` is itself empty, i.e. an empty file).
+fn missing_fragments(received: &HashSet<u32>) -> Vec<u32> {
+    let Some(&max_index) = received.iter().max() else {
+        return Vec::new();
+    };
+    (0..=max_index).filter(|index| !received.contains(index)).collect()
+}
+
+#[cfg(unix)]
+fn materialize_symlink(entry: &crate::codec::SymlinkEntry, output_dir: &Path) -> SlaveResponse {
+    let Some(relative) = sanitize_relative_path(&entry.path) else {
+        return SlaveResponse::SymlinkFailed;
+    };
+    let path = output_dir.join(&relative);
+    let Some(parent) = path.parent() else {
+        return SlaveResponse::SymlinkFailed;
+    };
+    // Unlike `entry.path` above, `entry.target` is written into the symlink
+    // exactly as the sending master supplied it, which is how a real symlink
+    // normally works (its target can point anywhere). A malicious or
+    // compromised master can abuse that to plant a link inside `output_dir`
+    // pointing anywhere else on this filesystem (e.g. `~/.ssh/authorized_keys`)
+    // for something else to later write through unknowingly, so a target
+    // that would resolve outside `output_dir` is rejected rather than
+    // recreated. Only meaningful protection if the sender is otherwise
+    // untrusted; a cooperating master that wants a link outside the transfer
+    // has no reason to go through this path at all.
+    if !symlink_target_is_contained(parent, &entry.target, output_dir) {
+        return SlaveResponse::SymlinkFailed;
+    }
+    if std::fs::create_dir_all(parent).is_err() {
+        return SlaveResponse::SymlinkFailed;
+    }
+    let _ = std::fs::remove_file(&path);
+    match std::os::unix::fs::symlink(&entry.target, &path) {
+        Ok(()) => SlaveResponse::Ok,
+        Err(_) => SlaveResponse::SymlinkFailed,
+    }
+}
+
+/// Whether `target` (a symlink's target, taken as-is from `entry.target`),
+/// resolved relative to `link_parent` if it's itself relative, stays inside
+/// `output_dir`. Resolved lexically rather than with [`Path::canonicalize`],
+/// since a symlink's target doesn't need to exist yet to be checked.
+#[cfg(unix)]
+fn symlink_target_is_contained(link_parent: &Path, target: &str, output_dir: &Path) -> bool {
+    let target = Path::new(target);
+    let candidate = if target.is_absolute() {
+        target.to_path_buf()
+    } else {
+        link_parent.join(target)
+    };
+    normalize_lexically(&candidate).starts_with(normalize_lexically(output_dir))
+}
+
+/// Resolves `.` and `..` components of `path` without touching the
+/// filesystem, unlike [`Path::canonicalize`].
+#[cfg(unix)]
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+#[cfg(not(unix))]
+fn materialize_symlink(_entry: &crate::codec::SymlinkEntry, _output_dir: &Path) -> SlaveResponse {
+    SlaveResponse::SymlinkFailed
+}
+
+/// Restores the modification time and, on Unix, permission bits recorded in
+/// `metadata`. Best-effort: failures here don't fail the transfer, since the
+/// file itself was already saved successfully.
+fn apply_metadata(path: &Path, metadata: &FileMetadata) {
+    if let Some(modified) = metadata.modified {
+        let mtime = filetime::FileTime::from_unix_time(modified, 0);
+        let _ = filetime::set_file_mtime(path, mtime);
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = metadata.unix_mode {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode));
+    }
+}
+
+/// Shared by every `Slave` a [`SlaveService`] spawns, tracking progress on
+/// a file left incomplete when one connection drops so it's resumable once
+/// a master reconnects; see [`Slave::set_resume_pool`].
+type ResumePool = Arc<std::sync::Mutex<HashMap<[u8; 32], PendingFile>>>;
+
+pub(crate) struct PendingFile {
+    metadata: FileMetadata,
+    /// Fragments are seeked and written straight to their final position
+    /// here instead of being buffered in memory, so a multi-gigabyte
+    /// transfer doesn't need a multi-gigabyte allocation and fragments may
+    /// arrive in any order; see [`Slave::handle_request`] and
+    /// [`finalize_part_file`]. Removed once the transfer finalizes, one way
+    /// or another.
+    part_path: PathBuf,
+    part_file: std::fs::File,
+    /// Every fragment index received so far. Used both to skip seeking for
+    /// one already on disk and by `missing_fragments` to detect gaps at
+    /// `EndOfFile`.
+    received: HashSet<u32>,
+    /// Sum of every distinct fragment's content length received so far,
+    /// regardless of order; see [`Slave::active_transfers`].
+    bytes_received: u64,
+    /// Fragments received since the last [`SlaveResponse::Progress`] for
+    /// this file, or since it started if none has been sent yet; see
+    /// [`Slave::set_progress_interval`].
+    fragments_since_progress: u32,
+    /// `bytes_received` the last time a [`SlaveResponse::Progress`] was
+    /// sent for this file, or `0` if none has been sent yet.
+    bytes_at_last_progress: u64,
+}
+
+/// A fresh, unique path for a file's `.part` file under the system temp
+/// directory. Unique per process and per call, so two slaves receiving a
+/// file with the same `file_id` at the same time (e.g. two connections in
+/// the same embedding application) never collide.
+fn part_file_path(file_id: u32) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("portal-{}-{file_id}-{unique}.part", std::process::id()))
+}
+
+/// Writes `data` directly at `index`'s byte offset in `pending.part_file`,
+/// derived from `pending.metadata.fragment_size` — every fragment but the
+/// last is exactly that size, so this is the same arithmetic a master uses
+/// to chunk the file in the first place; see `FileMetadata::fragment_size`.
+/// Out-of-order and duplicate fragments both just seek and overwrite, so
+/// neither needs special handling here.
+fn write_fragment_at_offset(pending: &mut PendingFile, index: u32, data: &[u8]) -> std::io::Result<()> {
+    let offset = index as u64 * pending.metadata.fragment_size as u64;
+    pending.part_file.seek(SeekFrom::Start(offset))?;
+    pending.part_file.write_all(data)
+}
+
+/// How many [`Slave::finalize_file`] outcomes `Slave::finalized` keeps
+/// around for a replayed `EndOfFile` to find, before the oldest is evicted
+/// to make room for a new one.
+const FINALIZED_CACHE_CAP: usize = 256;
+
+/// Receives files and custom requests from a connected [`crate::master::Master`].
+pub struct Slave {
+    stream: Framed<BoxedStream, SlaveCodec>,
+    file_pool: HashMap<u32, PendingFile>,
+    handlers: HashMap<u16, CustomHandler>,
+    text_handler: Option<TextHandler>,
+    pairing_key: Option<String>,
+    authenticated: bool,
+    /// The nonce handed out in the most recent [`SlaveResponse::AuthRequired`],
+    /// cleared as soon as it's spent on a matching [`MasterRequest::Auth`]
+    /// proof (successful or not), so a captured proof can never be replayed
+    /// against a later nonce.
+    pending_nonce: Option<[u8; 32]>,
+    /// Largest fragment content size this slave is willing to accept; see
+    /// [`Slave::set_max_content_size`] and the [`crate::codec`] module docs.
+    max_content_size: usize,
+    /// How long [`Slave::recv_request_thread`] will wait for the next
+    /// request before giving up on the master; see
+    /// [`Slave::set_idle_timeout`]. `None` waits forever.
+    idle_timeout: Option<Duration>,
+    /// Lets an embedding application stop [`Slave::recv_request_thread`]
+    /// cleanly; see [`Slave::set_cancellation_token`].
+    cancellation: Option<CancellationToken>,
+    /// What to do when an incoming file's name collides with one already on
+    /// disk; see [`Slave::set_collision_policy`].
+    collision_policy: CollisionPolicy,
+    /// Asked whether to accept each incoming file before any of its
+    /// fragments are; see [`Slave::on_incoming_file`].
+    accept_handler: Option<AcceptHandler>,
+    /// Invoked with every [`SlaveResponse::Progress`] sent for a transfer;
+    /// see [`Slave::on_progress`].
+    progress_handler: Option<ProgressHandler>,
+    /// The master's address, if known; see [`IncomingFile::peer_addr`].
+    peer_addr: Option<SocketAddr>,
+    /// Where incoming files and symlinks are written; see
+    /// [`Slave::set_output_dir`].
+    output_dir: PathBuf,
+    /// Shared across every `Slave` a [`SlaveService`] spawns, so progress on
+    /// a file left incomplete when one connection drops is still there for
+    /// [`MasterRequest::QueryResumeState`] and a resumed
+    /// [`MasterRequest::FileMetadata`] once the master reconnects.
+    resume_pool: Option<ResumePool>,
+    /// Caps how many files, across every `Slave` a [`SlaveService`] spawns,
+    /// may be receiving fragments at once; see
+    /// [`SlaveService::set_max_concurrent_files`].
+    max_concurrent_files: Option<usize>,
+    /// Shared across every `Slave` a [`SlaveService`] spawns; counts files
+    /// actively receiving fragments right now (not ones parked in
+    /// `resume_pool`).
+    concurrent_files: Option<Arc<AtomicUsize>>,
+    /// Caps total inbound fragment bytes per second across every `Slave` a
+    /// [`SlaveService`] spawns; see
+    /// [`SlaveService::set_max_inbound_bytes_per_sec`].
+    max_inbound_bytes_per_sec: Option<u64>,
+    /// Shared across every `Slave` a [`SlaveService`] spawns: the start of
+    /// the current one-second accounting window and the bytes admitted
+    /// within it.
+    rate_window: Option<Arc<std::sync::Mutex<(Instant, u64)>>>,
+    /// Largest `FileMetadata::file_size` this slave will accept; see
+    /// [`Slave::set_max_file_size`].
+    max_file_size: Option<u64>,
+    /// Extension allow/deny rules evaluated at `FileMetadata` time; see
+    /// [`Slave::set_file_type_filter`].
+    file_type_filter: Option<FileTypeFilter>,
+    /// The reply [`Slave::finalize_file`] gave the last time each `file_id`
+    /// finished, so a replayed `EndOfFile` after the master already saw the
+    /// result is answered the same way instead of `FileIdNotFound`. `file_id`
+    /// is allocated per transfer rather than drawn from a small range, so
+    /// this is capped at [`FINALIZED_CACHE_CAP`] entries and evicted in
+    /// insertion order via `finalized_order`, rather than left to grow for
+    /// as long as the connection (or, behind a `SlaveService`, the process)
+    /// stays up.
+    finalized: HashMap<u32, SlaveResponse>,
+    /// Insertion order of `finalized`'s keys, for FIFO eviction once it hits
+    /// [`FINALIZED_CACHE_CAP`].
+    finalized_order: VecDeque<u32>,
+    /// How often an accepted `FileFragment` is acknowledged with
+    /// [`SlaveResponse::Progress`] instead of a plain `Ok`; see
+    /// [`Slave::set_progress_interval`].
+    progress_interval: ProgressInterval,
+    /// Identifies this installation to the master during handshake; see
+    /// [`Slave::set_device_id`].
+    device_id: DeviceId,
+    /// The master's [`DeviceId`], learned from [`MasterRequest::Hello`].
+    /// `None` until the handshake is received.
+    peer_device_id: Option<DeviceId>,
+}
+
+impl From<TcpStream> for Slave {
+    fn from(stream: TcpStream) -> Self {
+        let peer_addr = stream.peer_addr().ok();
+        let mut slave = Self::from_stream(stream);
+        slave.peer_addr = peer_addr;
+        slave
+    }
+}
+
+impl Slave {
+    /// Wraps an already-established stream (plain TCP, TLS, or anything
+    /// else implementing [`AsyncStream`]) as a `Slave`.
+    pub fn from_stream(stream: impl AsyncStream + 'static) -> Self {
+        crate::metrics::connection_opened();
+        Self {
+            stream: Framed::new(Box::new(stream) as BoxedStream, SlaveCodec::new()),
+            file_pool: HashMap::new(),
+            handlers: HashMap::new(),
+            text_handler: None,
+            pairing_key: None,
+            authenticated: true,
+            pending_nonce: None,
+            max_content_size: DEFAULT_MAX_CONTENT_SIZE,
+            idle_timeout: None,
+            cancellation: None,
+            collision_policy: CollisionPolicy::default(),
+            accept_handler: None,
+            progress_handler: None,
+            peer_addr: None,
+            output_dir: default_output_dir(),
+            resume_pool: None,
+            max_concurrent_files: None,
+            concurrent_files: None,
+            max_inbound_bytes_per_sec: None,
+            rate_window: None,
+            max_file_size: None,
+            file_type_filter: None,
+            finalized: HashMap::new(),
+            finalized_order: VecDeque::new(),
+            progress_interval: ProgressInterval::default(),
+            device_id: DeviceId::generate(),
+            peer_device_id: None,
+        }
+    }
+
+    /// Shares `pool` with this `Slave` so a file left incomplete here is
+    /// still resumable after this connection drops and a new one takes its
+    /// place; see [`SlaveService`], which wires this up automatically for
+    /// every connection it accepts.
+    pub(crate) fn set_resume_pool(&mut self, pool: ResumePool) {
+        self.resume_pool = Some(pool);
+    }
+
+    /// Shares a concurrent-files cap and counter with this `Slave`; see
+    /// [`SlaveService::set_max_concurrent_files`].
+    pub(crate) fn set_concurrent_files_limit(&mut self, max: usize, counter: Arc<AtomicUsize>) {
+        self.max_concurrent_files = Some(max);
+        self.concurrent_files = Some(counter);
+    }
+
+    /// Shares an inbound-bandwidth cap and accounting window with this
+    /// `Slave`; see [`SlaveService::set_max_inbound_bytes_per_sec`].
+    pub(crate) fn set_inbound_rate_limit(
+        &mut self,
+        bytes_per_sec: u64,
+        window: Arc<std::sync::Mutex<(Instant, u64)>>,
+    ) {
+        self.max_inbound_bytes_per_sec = Some(bytes_per_sec);
+        self.rate_window = Some(window);
+    }
+
+    /// Requires the master to prove knowledge of `key` via a
+    /// [`MasterRequest::Auth`] challenge-response before any request is
+    /// served other than [`MasterRequest::Hello`] and [`MasterRequest::Ping`],
+    /// which stay available so a master can probe the connection (and learn
+    /// it needs to authenticate) before committing to a transfer.
+    pub fn set_pairing_key(&mut self, key: impl Into<String>) {
+        self.pairing_key = Some(key.into());
+        self.authenticated = false;
+    }
+
+    /// Identifies this installation to the master, advertised during
+    /// [`MasterRequest::Hello`] negotiation so the master can recognize it
+    /// across reconnects even if its address changes. Defaults to a freshly
+    /// generated [`DeviceId`] that isn't persisted anywhere; pass one loaded
+    /// via [`DeviceId::load_or_create`] if the same identity should survive
+    /// a restart.
+    pub fn set_device_id(&mut self, id: DeviceId) {
+        self.device_id = id;
+    }
+
+    /// The master's [`DeviceId`], learned from its [`MasterRequest::Hello`].
+    /// `None` until the handshake is received.
+    pub fn peer_device_id(&self) -> Option<DeviceId> {
+        self.peer_device_id
+    }
+
+    /// The largest fragment content size this slave is willing to accept,
+    /// advertised to the master during [`MasterRequest::Hello`] negotiation;
+    /// see the [`crate::codec`] module docs.
+    pub fn set_max_content_size(&mut self, size: usize) {
+        self.max_content_size = size.max(1);
+    }
+
+    /// Fails [`Slave::recv_request_thread`] with [`Error::PeerUnresponsive`]
+    /// if no request (including a keepalive [`MasterRequest::Ping`]) arrives
+    /// within `timeout`, instead of waiting on a master that may have hung
+    /// or vanished without closing the connection. Disabled by default.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    /// Lets `token` stop [`Slave::recv_request_thread`] cleanly: once
+    /// cancelled, the loop returns `Ok(())` after its current request (if
+    /// any) finishes, instead of waiting for the master to disconnect. Use
+    /// this to shut an embedding application's node down without abandoning
+    /// in-flight requests mid-response.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    /// Controls what happens when an incoming file's name collides with one
+    /// already under the received-files folder. Defaults to
+    /// [`CollisionPolicy::Overwrite`].
+    pub fn set_collision_policy(&mut self, policy: CollisionPolicy) {
+        self.collision_policy = policy;
+    }
+
+    /// Where incoming files and symlinks are written. Defaults to
+    /// `./received` in debug builds and `/tmp/portal-received` otherwise;
+    /// call this to pick an explicit destination instead, e.g. one
+    /// configured by the embedding application's own settings.
+    pub fn set_output_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.output_dir = dir.into();
+    }
+
+    /// Rejects any `FileMetadata` whose declared size exceeds `max`, so a
+    /// small device can't be sent a file it has no hope of holding.
+    /// Disabled (no cap) by default. A streamed send's `file_size` of `0`
+    /// means "unknown upfront" rather than "empty", so it always passes
+    /// this check regardless of `max`.
+    pub fn set_max_file_size(&mut self, max: u64) {
+        self.max_file_size = Some(max);
+    }
+
+    /// Restricts incoming files by extension; see [`FileTypeFilter`].
+    /// Disabled (every extension accepted) by default.
+    pub fn set_file_type_filter(&mut self, filter: FileTypeFilter) {
+        self.file_type_filter = Some(filter);
+    }
+
+    /// Sets how often an accepted `FileFragment` is acknowledged with
+    /// [`SlaveResponse::Progress`] (carrying the file's true
+    /// received-bytes total) instead of a plain `Ok`: every `fragments`
+    /// fragments, or every `bytes` bytes, whichever comes first. Defaults to
+    /// every 16 fragments or 1 MiB.
+    pub fn set_progress_interval(&mut self, fragments: u32, bytes: u64) {
+        self.progress_interval = ProgressInterval { fragments, bytes };
+    }
+
+    /// Registers a callback asked whether to accept each incoming file,
+    /// given its [`IncomingFile`] details, before any of its fragments are.
+    /// Rejecting sends [`SlaveResponse::Rejected`] and discards whatever
+    /// state was kept for it (if any, from a resumed transfer).
+    pub fn on_incoming_file(&mut self, handler: AcceptHandler) {
+        self.accept_handler = Some(handler);
+    }
+
+    /// Registers a callback invoked with an [`ActiveTransfer`] snapshot
+    /// every time this connection sends a [`SlaveResponse::Progress`] for
+    /// it, so a host (CLI, GUI, daemon) can render per-transfer progress as
+    /// it happens instead of polling [`Slave::active_transfers`], which a
+    /// [`SlaveService`] consumer has no access to once a connection is
+    /// accepted.
+    pub fn on_progress(&mut self, handler: ProgressHandler) {
+        self.progress_handler = Some(handler);
+    }
+
+    /// Snapshots every file currently being received on this connection,
+    /// plus any left incomplete by a previous connection and parked in the
+    /// shared resume pool (see [`SlaveService`]) waiting for a master to
+    /// reconnect and finish them, so a host (CLI, GUI, daemon) can show
+    /// what's in flight without tracking protocol internals itself.
+    pub fn active_transfers(&self) -> Vec<ActiveTransfer> {
+        let mut transfers: Vec<ActiveTransfer> = self
+            .file_pool
+            .values()
+            .map(|pending| ActiveTransfer {
+                file_id: pending.metadata.file_id,
+                file_name: pending.metadata.file_name.clone(),
+                peer_addr: self.peer_addr,
+                bytes_received: pending.bytes_received,
+                file_size: pending.metadata.file_size,
+                state: TransferState::Receiving,
+            })
+            .collect();
+        if let Some(pool) = &self.resume_pool {
+            transfers.extend(
+                pool.lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .values()
+                    .map(|pending| ActiveTransfer {
+                        file_id: pending.metadata.file_id,
+                        file_name: pending.metadata.file_name.clone(),
+                        peer_addr: None,
+                        bytes_received: pending.bytes_received,
+                        file_size: pending.metadata.file_size,
+                        state: TransferState::Paused,
+                    }),
+            );
+        }
+        transfers
+    }
+
+    fn needs_auth(&self) -> bool {
+        self.pairing_key.is_some() && !self.authenticated
+    }
+
+    /// Registers a handler for custom requests of the given `kind`.
+    ///
+    /// `kind` must lie within [`CUSTOM_KIND_MIN`]..=`u16::MAX`; kinds below
+    /// that are reserved for the built-in protocol.
+    pub fn register_handler(&mut self, kind: u16, handler: CustomHandler) -> Result<()> {
+        if kind < CUSTOM_KIND_MIN {
+            return Err(Error::CustomKindOutOfRange(kind));
+        }
+        self.handlers.insert(kind, handler);
+        Ok(())
+    }
+
+    /// Registers a callback invoked with the content of every incoming
+    /// [`MasterRequest::Text`], so the embedding app can surface it (e.g. to
+    /// the system clipboard) without portal-core knowing anything about
+    /// that platform's clipboard API.
+    pub fn on_text(&mut self, handler: TextHandler) {
+        self.text_handler = Some(handler);
+    }
+
+    /// Reads requests off the connection until it closes, replying to each.
+    #[tracing::instrument(skip(self), fields(peer = ?self.peer_addr))]
+    pub async fn recv_request_thread(&mut self) -> Result<()> {
+        loop {
+            let next = match self.cancellation.clone() {
+                Some(token) => tokio::select! {
+                    _ = token.cancelled() => return Ok(()),
+                    next = self.recv_one() => next,
+                },
+                None => self.recv_one().await,
+            }?;
+            let Some(request) = next else {
+                return Ok(());
+            };
+            let response = self.handle_request(request?).await;
+            self.stream.send(response).await?;
+        }
+    }
+
+    /// Waits for the next request, subject to [`Slave::set_idle_timeout`].
+    async fn recv_one(&mut self) -> Result<Option<Result<MasterRequest>>> {
+        match self.idle_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.stream.next())
+                .await
+                .map_err(|_| Error::PeerUnresponsive),
+            None => Ok(self.stream.next().await),
+        }
+    }
+
+    async fn handle_request(&mut self, request: MasterRequest) -> SlaveResponse {
+        match request {
+            MasterRequest::Hello {
+                protocol_version,
+                device_id,
+                ..
+            } => {
+                if protocol_version != PROTOCOL_VERSION {
+                    SlaveResponse::IncompatibleProtocol {
+                        slave_version: PROTOCOL_VERSION,
+                        master_version: protocol_version,
+                    }
+                } else {
+                    self.peer_device_id = Some(device_id);
+                    SlaveResponse::Hello {
+                        protocol_version: PROTOCOL_VERSION,
+                        features: crate::codec::local_features(),
+                        max_content_size: self.max_content_size as u32,
+                        device_id: self.device_id,
+                    }
+                }
+            }
+            MasterRequest::Ping => SlaveResponse::Pong,
+            MasterRequest::Auth { proof } => {
+                let Some(key) = &self.pairing_key else {
+                    self.authenticated = true;
+                    return SlaveResponse::Ok;
+                };
+                let Some(proof) = proof else {
+                    // No proof yet: hand out a fresh, single-use nonce for
+                    // the master to HMAC instead of proving anything here.
+                    let nonce = generate_nonce();
+                    self.pending_nonce = Some(nonce);
+                    return SlaveResponse::AuthRequired { nonce };
+                };
+                // The nonce is consumed whether or not the proof checks out,
+                // so a captured proof can't be retried against itself.
+                let Some(nonce) = self.pending_nonce.take() else {
+                    return SlaveResponse::AuthFailed;
+                };
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("HMAC-SHA256 accepts a key of any length");
+                mac.update(&nonce);
+                if mac.verify_slice(&proof).is_ok() {
+                    self.authenticated = true;
+                    SlaveResponse::Ok
+                } else {
+                    SlaveResponse::AuthFailed
+                }
+            }
+            _ if self.needs_auth() => {
+                let nonce = generate_nonce();
+                self.pending_nonce = Some(nonce);
+                SlaveResponse::AuthRequired { nonce }
+            }
+            MasterRequest::Text { content } => {
+                if let Some(handler) = &self.text_handler {
+                    handler(content);
+                }
+                SlaveResponse::Ok
+            }
+            MasterRequest::FileMetadata(metadata) => {
+                let file_id = metadata.file_id;
+                if let Some(max_file_size) = self.max_file_size {
+                    if metadata.file_size > max_file_size {
+                        return SlaveResponse::FileTooLarge { file_id, max_file_size };
+                    }
+                }
+                if let Some(filter) = &self.file_type_filter {
+                    if !filter.permits(&metadata.file_name) {
+                        return SlaveResponse::FileTypeNotAllowed { file_id };
+                    }
+                }
+                if let Some(handler) = &self.accept_handler {
+                    let incoming = IncomingFile {
+                        file_name: metadata.file_name.clone(),
+                        file_size: metadata.file_size,
+                        file_hash: metadata.file_hash,
+                        peer_addr: self.peer_addr,
+                    };
+                    if !handler(incoming).await {
+                        self.file_pool.remove(&file_id);
+                        return SlaveResponse::Rejected { file_id };
+                    }
+                }
+                if let Some(available) = insufficient_space(metadata.file_size, &self.output_dir) {
+                    return SlaveResponse::InsufficientSpace {
+                        file_id,
+                        required: metadata.file_size,
+                        available,
+                    };
+                }
+                // A master may resend metadata for a file it's resuming after
+                // a dropped connection; keep whatever fragments we already
+                // have for the same hash instead of discarding progress. A
+                // streamed send with no hash yet is always treated as new.
+                let keep_existing = metadata.file_hash.is_some()
+                    && self
+                        .file_pool
+                        .get(&file_id)
+                        .is_some_and(|pending| pending.metadata.file_hash == metadata.file_hash);
+                // The fragments above only cover resuming on a connection
+                // that's stayed open the whole time. An actually dropped
+                // connection loses `self.file_pool` along with the `Slave`
+                // that held it, so also check the pool a `SlaveService`
+                // shares across the `Slave`s it spawns for progress left
+                // behind by a previous connection for the same file.
+                let resumed = if !keep_existing {
+                    metadata.file_hash.and_then(|hash| {
+                        self.resume_pool
+                            .as_ref()
+                            .and_then(|pool| pool.lock().unwrap_or_else(|err| err.into_inner()).remove(&hash))
+                    })
+                } else {
+                    None
+                };
+                // A resumed or brand-new file starts actively receiving
+                // fragments, which counts against the shared cap; one kept
+                // unchanged from `keep_existing` is already counted.
+                if !keep_existing {
+                    if let (Some(max), Some(counter)) = (self.max_concurrent_files, &self.concurrent_files) {
+                        if counter.load(Ordering::SeqCst) >= max {
+                            return SlaveResponse::Busy;
+                        }
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                if let Some(mut pending) = resumed {
+                    pending.metadata = metadata;
+                    self.file_pool.insert(file_id, pending);
+                } else if !keep_existing {
+                    let part_path = part_file_path(file_id);
+                    let part_file = match std::fs::OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(&part_path)
+                    {
+                        Ok(file) => file,
+                        Err(_) => {
+                            if let Some(counter) = &self.concurrent_files {
+                                counter.fetch_sub(1, Ordering::SeqCst);
+                            }
+                            return SlaveResponse::CannotSaveFile { file_id };
+                        }
+                    };
+                    // Best-effort: a known size lets the filesystem allocate
+                    // the part file's extents up front instead of growing
+                    // them one fragment write at a time. Only valid for an
+                    // uncompressed transfer, where `file_size` is exactly
+                    // what ends up in the part file; a compressed one writes
+                    // a smaller, differently-sized payload there instead.
+                    if !metadata.compressed {
+                        let _ = part_file.set_len(metadata.file_size);
+                    }
+                    self.file_pool.insert(
+                        file_id,
+                        PendingFile {
+                            metadata,
+                            part_path,
+                            part_file,
+                            received: HashSet::new(),
+                            bytes_received: 0,
+                            fragments_since_progress: 0,
+                            bytes_at_last_progress: 0,
+                        },
+                    );
+                }
+                SlaveResponse::Ok
+            }
+            MasterRequest::FileFragment(fragment) => {
+                if let (Some(max), Some(window)) = (self.max_inbound_bytes_per_sec, &self.rate_window) {
+                    if !admit_bytes(window, max, fragment.data.len() as u64) {
+                        return SlaveResponse::Busy;
+                    }
+                }
+                let Some(pending) = self.file_pool.get_mut(&fragment.file_id) else {
+                    return SlaveResponse::FileIdNotFound {
+                        file_id: fragment.file_id,
+                    };
+                };
+                let data = match decompress_fragment(fragment.data, fragment.compressed, pending.metadata.fragment_size) {
+                    Ok(data) => data,
+                    Err(()) => {
+                        return SlaveResponse::CannotSaveFile {
+                            file_id: fragment.file_id,
+                        }
+                    }
+                };
+                let is_new = pending.received.insert(fragment.index);
+                if is_new {
+                    pending.bytes_received += data.len() as u64;
+                    crate::metrics::record_fragment_received();
+                    crate::metrics::record_bytes_received(data.len() as u64);
+                }
+                if write_fragment_at_offset(pending, fragment.index, &data).is_err() {
+                    return SlaveResponse::CannotSaveFile {
+                        file_id: fragment.file_id,
+                    };
+                }
+                if !is_new {
+                    return SlaveResponse::Ok;
+                }
+                pending.fragments_since_progress += 1;
+                let interval = self.progress_interval;
+                if pending.fragments_since_progress >= interval.fragments
+                    || pending.bytes_received - pending.bytes_at_last_progress >= interval.bytes
+                {
+                    pending.fragments_since_progress = 0;
+                    pending.bytes_at_last_progress = pending.bytes_received;
+                    let bytes_received = pending.bytes_received;
+                    if let Some(handler) = &self.progress_handler {
+                        handler(ActiveTransfer {
+                            file_id: fragment.file_id,
+                            file_name: pending.metadata.file_name.clone(),
+                            peer_addr: self.peer_addr,
+                            bytes_received,
+                            file_size: pending.metadata.file_size,
+                            state: TransferState::Receiving,
+                        });
+                    }
+                    return SlaveResponse::Progress {
+                        file_id: fragment.file_id,
+                        bytes_received,
+                    };
+                }
+                SlaveResponse::Ok
+            }
+            MasterRequest::FileHash { file_id, file_hash } => {
+                let Some(pending) = self.file_pool.get_mut(&file_id) else {
+                    return SlaveResponse::FileIdNotFound { file_id };
+                };
+                pending.metadata.file_hash = Some(file_hash);
+                SlaveResponse::Ok
+            }
+            MasterRequest::EndOfFile { file_id } => self.finalize_file(file_id).await,
+            MasterRequest::Symlink(entry) => materialize_symlink(&entry, &self.output_dir),
+            MasterRequest::QueryResumeState { file_hash } => {
+                let have_indices = self
+                    .file_pool
+                    .values()
+                    .find(|pending| pending.metadata.file_hash == Some(file_hash))
+                    .map(|pending| pending.received.iter().copied().collect())
+                    .or_else(|| {
+                        self.resume_pool.as_ref().and_then(|pool| {
+                            pool.lock()
+                                .unwrap_or_else(|err| err.into_inner())
+                                .get(&file_hash)
+                                .map(|pending| pending.received.iter().copied().collect())
+                        })
+                    })
+                    .unwrap_or_default();
+                SlaveResponse::ResumeState { have_indices }
+            }
+            MasterRequest::Custom { kind, payload } => match self.handlers.get(&kind) {
+                Some(handler) => handler(payload),
+                None => SlaveResponse::Custom {
+                    kind,
+                    payload: Vec::new(),
+                },
+            },
+        }
+    }
+
+    #[tracing::instrument(
+        skip(self),
+        fields(peer = ?self.peer_addr, file_name = tracing::field::Empty, bytes = tracing::field::Empty)
+    )]
+    async fn finalize_file(&mut self, file_id: u32) -> SlaveResponse {
+        let Some(pending) = self.file_pool.get(&file_id) else {
+            // A master that never saw our reply to the first `EndOfFile` (or
+            // sent a redundant one) gets the same answer again instead of
+            // `FileIdNotFound`, which would otherwise look like the transfer
+            // had never happened at all.
+            return self
+                .finalized
+                .get(&file_id)
+                .cloned()
+                .unwrap_or(SlaveResponse::FileIdNotFound { file_id });
+        };
+        let indices = missing_fragments(&pending.received);
+        if !indices.is_empty() {
+            return SlaveResponse::MissingFragments { file_id, indices };
+        }
+        let span = tracing::Span::current();
+        span.record("file_name", pending.metadata.file_name.as_str());
+        span.record("bytes", pending.bytes_received);
+
+        // Unwrap is safe: the lookup above just confirmed `file_id` is present.
+        let PendingFile {
+            metadata,
+            part_path,
+            part_file,
+            ..
+        } = self.file_pool.remove(&file_id).unwrap();
+        if let Some(counter) = &self.concurrent_files {
+            counter.fetch_sub(1, Ordering::SeqCst);
+        }
+        drop(part_file);
+        let collision_policy = self.collision_policy;
+        let output_dir = self.output_dir.clone();
+        // Hashing and writing out a large file is CPU/disk-bound work that
+        // would otherwise run on this connection's async task and block the
+        // executor thread from making progress on any other connection (see
+        // `SlaveService`) for as long as it takes. Running it on a blocking
+        // thread instead keeps the executor free while this finishes.
+        let response = tokio::task::spawn_blocking(move || {
+            let response = finalize_part_file(&metadata, &part_path, collision_policy, &output_dir);
+            // A no-op if `finalize_part_file` already moved it into place.
+            let _ = std::fs::remove_file(&part_path);
+            response
+        })
+        .await
+        .unwrap_or(SlaveResponse::CannotSaveFile { file_id });
+        if self.finalized.insert(file_id, response.clone()).is_none() {
+            self.finalized_order.push_back(file_id);
+            if self.finalized_order.len() > FINALIZED_CACHE_CAP {
+                if let Some(oldest) = self.finalized_order.pop_front() {
+                    self.finalized.remove(&oldest);
+                }
+            }
+        }
+        response
+    }
+}
+
+impl Drop for Slave {
+    /// Hands any still-incomplete transfers off to [`Self::resume_pool`]
+    /// rather than letting them vanish with this connection, so a master
+    /// that reconnects can pick up where it left off. Either way, they stop
+    /// counting against [`Self::concurrent_files`]: a parked transfer isn't
+    /// actively receiving fragments, and one with no hash to park under is
+    /// gone for good.
+    fn drop(&mut self) {
+        crate::metrics::connection_closed();
+        if let Some(counter) = &self.concurrent_files {
+            counter.fetch_sub(self.file_pool.len(), Ordering::SeqCst);
+        }
+        let Some(pool) = &self.resume_pool else {
+            return;
+        };
+        let mut pool = pool.lock().unwrap_or_else(|err| err.into_inner());
+        for pending in self.file_pool.drain().map(|(_, pending)| pending) {
+            if let Some(file_hash) = pending.metadata.file_hash {
+                pool.insert(file_hash, pending);
+            }
+        }
+    }
+}
+
+/// Configures every [`Slave`] a [`SlaveService`] spawns; applied right after
+/// accepting each connection, before its [`Slave::recv_request_thread`] runs.
+pub type SlaveConfigurer = Arc<dyn Fn(&mut Slave) + Send + Sync>;
+
+/// Owns a [`TcpListener`], accepting connections from any number of masters
+/// and spawning an independent [`Slave`] for each, so an embedding
+/// application doesn't have to hand-roll the accept loop itself.
+pub struct SlaveService {
+    listener: tokio::net::TcpListener,
+    configurer: Option<SlaveConfigurer>,
+    cancellation: Option<CancellationToken>,
+    /// Shared by every `Slave` this service spawns, so a transfer left
+    /// incomplete when one connection drops is resumable once a master
+    /// reconnects and a fresh `Slave` picks up the next connection.
+    resume_pool: ResumePool,
+    /// Caps how many connections this service will serve at once; see
+    /// [`SlaveService::set_max_connections`].
+    max_connections: Option<usize>,
+    active_connections: Arc<AtomicUsize>,
+    /// Shared with every spawned `Slave` via
+    /// [`Slave::set_concurrent_files_limit`]; see
+    /// [`SlaveService::set_max_concurrent_files`].
+    max_concurrent_files: Option<usize>,
+    concurrent_files: Arc<AtomicUsize>,
+    /// Shared with every spawned `Slave` via [`Slave::set_inbound_rate_limit`];
+    /// see [`SlaveService::set_max_inbound_bytes_per_sec`].
+    max_inbound_bytes_per_sec: Option<u64>,
+    rate_window: Arc<std::sync::Mutex<(Instant, u64)>>,
+}
+
+impl SlaveService {
+    pub async fn bind(addr: impl tokio::net::ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: tokio::net::TcpListener::bind(addr).await?,
+            configurer: None,
+            cancellation: None,
+            resume_pool: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            max_connections: None,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            max_concurrent_files: None,
+            concurrent_files: Arc::new(AtomicUsize::new(0)),
+            max_inbound_bytes_per_sec: None,
+            rate_window: Arc::new(std::sync::Mutex::new((Instant::now(), 0))),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Applied to every `Slave` this service spawns, right after it's
+    /// constructed and before its request loop starts. Use this to share
+    /// config (pairing key, collision policy, handlers, ...) across every
+    /// master that connects, instead of repeating it per connection.
+    pub fn configure(&mut self, configurer: SlaveConfigurer) {
+        self.configurer = Some(configurer);
+    }
+
+    /// Lets `token` stop [`SlaveService::run`] cleanly: once cancelled, the
+    /// accept loop stops taking new connections. Also handed to every
+    /// spawned `Slave` via [`Slave::set_cancellation_token`], so in-flight
+    /// connections wind down too instead of being abandoned.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    /// Caps how many masters may be connected at once. A connection beyond
+    /// the cap is sent a single [`SlaveResponse::Busy`] reply to its first
+    /// request and then closed, instead of being served. Disabled (no cap)
+    /// by default.
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.max_connections = Some(max);
+    }
+
+    /// Caps how many files, across every connection this service serves, may
+    /// be actively receiving fragments at once. A [`MasterRequest::FileMetadata`]
+    /// beyond the cap is answered with [`SlaveResponse::Busy`] instead of
+    /// starting the transfer. Disabled (no cap) by default.
+    pub fn set_max_concurrent_files(&mut self, max: usize) {
+        self.max_concurrent_files = Some(max);
+    }
+
+    /// Caps total inbound fragment bytes per second across every connection
+    /// this service serves. A [`MasterRequest::FileFragment`] that would
+    /// exceed the cap is answered with [`SlaveResponse::Busy`] instead of
+    /// being accepted, leaving it to the master to retry. Disabled (no cap)
+    /// by default.
+    pub fn set_max_inbound_bytes_per_sec(&mut self, bytes_per_sec: u64) {
+        self.max_inbound_bytes_per_sec = Some(bytes_per_sec);
+    }
+
+    /// Accepts connections until cancelled (or forever, if no cancellation
+    /// token was set), spawning a [`Slave::recv_request_thread`] task per
+    /// connection. A single connection failing to accept is logged and
+    /// skipped rather than ending the loop.
+    pub async fn run(&mut self) {
+        loop {
+            let accepted = match self.cancellation.clone() {
+                Some(token) => tokio::select! {
+                    _ = token.cancelled() => return,
+                    accepted = self.listener.accept() => accepted,
+                },
+                None => self.listener.accept().await,
+            };
+            let (stream, peer_addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!(%err, "failed to accept connection");
+                    continue;
+                }
+            };
+            if let Some(max) = self.max_connections {
+                if self.active_connections.load(Ordering::SeqCst) >= max {
+                    tokio::spawn(reject_busy(stream));
+                    continue;
+                }
+            }
+            let active_connections = self.active_connections.clone();
+            active_connections.fetch_add(1, Ordering::SeqCst);
+
+            let mut slave = Slave::from(stream);
+            slave.set_resume_pool(self.resume_pool.clone());
+            if let Some(max) = self.max_concurrent_files {
+                slave.set_concurrent_files_limit(max, self.concurrent_files.clone());
+            }
+            if let Some(bytes_per_sec) = self.max_inbound_bytes_per_sec {
+                slave.set_inbound_rate_limit(bytes_per_sec, self.rate_window.clone());
+            }
+            if let Some(configurer) = &self.configurer {
+                configurer(&mut slave);
+            }
+            if let Some(token) = &self.cancellation {
+                slave.set_cancellation_token(token.clone());
+            }
+            tokio::spawn(async move {
+                if let Err(err) = slave.recv_request_thread().await {
+                    tracing::warn!(%peer_addr, %err, "slave connection ended with an error");
+                }
+                active_connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+/// Replies to a single request with [`SlaveResponse::Busy`] and closes the
+/// connection, for a master that connected once [`SlaveService::set_max_connections`]
+/// was already saturated. Doesn't construct a full [`Slave`]: the connection
+/// is never going to be served, so there's nothing for one to do beyond this
+/// one reply.
+async fn reject_busy(stream: TcpStream) {
+    let mut framed = Framed::new(Box::new(stream) as BoxedStream, SlaveCodec::new());
+    if let Some(Ok(_request)) = framed.next().await {
+        let _ = framed.send(SlaveResponse::Busy).await;
+    }
+}
+
+/// Hashes `path` by streaming it through a fixed-size buffer rather than
+/// reading it into memory all at once, since fragments now land at their
+/// final offset as they arrive (see [`write_fragment_at_offset`]) instead of
+/// being hashed incrementally on the way in.
+fn hash_file(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = std::io::Read::read(&mut file, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Decompresses `payload`, aborting once the output would exceed `max_len`
+/// bytes rather than materializing it first and only then noticing. Without
+/// this, [`zstd::stream::decode_all`] will happily inflate a small malicious
+/// payload (a "zstd bomb") into a multi-gigabyte allocation before the
+/// caller ever gets to compare it against `metadata.file_size`.
+#[cfg(feature = "compression")]
+fn decompress_bounded(payload: &[u8], max_len: u64) -> std::result::Result<Vec<u8>, ()> {
+    use std::io::Read;
+    let mut decoder = zstd::stream::Decoder::new(payload).map_err(|_| ())?;
+    let mut out = Vec::new();
+    let mut chunk = [0u8; 64 * 1024];
+    loop {
+        let read = decoder.read(&mut chunk).map_err(|_| ())?;
+        if read == 0 {
+            break;
+        }
+        if out.len() as u64 + read as u64 > max_len {
+            return Err(());
+        }
+        out.extend_from_slice(&chunk[..read]);
+    }
+    Ok(out)
+}
+
+/// Verifies the hash of a fully-received `.part` file and moves it into
+/// place under the configured received-files folder. `part_file` must
+/// already be closed; the caller is responsible for cleaning up
+/// `part_path` afterward.
+fn finalize_part_file(
+    metadata: &FileMetadata,
+    part_path: &Path,
+    collision_policy: CollisionPolicy,
+    output_dir: &Path,
+) -> SlaveResponse {
+    let file_id = metadata.file_id;
+    let Some(expected_hash) = metadata.file_hash else {
+        // A streamed send never got its trailing `FileHash`.
+        return SlaveResponse::CannotSaveFile { file_id };
+    };
+    // Cheap and doesn't need the part file's content, so check these before
+    // doing any of the (de)compression or hashing work below.
+    let Some(relative) = sanitize_relative_path(&metadata.file_name) else {
+        return SlaveResponse::InvalidFileName { file_id };
+    };
+    let path = match resolve_collision(output_dir.join(&relative), collision_policy) {
+        Ok(Some(path)) => path,
+        // Nothing to do: the existing file wins and this transfer is
+        // reported as if it had succeeded.
+        Ok(None) => return SlaveResponse::Ok,
+        Err(()) => return SlaveResponse::FileExists { file_id },
+    };
+
+    // Whole-file compression has to be undone before its hash means
+    // anything, so it's decompressed into memory here; an uncompressed
+    // transfer is hashed straight off disk by `hash_file` instead, further
+    // down.
+    #[cfg(feature = "compression")]
+    let decompressed = if metadata.compressed {
+        let payload = match std::fs::read(part_path) {
+            Ok(payload) => payload,
+            Err(_) => return SlaveResponse::CannotSaveFile { file_id },
+        };
+        match decompress_bounded(&payload, metadata.file_size) {
+            Ok(data) => Some(data),
+            Err(()) => return SlaveResponse::CannotSaveFile { file_id },
+        }
+    } else {
+        None
+    };
+    #[cfg(not(feature = "compression"))]
+    let decompressed: Option<Vec<u8>> = if metadata.compressed {
+        return SlaveResponse::CannotSaveFile { file_id };
+    } else {
+        None
+    };
+
+    // A cheap sanity check ahead of the hash below: a size mismatch means
+    // the transfer is definitely incomplete or corrupt, without needing to
+    // read back and hash a file that's already known to be wrong. Skipped
+    // for a streamed send, whose declared `file_size` of `0` doesn't mean
+    // "empty".
+    if metadata.file_size > 0 {
+        let actual_size = match &decompressed {
+            Some(data) => data.len() as u64,
+            None => std::fs::metadata(part_path).map(|m| m.len()).unwrap_or(0),
+        };
+        if actual_size != metadata.file_size {
+            return SlaveResponse::ChecksumNotMatched { file_id };
+        }
+    }
+
+    let hash: [u8; 32] = match &decompressed {
+        Some(data) => Sha256::digest(data).into(),
+        None => match hash_file(part_path) {
+            Ok(hash) => hash,
+            Err(_) => return SlaveResponse::CannotSaveFile { file_id },
+        },
+    };
+    if hash != expected_hash {
+        return SlaveResponse::ChecksumNotMatched { file_id };
+    }
+
+    let Some(parent) = path.parent() else {
+        return SlaveResponse::CannotSaveFile { file_id };
+    };
+    if std::fs::create_dir_all(parent).is_err() {
+        return SlaveResponse::CannotSaveFile { file_id };
+    }
+
+    let wrote = match decompressed {
+        // The decompressed bytes never touched disk, so stage them in a
+        // sibling `.part` file next to the destination first instead of
+        // writing straight to `path`: a rename is atomic, so nothing ever
+        // observes a half-written file there.
+        Some(data) => {
+            let mut staged_name = path.file_name().unwrap_or_default().to_os_string();
+            staged_name.push(".part");
+            let staged = unique_path(path.with_file_name(staged_name));
+            let wrote = std::fs::write(&staged, data).is_ok()
+                && (std::fs::rename(&staged, &path).is_ok() || std::fs::copy(&staged, &path).is_ok());
+            let _ = std::fs::remove_file(&staged);
+            wrote
+        }
+        // Renaming avoids reading the whole file back into memory; fall
+        // back to a copy if the part file and destination don't share a
+        // filesystem, where a rename isn't possible.
+        None => std::fs::rename(part_path, &path).is_ok() || std::fs::copy(part_path, &path).is_ok(),
+    };
+    if !wrote {
+        return SlaveResponse::CannotSaveFile { file_id };
+    }
+
+    apply_metadata(&path, metadata);
+    SlaveResponse::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::master::Master;
+
+    #[tokio::test]
+    async fn rejects_requests_until_the_correct_key_is_proven() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_pairing_key("hunter2");
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = Master::from_stream(stream);
+
+        // Ping stays available even before authenticating.
+        master.ping().await.unwrap();
+
+        master
+            .send_request(MasterRequest::Text { content: "hi".into() })
+            .await
+            .unwrap();
+        assert!(matches!(
+            master.recv_response().await.unwrap(),
+            SlaveResponse::AuthRequired { .. }
+        ));
+
+        master.authenticate("wrong password").await.unwrap_err();
+
+        master.authenticate("hunter2").await.unwrap();
+        master
+            .send_request(MasterRequest::Text { content: "hi".into() })
+            .await
+            .unwrap();
+        assert!(matches!(
+            master.recv_response().await.unwrap(),
+            SlaveResponse::Ok
+        ));
+
+        drop(master);
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_proof_replayed_against_a_later_nonce() {
+        // A real socket just to satisfy `from_stream`; the exchange below is
+        // driven directly through `handle_request` so the same proof can be
+        // replayed on purpose, which a `Master` never does on its own.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_pairing_key("hunter2");
+
+        let SlaveResponse::AuthRequired { nonce } =
+            slave.handle_request(MasterRequest::Auth { proof: None }).await
+        else {
+            panic!("expected a nonce");
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"hunter2").unwrap();
+        mac.update(&nonce);
+        let proof: [u8; 32] = mac.finalize().into_bytes().into();
+        assert!(matches!(
+            slave.handle_request(MasterRequest::Auth { proof: Some(proof) }).await,
+            SlaveResponse::Ok
+        ));
+
+        // A fresh nonce was only ever handed out once; replaying the same
+        // proof against it (or against no pending nonce at all, as here)
+        // must not succeed a second time.
+        assert!(matches!(
+            slave.handle_request(MasterRequest::Auth { proof: Some(proof) }).await,
+            SlaveResponse::AuthFailed
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_and_recovers_from_a_missing_fragment() {
+        // A real socket just to satisfy `from_stream`; requests are driven
+        // directly through `handle_request` below so a fragment can be left
+        // out on purpose, which a real TCP connection won't do for us.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-missing-fragments-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let content = b"the quick brown fox jumps over".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "gap.txt".to_string(),
+                file_id: 9,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: 10,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 9,
+                index: 0,
+                data: Bytes::copy_from_slice(&content[0..10]),
+                compressed: false,
+            }))
+            .await;
+        // Index 1 is deliberately never sent, leaving a gap.
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 9,
+                index: 2,
+                data: Bytes::copy_from_slice(&content[20..]),
+                compressed: false,
+            }))
+            .await;
+
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 9 })
+            .await;
+        assert!(matches!(
+            response,
+            SlaveResponse::MissingFragments { file_id: 9, ref indices } if indices == &[1]
+        ));
+
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 9,
+                index: 1,
+                data: Bytes::copy_from_slice(&content[10..20]),
+                compressed: false,
+            }))
+            .await;
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 9 })
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+        assert_eq!(std::fs::read(dir.join("gap.txt")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn hashes_fragments_arriving_out_of_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-out-of-order-hash-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let content = b"0123456789abcdefghij".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "reordered.txt".to_string(),
+                file_id: 11,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: 5,
+            }))
+            .await;
+        // Fed in reverse, out of index order, unlike the hasher's own
+        // index-by-index consumption.
+        for index in (0..4u32).rev() {
+            let start = index as usize * 5;
+            slave
+                .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                    file_id: 11,
+                    index,
+                    data: Bytes::copy_from_slice(&content[start..start + 5]),
+                    compressed: false,
+                }))
+                .await;
+        }
+
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 11 })
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+        assert_eq!(std::fs::read(dir.join("reordered.txt")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn spills_fragments_to_a_part_file_and_cleans_up_on_success() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-spill-to-disk-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let content = b"spilled to a temp file while in flight".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+        let part_glob = |file_id: u32| -> Vec<PathBuf> {
+            let marker = format!("-{file_id}-");
+            std::fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+                        name.starts_with("portal-") && name.ends_with(".part") && name.contains(&marker)
+                    })
+                })
+                .collect()
+        };
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "spilled.txt".to_string(),
+                file_id: 13,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        // While the transfer is still in flight, the fragment lives on disk
+        // in a part file rather than being buffered in memory.
+        assert!(!part_glob(13).is_empty());
+
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 13,
+                index: 0,
+                data: Bytes::copy_from_slice(&content),
+                compressed: false,
+            }))
+            .await;
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 13 })
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        // The part file is cleaned up once the transfer finalizes.
+        assert!(part_glob(13).is_empty());
+        assert_eq!(std::fs::read(dir.join("spilled.txt")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_traversal_only_file_name_once_fully_received() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-invalid-file-name-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let content = b"nothing left after stripping ../..".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                // Sanitizes down to nothing: no real path component survives
+                // stripping the parent-dir and root segments.
+                file_name: "../../../".to_string(),
+                file_id: 19,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 19,
+                index: 0,
+                data: Bytes::copy_from_slice(&content),
+                compressed: false,
+            }))
+            .await;
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 19 })
+            .await;
+        assert!(matches!(response, SlaveResponse::InvalidFileName { file_id: 19 }));
+    }
+
+    #[tokio::test]
+    async fn renames_with_a_suffix_on_a_collision() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_collision_policy(CollisionPolicy::RenameWithSuffix);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-collision-rename-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+        std::fs::write(dir.join("taken.txt"), b"already here").unwrap();
+
+        let content = b"the incoming file".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "taken.txt".to_string(),
+                file_id: 21,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 21,
+                index: 0,
+                data: Bytes::copy_from_slice(&content),
+                compressed: false,
+            }))
+            .await;
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 21 })
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        // The original is untouched, and the incoming file landed alongside
+        // it under a disambiguated name.
+        assert_eq!(std::fs::read(dir.join("taken.txt")).unwrap(), b"already here");
+        assert_eq!(std::fs::read(dir.join("taken (1).txt")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn fails_a_collision_under_the_fail_policy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_collision_policy(CollisionPolicy::Fail);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-collision-fail-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+        std::fs::write(dir.join("taken.txt"), b"already here").unwrap();
+
+        let content = b"the incoming file".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "taken.txt".to_string(),
+                file_id: 23,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 23,
+                index: 0,
+                data: Bytes::copy_from_slice(&content),
+                compressed: false,
+            }))
+            .await;
+        let response = slave
+            .handle_request(MasterRequest::EndOfFile { file_id: 23 })
+            .await;
+        assert!(matches!(response, SlaveResponse::FileExists { file_id: 23 }));
+        assert_eq!(std::fs::read(dir.join("taken.txt")).unwrap(), b"already here");
+    }
+
+    #[tokio::test]
+    async fn runs_the_accept_hook_before_touching_any_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.on_incoming_file(Arc::new(|incoming: IncomingFile| {
+            Box::pin(async move { incoming.file_name != "forbidden.txt" })
+        }));
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-accept-hook-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "forbidden.txt".to_string(),
+                file_id: 29,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Rejected { file_id: 29 }));
+        assert!(slave.file_pool.is_empty());
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "allowed.txt".to_string(),
+                file_id: 30,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+        assert!(slave.file_pool.contains_key(&30));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_file_that_wont_fit_on_disk() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-insufficient-space-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        // No real filesystem in CI has room for an exabyte, so this is
+        // guaranteed to trip the preflight check regardless of environment.
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "too-big.bin".to_string(),
+                file_id: 17,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: u64::MAX,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(
+            response,
+            SlaveResponse::InsufficientSpace { file_id: 17, required: u64::MAX, .. }
+        ));
+        // Rejected outright: no part file was left behind.
+        assert!(slave.file_pool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stops_cleanly_once_cancelled() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let token = CancellationToken::new();
+        let thread_token = token.clone();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_cancellation_token(thread_token);
+            slave.recv_request_thread().await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let _master = Master::from_stream(stream);
+
+        token.cancel();
+        assert!(accept.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn serves_several_masters_concurrently() {
+        let mut service = SlaveService::bind("127.0.0.1:0").await.unwrap();
+        let addr = service.local_addr().unwrap();
+        service.configure(Arc::new(|slave: &mut Slave| {
+            slave.set_pairing_key("hunter2");
+        }));
+
+        let token = CancellationToken::new();
+        service.set_cancellation_token(token.clone());
+        let run = tokio::spawn(async move { service.run().await });
+
+        for _ in 0..3 {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut master = Master::from_stream(stream);
+            master.authenticate("hunter2").await.unwrap();
+            master.ping().await.unwrap();
+        }
+
+        token.cancel();
+        run.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_a_file_after_the_connection_drops() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-resume-reconnect-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        let content = "r".repeat(4096);
+        std::fs::write(&src, &content).unwrap();
+        let file_hash: [u8; 32] = Sha256::digest(content.as_bytes()).into();
+        let file_id = file_hash[0] as u32;
+
+        let recv_dir = dir.clone();
+        let mut service = SlaveService::bind("127.0.0.1:0").await.unwrap();
+        let addr = service.local_addr().unwrap();
+        service.configure(Arc::new(move |slave: &mut Slave| {
+            slave.set_output_dir(recv_dir.clone());
+        }));
+        let token = CancellationToken::new();
+        service.set_cancellation_token(token.clone());
+        let run = tokio::spawn(async move { service.run().await });
+
+        // The first connection sends metadata and only the first of four
+        // fragments, then drops without ever sending `EndOfFile`.
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = crate::master::MasterBuilder::new().max_content_size(1024).build(stream);
+        master
+            .send_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "source.txt".to_string(),
+                file_id,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(master.recv_response().await.unwrap(), SlaveResponse::Ok));
+        master
+            .send_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id,
+                index: 0,
+                data: Bytes::copy_from_slice(&content.as_bytes()[..1024]),
+                compressed: false,
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(master.recv_response().await.unwrap(), SlaveResponse::Ok));
+        drop(master);
+
+        // Give the dropped connection's `Slave` a moment to hand its
+        // progress off to `SlaveService`'s shared resume pool.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // A fresh connection resumes the same file by content hash and
+        // finishes it, sending only the three fragments still missing.
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut master = crate::master::MasterBuilder::new().max_content_size(1024).build(stream);
+        let response = master.resume_a_file(&src).await.unwrap();
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        token.cancel();
+        run.await.unwrap();
+
+        assert_eq!(
+            std::fs::read(dir.join("source.txt")).unwrap(),
+            content.into_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_bytes_received_for_an_active_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        assert!(slave.active_transfers().is_empty());
+
+        let content = b"the quick brown fox".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "fox.txt".to_string(),
+                file_id: 7,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 7,
+                index: 0,
+                data: Bytes::copy_from_slice(&content[..10]),
+                compressed: false,
+            }))
+            .await;
+
+        let transfers = slave.active_transfers();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].file_id, 7);
+        assert_eq!(transfers[0].file_name, "fox.txt");
+        assert_eq!(transfers[0].bytes_received, 10);
+        assert_eq!(transfers[0].file_size, content.len() as u64);
+        assert_eq!(transfers[0].state, TransferState::Receiving);
+    }
+
+    #[tokio::test]
+    async fn on_progress_fires_with_the_transfer_snapshot_on_each_progress_ack() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_progress_interval(1, u64::MAX);
+
+        let seen: Arc<std::sync::Mutex<Vec<ActiveTransfer>>> = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        slave.on_progress(Arc::new(move |transfer| recorder.lock().unwrap().push(transfer)));
+
+        let content = b"the quick brown fox".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "fox.txt".to_string(),
+                file_id: 7,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: 10,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 7,
+                index: 0,
+                data: Bytes::copy_from_slice(&content[..10]),
+                compressed: false,
+            }))
+            .await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].file_id, 7);
+        assert_eq!(seen[0].file_name, "fox.txt");
+        assert_eq!(seen[0].bytes_received, 10);
+        assert_eq!(seen[0].file_size, content.len() as u64);
+        assert_eq!(seen[0].state, TransferState::Receiving);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_new_file_once_the_concurrent_files_cap_is_reached() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_concurrent_files_limit(1, Arc::new(AtomicUsize::new(0)));
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-concurrent-files-cap-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let first_hash: [u8; 32] = Sha256::digest(b"test").into();
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "first.txt".to_string(),
+                file_id: 41,
+                file_hash: Some(first_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        // A second, distinct file arrives while the first is still in
+        // flight, pushing the active count past the cap of one.
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "second.txt".to_string(),
+                file_id: 42,
+                file_hash: Some([2u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Busy));
+        assert!(!slave.file_pool.contains_key(&42));
+
+        // Finishing the first file frees up the slot for the second.
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 41,
+                index: 0,
+                data: Bytes::from_static(b"test"),
+                compressed: false,
+            }))
+            .await;
+        let response = slave.handle_request(MasterRequest::EndOfFile { file_id: 41 }).await;
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "second.txt".to_string(),
+                file_id: 42,
+                file_hash: Some([2u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+    }
+
+    #[tokio::test]
+    async fn replies_busy_once_the_inbound_rate_limit_is_exhausted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_inbound_rate_limit(10, Arc::new(std::sync::Mutex::new((Instant::now(), 0))));
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-inbound-rate-limit-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "throttled.txt".to_string(),
+                file_id: 43,
+                file_hash: Some([3u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 20,
+                fragment_size: 1024,
+            }))
+            .await;
+
+        let response = slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 43,
+                index: 0,
+                data: Bytes::copy_from_slice(b"0123456789"),
+                compressed: false,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+
+        // The window's ten-byte budget for this second is already spent.
+        let response = slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 43,
+                index: 1,
+                data: Bytes::copy_from_slice(b"0123456789"),
+                compressed: false,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Busy));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_file_larger_than_the_configured_maximum() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_max_file_size(1024);
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "too-big.bin".to_string(),
+                file_id: 51,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 2048,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(
+            response,
+            SlaveResponse::FileTooLarge { file_id: 51, max_file_size: 1024 }
+        ));
+        assert!(slave.file_pool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_denied_extension() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_file_type_filter(FileTypeFilter::Deny(HashSet::from(["exe".to_string()])));
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "virus.EXE".to_string(),
+                file_id: 52,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::FileTypeNotAllowed { file_id: 52 }));
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "notes.txt".to_string(),
+                file_id: 53,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+    }
+
+    #[tokio::test]
+    async fn only_accepts_allow_listed_extensions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+        slave.set_file_type_filter(FileTypeFilter::Allow(HashSet::from(["txt".to_string()])));
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "no-extension".to_string(),
+                file_id: 54,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::FileTypeNotAllowed { file_id: 54 }));
+
+        let response = slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "notes.txt".to_string(),
+                file_id: 55,
+                file_hash: Some([0u8; 32]),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: 4,
+                fragment_size: 1024,
+            }))
+            .await;
+        assert!(matches!(response, SlaveResponse::Ok));
+    }
+
+    #[tokio::test]
+    async fn replays_the_prior_result_for_a_repeated_end_of_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-replayed-end-of-file-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let content = b"replayed end of file".to_vec();
+        let file_hash: [u8; 32] = Sha256::digest(&content).into();
+
+        slave
+            .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                file_name: "replay.txt".to_string(),
+                file_id: 61,
+                file_hash: Some(file_hash),
+                compressed: false,
+                modified: None,
+                unix_mode: None,
+                file_size: content.len() as u64,
+                fragment_size: content.len() as u32,
+            }))
+            .await;
+        slave
+            .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                file_id: 61,
+                index: 0,
+                data: Bytes::copy_from_slice(&content),
+                compressed: false,
+            }))
+            .await;
+        let first = slave.handle_request(MasterRequest::EndOfFile { file_id: 61 }).await;
+        assert!(matches!(first, SlaveResponse::Ok));
+
+        // The master never saw the first `Ok` (or sent a redundant
+        // `EndOfFile` anyway) and asks again; the file is no longer in
+        // `file_pool`, but the answer should match rather than claiming the
+        // file_id was never seen.
+        let replayed = slave.handle_request(MasterRequest::EndOfFile { file_id: 61 }).await;
+        assert!(matches!(replayed, SlaveResponse::Ok));
+        assert_eq!(std::fs::read(dir.join("replay.txt")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn evicts_the_oldest_finalized_entry_once_the_cache_fills() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-finalized-cache-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        for file_id in 0..=FINALIZED_CACHE_CAP as u32 {
+            let content = file_id.to_le_bytes().to_vec();
+            let file_hash: [u8; 32] = Sha256::digest(&content).into();
+            slave
+                .handle_request(MasterRequest::FileMetadata(FileMetadata {
+                    file_name: format!("f{file_id}.bin"),
+                    file_id,
+                    file_hash: Some(file_hash),
+                    compressed: false,
+                    modified: None,
+                    unix_mode: None,
+                    file_size: content.len() as u64,
+                    fragment_size: content.len() as u32,
+                }))
+                .await;
+            slave
+                .handle_request(MasterRequest::FileFragment(crate::codec::FileFragment {
+                    file_id,
+                    index: 0,
+                    data: Bytes::copy_from_slice(&content),
+                    compressed: false,
+                }))
+                .await;
+            slave.handle_request(MasterRequest::EndOfFile { file_id }).await;
+        }
+
+        // `FINALIZED_CACHE_CAP + 1` files went through above, so the first
+        // one's cached result must have been evicted to make room for the
+        // last; a replayed `EndOfFile` for it no longer finds anything.
+        let replayed = slave.handle_request(MasterRequest::EndOfFile { file_id: 0 }).await;
+        assert!(matches!(replayed, SlaveResponse::FileIdNotFound { file_id: 0 }));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_symlink_target_that_would_escape_the_output_dir() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        accept.await.unwrap();
+        let mut slave = Slave::from_stream(stream);
+
+        let dir = std::env::temp_dir().join(format!(
+            "portal-symlink-escape-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        slave.set_output_dir(dir.clone());
+
+        let escaping = slave
+            .handle_request(MasterRequest::Symlink(crate::codec::SymlinkEntry {
+                path: "evil.txt".to_string(),
+                target: "../../../../etc/passwd".to_string(),
+            }))
+            .await;
+        assert!(matches!(escaping, SlaveResponse::SymlinkFailed));
+        assert!(!dir.join("evil.txt").exists());
+
+        let contained = slave
+            .handle_request(MasterRequest::Symlink(crate::codec::SymlinkEntry {
+                path: "fine.txt".to_string(),
+                target: "target.txt".to_string(),
+            }))
+            .await;
+        assert!(matches!(contained, SlaveResponse::Ok));
+        assert!(dir.join("fine.txt").symlink_metadata().is_ok());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn decompress_bounded_stays_within_the_cap() {
+        let payload = zstd::stream::encode_all(&b"hello world"[..], 0).unwrap();
+        let data = decompress_bounded(&payload, 11).unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn decompress_bounded_rejects_output_past_the_cap() {
+        // Not a real zstd bomb's ratio, but enough to prove a payload that
+        // decompresses past `max_len` is rejected rather than fully
+        // materialized first.
+        let payload = zstd::stream::encode_all(&b"hello world"[..], 0).unwrap();
+        assert!(decompress_bounded(&payload, 5).is_err());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn decompress_fragment_stays_within_the_cap() {
+        let payload = Bytes::from(lz4_flex::compress_prepend_size(b"hello world"));
+        let data = decompress_fragment(payload, true, 11).unwrap();
+        assert_eq!(data, Bytes::from_static(b"hello world"));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn decompress_fragment_rejects_a_size_header_past_the_cap() {
+        // A real compressed fragment, but claiming (via a forged size header)
+        // an uncompressed size past what `fragment_size` allows, which must be
+        // rejected before anything is allocated for it — not just once the
+        // decompressed bytes are counted.
+        let mut payload = lz4_flex::compress_prepend_size(b"hello world");
+        payload[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress_fragment(Bytes::from(payload), true, 11).is_err());
+    }
+}
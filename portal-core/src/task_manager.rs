@@ -0,0 +1,958 @@
+//! Tracks outgoing transfers by id so an embedding application (the CLI's
+//! `ListTask`/`PauseTask`/`ResumeTask`/`AbortTask` commands, for instance)
+//! can manage several of them without holding onto a [`crate::master::Master`] itself.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::codec::SlaveResponse;
+use crate::error::Result;
+use crate::journal::{Journal, JournalEntry};
+use crate::master::{MasterBuilder, Progress};
+use crate::transport::{AsyncStream, BoxedStream};
+
+/// Identifies a transfer submitted to a [`TaskManager`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// The raw id, e.g. for an embedding application that wants to display
+    /// or serialize it without relying on `Debug`'s `TaskId(3)` formatting.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How a finished task's transfer turned out. A plain `String` stands in
+/// for [`crate::error::Error`] here (rather than the error itself) so a
+/// task's outcome can be reported more than once without needing the error
+/// type to be [`Clone`].
+#[derive(Debug, Clone)]
+pub enum TaskOutcome {
+    Response(SlaveResponse),
+    Error(String),
+}
+
+/// A snapshot of where a task stands, as returned by [`TaskManager::status`].
+#[derive(Debug, Clone)]
+pub enum TaskStatus {
+    /// Submitted via [`TaskManager::submit_file_with_priority`] but not
+    /// started yet, because [`TaskManager::set_max_concurrent`]'s limit was
+    /// already reached. Waiting for a running transfer to finish, pause, or
+    /// be aborted.
+    Queued,
+    /// The transfer is in flight.
+    Running,
+    /// [`TaskManager::pause`] cancelled the transfer; call
+    /// [`TaskManager::resume`] to pick it back up.
+    Paused,
+    /// The transfer ran to completion (successfully or not); this is the
+    /// final status, same as what a later call to [`TaskManager::status`]
+    /// for the same id will keep returning.
+    Finished(TaskOutcome),
+}
+
+/// How far back [`TaskManager::stats`] looks when averaging throughput.
+/// Older samples are dropped as soon as a newer one pushes them outside
+/// this window, so a stalled or since-sped-up transfer is reflected
+/// quickly instead of being smoothed out by the whole transfer's history
+/// (which is what [`Progress::rate`] already gives you).
+const STATS_WINDOW: Duration = Duration::from_secs(5);
+
+/// Bytes-sent/second and estimated time to completion for a task, as
+/// returned by [`TaskManager::stats`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskStats {
+    /// Bytes sent per second, averaged over the last [`STATS_WINDOW`]
+    /// rather than since the transfer started; see [`Progress::rate`] for
+    /// the cumulative figure.
+    pub throughput: f64,
+    /// Estimated time to finish sending `bytes_total - bytes_done` at the
+    /// current `throughput`. `None` if there isn't enough history yet, or
+    /// `throughput` is zero.
+    pub eta: Option<Duration>,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// One transfer tracked by a [`TaskManager`].
+struct Task {
+    path: PathBuf,
+    cancellation: CancellationToken,
+    progress: tokio::sync::watch::Receiver<Progress>,
+    /// `None` once the task has paused or finished.
+    handle: Option<JoinHandle<Result<SlaveResponse>>>,
+    finished: Option<TaskOutcome>,
+    /// Recent `(when, bytes_sent)` samples, oldest first, used by
+    /// [`TaskManager::stats`] to compute a sliding-window throughput.
+    /// Populated lazily, on each call to `stats`, rather than on a
+    /// background timer.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+/// A submission that hasn't started yet because
+/// [`TaskManager::set_max_concurrent`]'s limit was reached when it came in.
+struct QueuedSubmission {
+    id: TaskId,
+    builder: MasterBuilder,
+    stream: BoxedStream,
+    path: PathBuf,
+    priority: i32,
+    file_size: u64,
+}
+
+/// Assigns ids to outgoing transfers and tracks them so they can be listed,
+/// paused, resumed, or aborted by id instead of by holding onto a
+/// [`crate::master::Master`] directly.
+///
+/// There's no "pause" at the wire protocol level, so [`TaskManager::pause`]
+/// cancels the transfer the same way [`TaskManager::abort`] does; the
+/// difference is bookkeeping. A paused task stays registered and can be
+/// restarted with [`TaskManager::resume`], which reconnects and continues
+/// via [`crate::master::Master::resume_a_file`]; an aborted one is dropped for good.
+///
+/// If opened [`TaskManager::with_journal`], every registered task's path
+/// and last-known `bytes_confirmed` are persisted to disk, so
+/// [`TaskManager::interrupted`] can list transfers that were still running
+/// when a previous process exited without finishing or aborting them, and
+/// [`TaskManager::resume_interrupted`] can pick them back up.
+///
+/// [`TaskManager::submit_file_with_priority`] additionally respects
+/// [`TaskManager::set_max_concurrent`]: once that many transfers are
+/// running, further submissions queue instead of starting right away, and
+/// are started in priority order (highest first, ties broken by the
+/// smaller file) as running ones finish, pause, or are aborted.
+/// [`TaskManager::submit_file`] ignores this limit entirely and always
+/// starts immediately, exactly as before.
+#[derive(Default)]
+pub struct TaskManager {
+    next_id: u64,
+    tasks: HashMap<TaskId, Task>,
+    journal: Option<Journal>,
+    max_concurrent: Option<usize>,
+    queue: Vec<QueuedSubmission>,
+}
+
+impl TaskManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like [`TaskManager::new`], but persists every task to `journal_path`
+    /// as it progresses. Task ids continue on from the highest one found in
+    /// the journal, so an id loaded from a previous run is never reused for
+    /// an unrelated new task.
+    pub fn with_journal(journal_path: impl Into<PathBuf>) -> Result<Self> {
+        let journal = Journal::open(journal_path)?;
+        let next_id = journal.max_task_id().map_or(0, |id| id + 1);
+        Ok(Self {
+            next_id,
+            tasks: HashMap::new(),
+            journal: Some(journal),
+            max_concurrent: None,
+            queue: Vec::new(),
+        })
+    }
+
+    /// Transfers left over in the journal from a previous run that this
+    /// manager hasn't (yet) resumed or forgotten.
+    pub fn interrupted(&self) -> Vec<JournalEntry> {
+        let Some(journal) = &self.journal else {
+            return Vec::new();
+        };
+        journal
+            .entries()
+            .filter(|entry| !self.tasks.contains_key(&TaskId(entry.task_id)))
+            .cloned()
+            .collect()
+    }
+
+    /// Restarts a transfer found by [`TaskManager::interrupted`], keeping
+    /// its original [`TaskId`] so it reads as the same task across the
+    /// restart. Continues via [`crate::master::Master::resume_a_file`], the
+    /// same as [`TaskManager::resume`] does for a task paused in this same
+    /// process.
+    pub fn resume_interrupted(
+        &mut self,
+        entry: JournalEntry,
+        builder: MasterBuilder,
+        stream: impl AsyncStream + 'static,
+    ) -> TaskId {
+        let id = TaskId(entry.task_id);
+        let cancellation = CancellationToken::new();
+        let (_progress_tx, progress_rx) = tokio::sync::watch::channel(Progress::default());
+        let mut master = builder.cancellation_token(cancellation.clone()).build(stream);
+        let path = entry.path.clone();
+        let handle = tokio::spawn(async move { master.resume_a_file(&path).await });
+
+        self.tasks.insert(
+            id,
+            Task {
+                path: entry.path,
+                cancellation,
+                progress: progress_rx,
+                handle: Some(handle),
+                finished: None,
+                samples: VecDeque::new(),
+            },
+        );
+        self.persist(id);
+        id
+    }
+
+    /// Removes a [`TaskManager::interrupted`] entry from the journal without
+    /// resuming it, so it stops being offered as interrupted on the next
+    /// call. Returns `false` if no journal was opened or `task_id` wasn't in
+    /// it.
+    pub fn discard_interrupted(&mut self, task_id: u64) -> bool {
+        let Some(journal) = &mut self.journal else {
+            return false;
+        };
+        if !journal.entries().any(|entry| entry.task_id == task_id) {
+            return false;
+        }
+        if let Err(err) = journal.forget(task_id) {
+            tracing::warn!(task_id, %err, "failed to remove task from the journal");
+        }
+        true
+    }
+
+    fn allocate_task_id(&mut self) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    /// Best-effort: writes `id`'s current path and confirmed-bytes total to
+    /// the journal, if one was opened. A failure here doesn't affect the
+    /// transfer itself, so it's logged rather than propagated.
+    fn persist(&mut self, id: TaskId) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        let Some(task) = self.tasks.get(&id) else {
+            return;
+        };
+        let entry = JournalEntry {
+            task_id: id.0,
+            path: task.path.clone(),
+            bytes_confirmed: task.progress.borrow().bytes_confirmed,
+        };
+        if let Err(err) = journal.record(entry) {
+            tracing::warn!(task_id = id.0, %err, "failed to persist task to the journal");
+        }
+    }
+
+    /// Best-effort removal of `id` from the journal; see [`Self::persist`].
+    fn forget_journal(&mut self, id: TaskId) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        if let Err(err) = journal.forget(id.0) {
+            tracing::warn!(task_id = id.0, %err, "failed to remove task from the journal");
+        }
+    }
+
+    /// Starts sending `path` over `stream` and registers it under a new
+    /// [`TaskId`]. `builder`'s cancellation token, if any, is replaced with
+    /// one the manager controls so [`TaskManager::pause`]/[`TaskManager::abort`]
+    /// work. Always starts immediately, ignoring
+    /// [`TaskManager::set_max_concurrent`]; use
+    /// [`TaskManager::submit_file_with_priority`] to have the submission
+    /// queue instead when the limit is reached.
+    pub fn submit_file(
+        &mut self,
+        builder: MasterBuilder,
+        stream: impl AsyncStream + 'static,
+        path: impl Into<PathBuf>,
+    ) -> TaskId {
+        let id = self.allocate_task_id();
+        self.start(id, path.into(), builder, Box::new(stream));
+        id
+    }
+
+    /// Like [`TaskManager::submit_file`], but participates in
+    /// [`TaskManager::set_max_concurrent`]'s scheduling: if the limit is
+    /// already reached, the submission queues instead of starting, and is
+    /// started later in priority order (higher `priority` first, ties
+    /// broken in favor of the smaller file) as running transfers finish,
+    /// pause, or are aborted. Use [`TaskManager::set_priority`] to change a
+    /// still-queued submission's place in that order.
+    pub fn submit_file_with_priority(
+        &mut self,
+        builder: MasterBuilder,
+        stream: impl AsyncStream + 'static,
+        path: impl Into<PathBuf>,
+        priority: i32,
+    ) -> Result<TaskId> {
+        let path = path.into();
+        let id = self.allocate_task_id();
+        if self.has_free_slot() {
+            self.start(id, path, builder, Box::new(stream));
+        } else {
+            let file_size = std::fs::metadata(&path)?.len();
+            self.queue.push(QueuedSubmission {
+                id,
+                builder,
+                stream: Box::new(stream),
+                path,
+                priority,
+                file_size,
+            });
+            self.sort_queue();
+        }
+        Ok(id)
+    }
+
+    /// Limits how many transfers submitted via
+    /// [`TaskManager::submit_file_with_priority`] may run at once; further
+    /// submissions through that method queue instead. `None` (the default)
+    /// means no limit. Raising the limit, or clearing it, immediately
+    /// starts as many queued submissions as now fit.
+    pub fn set_max_concurrent(&mut self, limit: Option<usize>) {
+        self.max_concurrent = limit;
+        self.drain_queue();
+    }
+
+    /// Changes a queued submission's priority and re-sorts the queue.
+    /// Returns `false` if `id` isn't currently queued (it may already be
+    /// running, or unknown).
+    pub fn set_priority(&mut self, id: TaskId, priority: i32) -> bool {
+        let Some(submission) = self.queue.iter_mut().find(|q| q.id == id) else {
+            return false;
+        };
+        submission.priority = priority;
+        self.sort_queue();
+        true
+    }
+
+    /// The ids of currently queued submissions, in the order they'll be
+    /// started as slots free up.
+    pub fn queued(&self) -> Vec<TaskId> {
+        self.queue.iter().map(|q| q.id).collect()
+    }
+
+    fn has_free_slot(&self) -> bool {
+        match self.max_concurrent {
+            None => true,
+            Some(limit) => self.running_count() < limit,
+        }
+    }
+
+    /// How many tasks are actually transferring right now (as opposed to
+    /// queued, paused, or finished), i.e. how many of
+    /// [`TaskManager::set_max_concurrent`]'s slots are in use.
+    fn running_count(&self) -> usize {
+        self.tasks
+            .values()
+            .filter(|task| task.handle.is_some() && task.finished.is_none())
+            .count()
+    }
+
+    fn sort_queue(&mut self) {
+        self.queue
+            .sort_by(|a, b| b.priority.cmp(&a.priority).then(a.file_size.cmp(&b.file_size)));
+    }
+
+    /// Starts as many queued submissions as now have a free slot, in queue
+    /// order (already priority-sorted by [`Self::sort_queue`]).
+    fn drain_queue(&mut self) {
+        while self.has_free_slot() && !self.queue.is_empty() {
+            let submission = self.queue.remove(0);
+            self.start(submission.id, submission.path, submission.builder, submission.stream);
+        }
+    }
+
+    fn start(&mut self, id: TaskId, path: PathBuf, builder: MasterBuilder, stream: BoxedStream) {
+        let cancellation = CancellationToken::new();
+        let (progress_tx, progress_rx) = tokio::sync::watch::channel(Progress::default());
+        let mut master = builder.cancellation_token(cancellation.clone()).build(stream);
+
+        let send_path = path.clone();
+        let handle = tokio::spawn(async move {
+            master
+                .send_a_file_with_progress(&send_path, progress_tx)
+                .await
+        });
+
+        self.tasks.insert(
+            id,
+            Task {
+                path,
+                cancellation,
+                progress: progress_rx,
+                handle: Some(handle),
+                finished: None,
+                samples: VecDeque::new(),
+            },
+        );
+        self.persist(id);
+    }
+
+    /// Every task id currently registered, regardless of status, including
+    /// ones still queued.
+    pub fn list(&self) -> Vec<TaskId> {
+        self.tasks
+            .keys()
+            .copied()
+            .chain(self.queue.iter().map(|q| q.id))
+            .collect()
+    }
+
+    /// The last [`Progress`] reported for `id`, if it's known to this
+    /// manager.
+    pub fn progress(&self, id: TaskId) -> Option<Progress> {
+        self.tasks.get(&id).map(|task| *task.progress.borrow())
+    }
+
+    /// Throughput (averaged over [`STATS_WINDOW`]) and an ETA for `id`,
+    /// derived from [`TaskManager::progress`]. Each call records a new
+    /// sample and drops ones that have aged out of the window, so calling
+    /// this regularly (e.g. to drive a progress bar) is what keeps the
+    /// window moving; calling it once tells you very little, since a
+    /// single sample has no rate to compute yet.
+    pub fn stats(&mut self, id: TaskId) -> Option<TaskStats> {
+        let task = self.tasks.get_mut(&id)?;
+        let progress = *task.progress.borrow();
+
+        let now = Instant::now();
+        task.samples.push_back((now, progress.bytes_sent));
+        while task
+            .samples
+            .front()
+            .is_some_and(|(when, _)| now.duration_since(*when) > STATS_WINDOW)
+        {
+            task.samples.pop_front();
+        }
+
+        let throughput = match (task.samples.front(), task.samples.back()) {
+            (Some((start, start_bytes)), Some((end, end_bytes))) if end > start => {
+                let elapsed = end.duration_since(*start).as_secs_f64();
+                (end_bytes - start_bytes) as f64 / elapsed
+            }
+            _ => 0.0,
+        };
+
+        let remaining = progress.total.saturating_sub(progress.bytes_sent);
+        let eta = if throughput > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / throughput))
+        } else {
+            None
+        };
+
+        Some(TaskStats {
+            throughput,
+            eta,
+            bytes_done: progress.bytes_sent,
+            bytes_total: progress.total,
+        })
+    }
+
+    /// Where `id` currently stands. Polls the transfer's task without
+    /// blocking, so a `Running` transfer that has in fact already finished
+    /// is reflected as `Finished` as soon as this is next called.
+    pub async fn status(&mut self, id: TaskId) -> Option<TaskStatus> {
+        if self.queue.iter().any(|q| q.id == id) {
+            return Some(TaskStatus::Queued);
+        }
+        let task = self.tasks.get_mut(&id)?;
+
+        if let Some(outcome) = &task.finished {
+            return Some(TaskStatus::Finished(outcome.clone()));
+        }
+
+        let status = match &task.handle {
+            Some(handle) if handle.is_finished() => {
+                let handle = task.handle.take().expect("checked Some above");
+                let result = handle
+                    .await
+                    .unwrap_or(Err(crate::error::Error::TransferTaskPanicked));
+                let outcome = match result {
+                    Ok(response) => TaskOutcome::Response(response),
+                    Err(err) => TaskOutcome::Error(err.to_string()),
+                };
+                task.finished = Some(outcome.clone());
+                TaskStatus::Finished(outcome)
+            }
+            Some(_) => TaskStatus::Running,
+            None => TaskStatus::Paused,
+        };
+
+        // A finished task no longer needs resuming after a crash; anything
+        // else gets its latest confirmed-bytes total written through.
+        if matches!(status, TaskStatus::Finished(_)) {
+            self.forget_journal(id);
+            self.drain_queue();
+        } else {
+            self.persist(id);
+        }
+        Some(status)
+    }
+
+    /// Cancels `id`'s in-flight transfer without forgetting the task, then
+    /// waits for it to actually stop so [`TaskManager::status`] reports
+    /// `Paused` right away; [`TaskManager::resume`] can restart it later.
+    /// No-op if `id` is unknown, already paused, or already finished.
+    pub async fn pause(&mut self, id: TaskId) {
+        let Some(task) = self.tasks.get_mut(&id) else {
+            return;
+        };
+        task.cancellation.cancel();
+        let was_running = task.handle.is_some();
+        if let Some(handle) = task.handle.take() {
+            // Cancelling makes the transfer stop (with `Error::Cancelled`)
+            // rather than run to completion; that's expected here, so it's
+            // discarded instead of being recorded as `task.finished`.
+            let _ = handle.await;
+        }
+        self.persist(id);
+        // Pausing frees up the slot it was occupying, same as finishing
+        // does, so a queued submission can take its place.
+        if was_running {
+            self.drain_queue();
+        }
+    }
+
+    /// Cancels `id`'s transfer (if any) and forgets it, including from the
+    /// journal. Returns `false` if `id` was unknown, whether still queued
+    /// or already registered as a task.
+    pub fn abort(&mut self, id: TaskId) -> bool {
+        if let Some(pos) = self.queue.iter().position(|q| q.id == id) {
+            self.queue.remove(pos);
+            return true;
+        }
+        self.forget_journal(id);
+        match self.tasks.remove(&id) {
+            Some(task) => {
+                let was_running = task.handle.is_some() && task.finished.is_none();
+                task.cancellation.cancel();
+                if was_running {
+                    self.drain_queue();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restarts `id`'s transfer over a new `stream`, continuing from where
+    /// it left off via [`crate::master::Master::resume_a_file`]. Returns `false` if `id`
+    /// is unknown.
+    pub fn resume(
+        &mut self,
+        id: TaskId,
+        builder: MasterBuilder,
+        stream: impl AsyncStream + 'static,
+    ) -> bool {
+        let Some(task) = self.tasks.get_mut(&id) else {
+            return false;
+        };
+
+        let cancellation = CancellationToken::new();
+        // `resume_a_file` doesn't report per-fragment progress the way
+        // `send_a_file_with_progress` does, so this channel only ever holds
+        // its default value; it exists so `progress()` keeps returning
+        // *something* for a resumed task instead of `None`.
+        let (_progress_tx, progress_rx) = tokio::sync::watch::channel(Progress::default());
+        let mut master = builder.cancellation_token(cancellation.clone()).build(stream);
+        let path = task.path.clone();
+        let handle = tokio::spawn(async move { master.resume_a_file(&path).await });
+
+        task.cancellation = cancellation;
+        task.progress = progress_rx;
+        task.handle = Some(handle);
+        task.finished = None;
+        task.samples.clear();
+        self.persist(id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::master::MasterBuilder;
+    use crate::slave::Slave;
+
+    #[tokio::test]
+    async fn lists_tracks_progress_and_reports_completion() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello world").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut manager = TaskManager::new();
+        let id = manager.submit_file(MasterBuilder::new(), stream, &src);
+
+        assert_eq!(manager.list(), vec![id]);
+
+        let status = loop {
+            match manager.status(id).await.unwrap() {
+                TaskStatus::Running => tokio::task::yield_now().await,
+                finished @ TaskStatus::Finished(_) => break finished,
+                other => panic!("unexpected status: {other:?}"),
+            }
+        };
+        assert!(matches!(
+            status,
+            TaskStatus::Finished(TaskOutcome::Response(SlaveResponse::Ok))
+        ));
+        // Idempotent: asking again after completion returns the same thing.
+        assert!(matches!(
+            manager.status(id).await.unwrap(),
+            TaskStatus::Finished(TaskOutcome::Response(SlaveResponse::Ok))
+        ));
+
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats_reports_throughput_and_a_shrinking_eta_then_settles_once_finished() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-stats-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "x".repeat(200_000)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut manager = TaskManager::new();
+        let id = manager.submit_file(MasterBuilder::new(), stream, &src);
+
+        // A single sample has no elapsed time to divide by yet.
+        let first = manager.stats(id).unwrap();
+        assert_eq!(first.throughput, 0.0);
+        assert_eq!(first.eta, None);
+
+        let status = loop {
+            match manager.status(id).await.unwrap() {
+                TaskStatus::Running => {
+                    manager.stats(id).unwrap();
+                    tokio::task::yield_now().await;
+                }
+                finished @ TaskStatus::Finished(_) => break finished,
+                other => panic!("unexpected status: {other:?}"),
+            }
+        };
+        assert!(matches!(
+            status,
+            TaskStatus::Finished(TaskOutcome::Response(SlaveResponse::Ok))
+        ));
+
+        let done = manager.stats(id).unwrap();
+        assert_eq!(done.bytes_done, 200_000);
+        assert_eq!(done.bytes_total, 200_000);
+
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pause_cancels_the_transfer_and_resume_continues_it() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-pause-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "x".repeat(50_000)).await.unwrap();
+
+        let slave_dir = dir.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let first_connection = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            let _ = slave.recv_request_thread().await;
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut manager = TaskManager::new();
+        let id = manager.submit_file(MasterBuilder::new(), stream, &src);
+
+        manager.pause(id).await;
+        assert!(matches!(
+            manager.status(id).await.unwrap(),
+            TaskStatus::Paused
+        ));
+        first_connection.await.unwrap();
+
+        let slave_dir = dir.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let second_connection = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(manager.resume(id, MasterBuilder::new(), stream));
+
+        let status = loop {
+            match manager.status(id).await.unwrap() {
+                TaskStatus::Running => tokio::task::yield_now().await,
+                finished @ TaskStatus::Finished(_) => break finished,
+                other => panic!("unexpected status: {other:?}"),
+            }
+        };
+        assert!(matches!(
+            status,
+            TaskStatus::Finished(TaskOutcome::Response(SlaveResponse::Ok))
+        ));
+
+        second_connection.await.unwrap();
+        assert_eq!(
+            tokio::fs::read(dir.join("source.txt")).await.unwrap(),
+            "x".repeat(50_000).into_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn abort_forgets_the_task() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-abort-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "hello world").await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(dir);
+            let _ = slave.recv_request_thread().await;
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut manager = TaskManager::new();
+        let id = manager.submit_file(MasterBuilder::new(), stream, &src);
+
+        assert!(manager.abort(id));
+        assert!(!manager.abort(id));
+        assert!(manager.status(id).await.is_none());
+        assert!(manager.list().is_empty());
+
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn survives_a_restart_by_resuming_from_the_journal() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-journal-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("source.txt");
+        tokio::fs::write(&src, "x".repeat(50_000)).await.unwrap();
+        let journal_path = dir.join("journal.bin");
+        let _ = std::fs::remove_file(&journal_path);
+
+        // First "process": submit a transfer, then crash (drop the manager
+        // and its connection) before it finishes, without aborting or
+        // pausing it cleanly.
+        let slave_dir = dir.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let first_connection = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            let _ = slave.recv_request_thread().await;
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let original_id = {
+            let mut manager = TaskManager::with_journal(&journal_path).unwrap();
+            manager.submit_file(MasterBuilder::new(), stream, &src)
+        };
+        first_connection.await.unwrap();
+
+        // Second "process": reopen the journal and find the interrupted
+        // transfer still there, under the same task id.
+        let mut manager = TaskManager::with_journal(&journal_path).unwrap();
+        let interrupted = manager.interrupted();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].task_id, original_id.0);
+        assert_eq!(interrupted[0].path, src);
+
+        let slave_dir = dir.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let second_connection = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(slave_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let resumed_id = manager.resume_interrupted(interrupted[0].clone(), MasterBuilder::new(), stream);
+        assert_eq!(resumed_id, original_id);
+        assert!(manager.interrupted().is_empty());
+
+        let status = loop {
+            match manager.status(resumed_id).await.unwrap() {
+                TaskStatus::Finished(outcome) => break outcome,
+                _ => tokio::task::yield_now().await,
+            }
+        };
+        assert!(matches!(status, TaskOutcome::Response(SlaveResponse::Ok)));
+
+        second_connection.await.unwrap();
+
+        // Finishing should have dropped it from the journal too.
+        let reloaded = Journal::open(&journal_path).unwrap();
+        assert_eq!(reloaded.entries().count(), 0);
+    }
+
+    #[test]
+    fn discard_interrupted_removes_it_without_resuming() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-discard-test-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal_path = dir.join("journal.bin");
+        let _ = std::fs::remove_file(&journal_path);
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+        journal.record(JournalEntry { task_id: 7, path: dir.join("orphan.txt"), bytes_confirmed: 10 }).unwrap();
+        drop(journal);
+
+        let mut manager = TaskManager::with_journal(&journal_path).unwrap();
+        assert_eq!(manager.interrupted().len(), 1);
+
+        assert!(manager.discard_interrupted(7));
+        assert!(manager.interrupted().is_empty());
+        assert!(!manager.discard_interrupted(7));
+
+        let reloaded = Journal::open(&journal_path).unwrap();
+        assert_eq!(reloaded.entries().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn queues_submissions_past_the_limit_and_starts_the_higher_priority_one_first() {
+        let dir = std::env::temp_dir().join(format!(
+            "portal-task-manager-priority-test-{:?}",
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let low = dir.join("low.txt");
+        let high = dir.join("high.txt");
+        tokio::fs::write(&low, "low priority").await.unwrap();
+        tokio::fs::write(&high, "high priority").await.unwrap();
+
+        let mut manager = TaskManager::new();
+        manager.set_max_concurrent(Some(1));
+
+        // Occupy the only slot with a task that never gets a slave to talk
+        // to, so it stays `Running` until aborted and the queue never
+        // drains on its own.
+        let occupying_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let occupying_addr = occupying_listener.local_addr().unwrap();
+        let occupying_stream = tokio::net::TcpStream::connect(occupying_addr).await.unwrap();
+        let occupying = manager.submit_file(MasterBuilder::new(), occupying_stream, &low);
+
+        let low_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let low_addr = low_listener.local_addr().unwrap();
+        let low_stream = tokio::net::TcpStream::connect(low_addr).await.unwrap();
+        let low_id = manager
+            .submit_file_with_priority(MasterBuilder::new(), low_stream, &low, 0)
+            .unwrap();
+
+        let high_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let high_addr = high_listener.local_addr().unwrap();
+        let high_stream = tokio::net::TcpStream::connect(high_addr).await.unwrap();
+        let high_id = manager
+            .submit_file_with_priority(MasterBuilder::new(), high_stream, &high, 10)
+            .unwrap();
+
+        assert!(matches!(
+            manager.status(low_id).await.unwrap(),
+            TaskStatus::Queued
+        ));
+        assert!(matches!(
+            manager.status(high_id).await.unwrap(),
+            TaskStatus::Queued
+        ));
+        // Higher priority sorts ahead of the lower one despite arriving later.
+        assert_eq!(manager.queued(), vec![high_id, low_id]);
+
+        let out_dir = dir.clone();
+        let accept_high = tokio::spawn(async move {
+            let (stream, _) = high_listener.accept().await.unwrap();
+            let mut slave = Slave::from_stream(stream);
+            slave.set_output_dir(out_dir);
+            slave.recv_request_thread().await.unwrap();
+        });
+
+        // Freeing the occupied slot should start the queue's front, i.e.
+        // the high-priority submission, not the low-priority one.
+        assert!(manager.abort(occupying));
+        assert_eq!(manager.queued(), vec![low_id]);
+
+        let status = loop {
+            match manager.status(high_id).await.unwrap() {
+                TaskStatus::Running => tokio::task::yield_now().await,
+                finished @ TaskStatus::Finished(_) => break finished,
+                other => panic!("unexpected status: {other:?}"),
+            }
+        };
+        assert!(matches!(
+            status,
+            TaskStatus::Finished(TaskOutcome::Response(SlaveResponse::Ok))
+        ));
+        accept_high.await.unwrap();
+
+        // The high-priority transfer finishing freed its slot, so the
+        // status check above should have started the low-priority one too.
+        assert!(manager.queued().is_empty());
+        assert!(matches!(
+            manager.status(low_id).await.unwrap(),
+            TaskStatus::Running
+        ));
+        assert!(manager.abort(low_id));
+    }
+}
@@ -0,0 +1,208 @@
+//! Optional TLS transport via rustls (`tls` feature), for users who don't
+//! want LAN transfers going out in the clear. A throwaway self-signed
+//! certificate is generated by default; callers who need real verification
+//! can build their own `rustls` configs.
+
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::error::{Error, Result};
+use crate::master::Master;
+use crate::slave::Slave;
+
+fn ensure_crypto_provider() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+}
+
+/// Generates a throwaway self-signed certificate and key for `subject_alt_name`
+/// (typically a hostname or IP), suitable for opportunistic LAN encryption.
+pub fn generate_self_signed(
+    subject_alt_name: &str,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let generated = rcgen::generate_simple_self_signed(vec![subject_alt_name.to_string()])
+        .map_err(|e| Error::Tls(e.to_string()))?;
+    let key = PrivateKeyDer::Pkcs8(generated.key_pair.serialize_der().into());
+    Ok((generated.cert.into(), key))
+}
+
+/// Builds a server config presenting `cert`/`key` to connecting masters.
+pub fn server_config(cert: CertificateDer<'static>, key: PrivateKeyDer<'static>) -> Result<Arc<ServerConfig>> {
+    ensure_crypto_provider();
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .map_err(|e| Error::Tls(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+/// A client config that accepts any server certificate, matching the
+/// self-signed certs [`generate_self_signed`] produces. Only appropriate on
+/// a trusted LAN; construct a real `rustls::ClientConfig` if you need
+/// verification against a CA or a pinned certificate.
+pub fn insecure_client_config() -> Arc<ClientConfig> {
+    ensure_crypto_provider();
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// A client config that only accepts a server presenting exactly `pinned_cert`.
+pub fn pinned_client_config(pinned_cert: CertificateDer<'static>) -> Arc<ClientConfig> {
+    ensure_crypto_provider();
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCert(pinned_cert)))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+#[derive(Debug)]
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[derive(Debug)]
+struct PinnedCert(CertificateDer<'static>);
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.0.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "server certificate does not match the pinned certificate".into(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl Master {
+    /// Dials `stream` wrapped in a TLS client session, then returns a
+    /// ready-to-use `Master`. Call [`Master::handshake`] afterwards as usual.
+    pub async fn connect_tls(
+        stream: TcpStream,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self> {
+        let connector = TlsConnector::from(config);
+        let tls_stream = connector
+            .connect(server_name, stream)
+            .await
+            .map_err(Error::Io)?;
+        Ok(Self::from_stream(tls_stream))
+    }
+}
+
+impl Slave {
+    /// Accepts `stream` as a TLS server session, then returns a ready-to-use
+    /// `Slave`.
+    pub async fn accept_tls(stream: TcpStream, config: Arc<ServerConfig>) -> Result<Self> {
+        let acceptor = TlsAcceptor::from(config);
+        let tls_stream = acceptor.accept(stream).await.map_err(Error::Io)?;
+        Ok(Self::from_stream(tls_stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn completes_a_tls_handshake_with_a_self_signed_certificate() {
+        let (cert, key) = generate_self_signed("127.0.0.1").unwrap();
+        let server_config = server_config(cert, key).unwrap();
+        let client_config = insecure_client_config();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            Slave::accept_tls(stream, server_config).await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("127.0.0.1").unwrap();
+        let master = Master::connect_tls(stream, server_name, client_config)
+            .await
+            .unwrap();
+        drop(master);
+
+        accept.await.unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+//! Transport-agnostic stream used by [`crate::master::Master`] and
+//! [`crate::slave::Slave`], so a plain TCP connection and an encrypted one
+//! (see [`crate::tls`]) can be handled identically once established.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
@@ -0,0 +1,192 @@
+//! Trust-on-first-use tracking of peer identities, in the spirit of SSH's
+//! `known_hosts`: the first time a [`DeviceId`] is paired with a Noise
+//! static public key (see [`crate::noise::Master::connect_noise_with_key`]
+//! and [`crate::noise::Slave::accept_noise_with_key`]), that pairing is
+//! remembered. If the same device id ever shows up with a different key —
+//! a sign of impersonation, or just a key rotated without telling us —
+//! [`TrustPolicy`] decides whether that's merely logged or refused outright.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::identity::DeviceId;
+
+/// What to do when a known device reappears with a different public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustPolicy {
+    /// Log a warning but let the connection proceed.
+    Warn,
+    /// Reject the connection with [`Error::UntrustedPeer`].
+    Refuse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrustedPeer {
+    device_id: DeviceId,
+    public_key: Vec<u8>,
+}
+
+/// An on-disk store of `DeviceId` -> Noise static public key pairings,
+/// rewriting the backing file in full on every change, the same way
+/// [`crate::journal::Journal`] does — known devices are few, so a
+/// rewrite-on-write design doesn't need compaction.
+pub struct TrustStore {
+    path: PathBuf,
+    peers: HashMap<DeviceId, Vec<u8>>,
+}
+
+impl TrustStore {
+    /// Loads `path` if it exists (an empty or missing file means no
+    /// trusted devices yet), or starts a fresh store that will be created
+    /// at `path` on the first write.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let peers = match std::fs::read(&path) {
+            Ok(bytes) if bytes.is_empty() => HashMap::new(),
+            Ok(bytes) => {
+                let peers: Vec<TrustedPeer> = bincode::deserialize(&bytes)?;
+                peers.into_iter().map(|peer| (peer.device_id, peer.public_key)).collect()
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { path, peers })
+    }
+
+    /// Checks `public_key` against whatever is on file for `device_id`. A
+    /// device seen for the first time is trusted on the spot and
+    /// remembered; a device whose key has changed since is handled per
+    /// `policy`.
+    pub fn verify(&mut self, device_id: DeviceId, public_key: &[u8], policy: TrustPolicy) -> Result<()> {
+        match self.peers.get(&device_id) {
+            None => {
+                self.peers.insert(device_id, public_key.to_vec());
+                self.flush()
+            }
+            Some(known) if known.as_slice() == public_key => Ok(()),
+            Some(_) => match policy {
+                TrustPolicy::Warn => {
+                    tracing::warn!(%device_id, "known device reappeared with a different public key");
+                    Ok(())
+                }
+                TrustPolicy::Refuse => Err(Error::UntrustedPeer(device_id.to_string())),
+            },
+        }
+    }
+
+    /// Removes `device_id` from the store, e.g. so a key rotation can be
+    /// accepted as trust-on-first-use again.
+    pub fn forget(&mut self, device_id: DeviceId) -> Result<()> {
+        self.peers.remove(&device_id);
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<()> {
+        let peers: Vec<TrustedPeer> = self
+            .peers
+            .iter()
+            .map(|(&device_id, public_key)| TrustedPeer {
+                device_id,
+                public_key: public_key.clone(),
+            })
+            .collect();
+        let bytes = bincode::serialize(&peers)?;
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, &bytes)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("portal-trust-{name}-test-{:?}.bin", std::thread::current().id()))
+    }
+
+    #[test]
+    fn trusts_and_remembers_a_device_seen_for_the_first_time() {
+        let path = test_path("tofu");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = TrustStore::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+
+        let reopened = TrustStore::open(&path).unwrap();
+        assert_eq!(reopened.peers.get(&device_id).unwrap(), b"key-a");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn accepts_the_same_key_on_a_later_connection() {
+        let path = test_path("same-key");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = TrustStore::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_changed_key_under_the_refuse_policy() {
+        let path = test_path("refuse");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = TrustStore::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+
+        let result = store.verify(device_id, b"key-b", TrustPolicy::Refuse);
+        assert!(matches!(result, Err(Error::UntrustedPeer(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn allows_a_changed_key_under_the_warn_policy() {
+        let path = test_path("warn");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = TrustStore::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+
+        store.verify(device_id, b"key-b", TrustPolicy::Warn).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forgetting_a_device_resets_it_to_trust_on_first_use() {
+        let path = test_path("forget");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = TrustStore::open(&path).unwrap();
+        let device_id = DeviceId::generate();
+        store.verify(device_id, b"key-a", TrustPolicy::Refuse).unwrap();
+        store.forget(device_id).unwrap();
+
+        store.verify(device_id, b"key-b", TrustPolicy::Refuse).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn treats_a_missing_file_as_an_empty_store() {
+        let path = test_path("missing");
+        let _ = std::fs::remove_file(&path);
+
+        let store = TrustStore::open(&path).unwrap();
+        assert!(store.peers.is_empty());
+    }
+}